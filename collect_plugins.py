@@ -3,13 +3,17 @@ import hashlib
 import shutil
 import os
 
-target_dir = pathlib.Path("converters/target/wasm32-unknown-unknown/release/")
+# core modules (V1 ABI) and components (converter world, built with `--features component`)
+target_dirs = [
+    pathlib.Path("converters/target/wasm32-unknown-unknown/release/"),
+    pathlib.Path("converters/target/wasm32-wasip2/release/"),
+]
 plugin_dir = pathlib.Path("fg-index/converters/")
 
 if not plugin_dir.exists():
     os.makedirs(plugin_dir)
 
-for fileobj in target_dir.glob("*.wasm"):
+for fileobj in (f for target_dir in target_dirs for f in target_dir.glob("*.wasm")):
     size = int(os.path.getsize(fileobj) / 1024)
     hash = hashlib.sha256(fileobj.read_bytes()).hexdigest()
     target_path = pathlib.Path(plugin_dir, f"{hash}.wasm")
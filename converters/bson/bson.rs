@@ -1,14 +1,40 @@
 
 use format_galaxy_core::gen_plugin;
+use format_galaxy_core::options::Options;
+use format_galaxy_core::styled::{Style, StyledText};
 use std::convert::TryFrom;
 use anyhow::anyhow;
 
-struct Impl {}
+pub struct Impl {}
 
-fn present_inner(bytes: &[u8]) -> Result<String, anyhow::Error> {
+// whether the relaxed extended JSON mode is selected
+fn relaxed(options: &Options) -> Result<bool, anyhow::Error> {
+    match options.str("extjson", "canonical") {
+        "canonical" => Ok(false),
+        "relaxed" => Ok(true),
+        mode => Err(anyhow!("Unknown extended JSON mode '{}'", mode)),
+    }
+}
+
+// relaxed extended JSON, except for 64 bit integers that fit into 32 bits, plain numbers like
+// these are stored as 32 bit integers
+fn into_relaxed_extjson(v: bson::Bson) -> serde_json::Value {
+    match v {
+        bson::Bson::Int64(n) if i32::try_from(n).is_ok() => bson::Bson::Int64(n).into_canonical_extjson(),
+        bson::Bson::Array(a) => serde_json::Value::Array(a.into_iter().map(into_relaxed_extjson).collect()),
+        bson::Bson::Document(d) => serde_json::Value::Object(d.into_iter()
+            .map(|(k, v)| (k, into_relaxed_extjson(v))).collect()),
+        v => v.into_relaxed_extjson(),
+    }
+}
+
+fn present_inner(bytes: &[u8], options: &Options) -> Result<String, anyhow::Error> {
+    let relaxed = relaxed(options)?;
     let mut cur = std::io::Cursor::new(bytes);
     let doc = bson::Document::from_reader(&mut cur)?;
-    let map: serde_json::Map<String, serde_json::Value> = doc.into_iter().map(|(k, v)| (k, v.into_canonical_extjson())).collect();
+    let map: serde_json::Map<String, serde_json::Value> = doc.into_iter()
+        .map(|(k, v)| (k, if relaxed { into_relaxed_extjson(v) } else { v.into_canonical_extjson() }))
+        .collect();
     let val = serde_json::Value::from(map);
     Ok(serde_json::to_string_pretty(&val)?)
 }
@@ -25,14 +51,167 @@ fn store_inner(s: &str) -> Result<Vec<u8>, anyhow::Error> {
     Err(anyhow!("Expected top-level object to be an object."))
 }
 
+// highlights JSON as printed by serde_json, keys are the strings followed by a colon
+fn style_json(text: &str) -> StyledText {
+    let mut styled = StyledText::default();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let token_len = |rest: &str, f: fn(char) -> bool| rest.find(|c| !f(c)).unwrap_or(rest.len());
+        let (len, style) = match c {
+            '"' => {
+                let mut escaped = false;
+                let len = rest[1..].find(|c| {
+                    let end = c == '"' && !escaped;
+                    escaped = c == '\\' && !escaped;
+                    end
+                }).map_or(rest.len(), |i| i + 2);
+                let is_key = rest[len..].trim_start().starts_with(':');
+                (len, if is_key { Style::Key } else { Style::String })
+            }
+            '{' | '}' | '[' | ']' | ',' | ':' => (1, Style::Punctuation),
+            '-' | '0'..='9' => (token_len(rest, |c| c.is_ascii_alphanumeric() || "+-.".contains(c)), Style::Number),
+            'a'..='z' => (token_len(rest, |c| c.is_ascii_alphabetic()), Style::Literal),
+            _ => (c.len_utf8(), Style::Plain),
+        };
+        styled.push(&rest[..len], style);
+        rest = &rest[len..];
+    }
+    styled
+}
+
 impl format_galaxy_core::GalaxyFormat for Impl {
     fn present(bytes: &[u8]) -> Result<String, String> {
-        present_inner(bytes).map_err(|e| e.to_string())
+        Self::present_with_options(bytes, &Options::new())
+    }
+
+    fn present_styled(bytes: &[u8]) -> Result<StyledText, String> {
+        Self::present_styled_with_options(bytes, &Options::new())
+    }
+
+    fn present_with_options(bytes: &[u8], options: &Options) -> Result<String, String> {
+        present_inner(bytes, options).map_err(|e| e.to_string())
+    }
+
+    fn present_styled_with_options(bytes: &[u8], options: &Options) -> Result<StyledText, String> {
+        Self::present_with_options(bytes, options).map(|text| style_json(&text))
     }
 
     fn store(s: &str) -> Result<Vec<u8>, String> {
         store_inner(s).map_err(|e| e.to_string())
     }
+
+    // both modes are understood when storing, the option is only checked
+    fn store_with_options(s: &str, options: &Options) -> Result<Vec<u8>, String> {
+        relaxed(options).map_err(|e| e.to_string())?;
+        Self::store(s)
+    }
+
+    fn sniff(bytes: &[u8]) -> u8 {
+        // a document starts with its total length (i32, little endian) and ends with a null byte
+        if bytes.len() < 5 {
+            return 0;
+        }
+        let len = i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        let is_element_type = matches!(bytes[4], 0x00..=0x13 | 0x7f | 0xff);
+        match usize::try_from(len) {
+            Ok(len) if len == bytes.len() && bytes[len - 1] == 0 && is_element_type => 80,
+            // `bytes` may only be the beginning of the document
+            Ok(len) if len > bytes.len() && is_element_type => 20,
+            _ => 0,
+        }
+    }
+}
+
+gen_plugin!{Impl, styled, sniff, options}
+
+#[test]
+fn test_extjson() {
+    use format_galaxy_core::GalaxyFormat;
+    let mut doc = bson::Document::new();
+    doc.insert("n", 5i32);
+    let mut bytes = vec!();
+    doc.to_writer(&mut bytes).unwrap();
+
+    let canonical = Impl::present(&bytes).unwrap();
+    assert!(canonical.contains("\"$numberInt\": \"5\""));
+    let mut options = Options::new();
+    options.set("extjson", "relaxed").unwrap();
+    let relaxed = Impl::present_with_options(&bytes, &options).unwrap();
+    assert_eq!(relaxed, "{\n  \"n\": 5\n}");
+    // both modes are understood when storing, relaxed numbers become the smallest fitting type
+    assert_eq!(Impl::store(&canonical), Ok(bytes.clone()));
+    assert_eq!(Impl::store(&relaxed), Ok(bytes.clone()));
+
+    assert_eq!(Impl::store_with_options(&relaxed, &options), Ok(bytes.clone()));
+
+    // except for 64 bit integers that would be stored as 32 bit ones, also when nested
+    let mut doc = bson::Document::new();
+    doc.insert("small", 5i64);
+    doc.insert("large", i64::MAX);
+    doc.insert("nested", vec!(bson::Bson::Int64(-5)));
+    let mut bytes_64 = vec!();
+    doc.to_writer(&mut bytes_64).unwrap();
+    let relaxed_64 = Impl::present_with_options(&bytes_64, &options).unwrap();
+    assert_eq!(relaxed_64, "{\n  \"small\": {\n    \"$numberLong\": \"5\"\n  },\n  \"large\": 9223372036854775807,\n  \"nested\": [\n    {\n      \"$numberLong\": \"-5\"\n    }\n  ]\n}");
+    assert_eq!(Impl::store_with_options(&relaxed_64, &options), Ok(bytes_64));
+
+    // the highlighting doesn't depend on the mode
+    let styled = Impl::present_styled_with_options(&bytes, &options).unwrap();
+    assert_eq!(styled.text, relaxed);
+    assert_eq!(styled.segments(), vec!(
+        ("{", Style::Punctuation), ("\n  ", Style::Plain), ("\"n\"", Style::Key),
+        (":", Style::Punctuation), (" ", Style::Plain), ("5", Style::Number),
+        ("\n", Style::Plain), ("}", Style::Punctuation),
+    ));
+    assert_eq!(Impl::present_styled(&bytes).unwrap().text, canonical);
+
+    options.set("extjson", "legacy").unwrap();
+    assert!(Impl::present_with_options(&[], &options).is_err());
+    assert!(Impl::store_with_options(&relaxed, &options).is_err());
+}
+
+#[test]
+fn test_style_json() {
+    let styled = style_json("{\"a\\\"\": [\"b\", -1.5e3, true, null]}");
+    let styles: Vec<_> = styled.segments().into_iter().filter(|(_s, style)| *style != Style::Plain).collect();
+    assert_eq!(styles, vec!(
+        ("{", Style::Punctuation), ("\"a\\\"\"", Style::Key), (":", Style::Punctuation),
+        ("[", Style::Punctuation), ("\"b\"", Style::String), (",", Style::Punctuation),
+        ("-1.5e3", Style::Number), (",", Style::Punctuation), ("true", Style::Literal),
+        (",", Style::Punctuation), ("null", Style::Literal), ("]", Style::Punctuation),
+        ("}", Style::Punctuation),
+    ));
 }
 
-gen_plugin!{Impl}
+#[test]
+fn test_round_trip() {
+    use format_galaxy_core::testing::{check_round_trip, check_round_trip_with_options, check_arbitrary_input, proptest::prelude::*};
+    use bson::{Bson, Document};
+
+    // keys are C strings, doubles round-trip through their canonical string form
+    let key = "[a-zA-Z0-9_ äöü€❤]{0,8}";
+    let leaf = prop_oneof![
+        Just(Bson::Null),
+        any::<bool>().prop_map(Bson::Boolean),
+        any::<i32>().prop_map(Bson::Int32),
+        any::<i64>().prop_map(Bson::Int64),
+        any::<f64>().prop_filter("finite", |f| f.is_finite()).prop_map(Bson::Double),
+        ".*".prop_map(Bson::String),
+    ];
+    let value = leaf.prop_recursive(3, 32, 4, move |inner| prop_oneof![
+        prop::collection::vec(inner.clone(), 0..4).prop_map(Bson::Array),
+        prop::collection::vec((key, inner), 0..4).prop_map(|v| Bson::Document(v.into_iter().collect())),
+    ]);
+    let doc = prop::collection::vec((key, value), 0..8).prop_map(|v| {
+        let doc: Document = v.into_iter().collect();
+        let mut bytes = vec!();
+        doc.to_writer(&mut bytes).unwrap();
+        bytes
+    });
+
+    check_round_trip::<Impl, _>(doc.clone()).unwrap();
+    let mut options = Options::new();
+    options.set("extjson", "relaxed").unwrap();
+    check_round_trip_with_options::<Impl, _>(&options, doc).unwrap();
+    check_arbitrary_input::<Impl>().unwrap();
+}
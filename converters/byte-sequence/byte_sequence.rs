@@ -1,10 +1,9 @@
 
-use format_galaxy_core::gen_plugin;
-
+#[cfg(target_arch="wasm32")]
 #[global_allocator]
 static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 
-struct Impl {}
+pub struct Impl {}
 
 impl format_galaxy_core::GalaxyFormat for Impl {
     fn present(bytes: &[u8]) -> Result<String, String> {
@@ -12,12 +11,20 @@ impl format_galaxy_core::GalaxyFormat for Impl {
     }
 
     fn store(s: &str) -> Result<Vec<u8>, String> {
+        // the empty sequence is presented as an empty string
+        if s.trim().is_empty() {
+            return Ok(vec!());
+        }
         s.trim().split(',').map(|x| x.parse()).collect::<Result<Vec<u8>,_>>()
             .map_err(|_| "Could not convert text to byte sequence.".to_string())
     }
 }
 
-gen_plugin!{Impl}
+#[cfg(not(feature = "component"))]
+format_galaxy_core::gen_plugin!{Impl}
+
+#[cfg(feature = "component")]
+format_galaxy_core::gen_component!{Impl}
 
 #[test]
 fn test_impl() {
@@ -26,4 +33,12 @@ fn test_impl() {
     assert_eq!(Impl::present(&[]), Ok("".to_string()));
     assert_eq!(Impl::store("1,2,3"), Ok(vec!(1,2,3)));
     assert_eq!(Impl::store("1,2,3\n"), Ok(vec!(1,2,3)));
+    assert_eq!(Impl::store(""), Ok(vec!()));
+}
+
+#[test]
+fn test_round_trip() {
+    use format_galaxy_core::testing::{check_round_trip, check_arbitrary_input, proptest::prelude::*};
+    check_round_trip::<Impl, _>(any::<Vec<u8>>()).unwrap();
+    check_arbitrary_input::<Impl>().unwrap();
 }
@@ -0,0 +1,7 @@
+#![no_main]
+use format_galaxy_core::GalaxyFormat;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|bytes: &[u8]| {
+    let _ = bson_converter::Impl::present(bytes);
+});
@@ -0,0 +1,10 @@
+#![no_main]
+use format_galaxy_core::GalaxyFormat;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|s: &str| {
+    // stored bytes must be presentable again
+    if let Ok(bytes) = hexdump::Impl::store(s) {
+        hexdump::Impl::present(&bytes).expect("stored bytes could not be presented");
+    }
+});
@@ -0,0 +1,148 @@
+/* Hex dump of arbitrary bytes
+
+    00000000  48 65 6c 6c 6f 2c 20 77  6f 72 6c 64 21 0a 00 ff  |Hello, world!...|
+    00000010  2a                                                |*|
+
+Every line is a record for windowed presentation, so huge files can be dumped in parts.
+
+Storing parses the hex columns only. Offsets and the ASCII gutter (everything after the first `|`
+of a line) are ignored, so bytes can be added or removed anywhere without fixing up the rest of
+the dump.
+*/
+
+use format_galaxy_core::gen_plugin;
+use format_galaxy_core::window::Window;
+
+#[cfg(target_arch="wasm32")]
+#[global_allocator]
+static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
+
+const BYTES_PER_LINE: usize = 16;
+
+pub struct Impl {}
+
+// offsets are u64, usize is 32 bits in converters and files can be larger than 4 GiB
+fn present_line(offset: u64, chunk: &[u8]) -> String {
+    let mut line = format!("{:08x} ", offset);
+    for i in 0..BYTES_PER_LINE {
+        if i % 8 == 0 {
+            line.push(' ');
+        }
+        match chunk.get(i) {
+            Some(b) => line.push_str(&format!("{:02x} ", b)),
+            None => line.push_str("   "),
+        }
+    }
+    line.push(' ');
+    line.push('|');
+    line.extend(chunk.iter().map(|b| if b.is_ascii_graphic() || *b == b' ' { *b as char } else { '.' }));
+    line.push('|');
+    line
+}
+
+fn store_line(line: &str, bytes: &mut Vec<u8>) -> Result<(), String> {
+    let hex = match line.find('|') {
+        Some(idx) => &line[..idx],
+        None => line,
+    };
+    for (i, token) in hex.split_whitespace().enumerate() {
+        // the offset is the only token that isn't a single byte
+        if i == 0 && token.len() != 2 {
+            let offset = token.strip_suffix(':').unwrap_or(token);
+            if u64::from_str_radix(offset, 16).is_err() {
+                return Err(format!("Invalid offset '{}'", token));
+            }
+            continue;
+        }
+        if token.len() != 2 {
+            return Err(format!("Expected a byte (two hex digits), found '{}'", token));
+        }
+        let b = u8::from_str_radix(token, 16).map_err(|_| format!("Invalid byte '{}'", token))?;
+        bytes.push(b);
+    }
+    Ok(())
+}
+
+impl format_galaxy_core::GalaxyFormat for Impl {
+    fn present(bytes: &[u8]) -> Result<String, String> {
+        Ok(bytes.chunks(BYTES_PER_LINE).enumerate()
+            .map(|(i, chunk)| present_line((i * BYTES_PER_LINE) as u64, chunk) + "\n")
+            .collect())
+    }
+
+    fn present_window(chunk: &[u8], offset: u64, max_records: u32) -> Result<Window, String> {
+        let lines: Vec<_> = chunk.chunks(BYTES_PER_LINE).take(max_records as usize).collect();
+        let len: usize = lines.iter().map(|line| line.len()).sum();
+        Ok(Window {
+            text: lines.iter().enumerate()
+                .map(|(i, line)| present_line(offset + (i * BYTES_PER_LINE) as u64, line) + "\n")
+                .collect(),
+            records: lines.len() as u32,
+            next: offset + len as u64,
+        })
+    }
+
+    fn store(s: &str) -> Result<Vec<u8>, String> {
+        let mut bytes = vec!();
+        for (idx, line) in s.lines().enumerate() {
+            store_line(line, &mut bytes).map_err(|e| format!("Line {}: {}", idx + 1, e))?;
+        }
+        Ok(bytes)
+    }
+}
+
+gen_plugin!{Impl, windows}
+
+#[test]
+fn test_impl() {
+    use format_galaxy_core::GalaxyFormat;
+    let s = Impl::present(b"Hello, world!\n\0\xff*").unwrap();
+    assert_eq!(s, concat!(
+        "00000000  48 65 6c 6c 6f 2c 20 77  6f 72 6c 64 21 0a 00 ff  |Hello, world!...|\n",
+        "00000010  2a                                                |*|\n",
+    ));
+    assert_eq!(Impl::store(&s), Ok(b"Hello, world!\n\0\xff*".to_vec()));
+    assert_eq!(Impl::present(&[]), Ok("".to_string()));
+    assert_eq!(Impl::store(""), Ok(vec!()));
+}
+
+#[test]
+fn test_present_window() {
+    use format_galaxy_core::GalaxyFormat;
+    let bytes: Vec<u8> = (0..40).collect();
+    let window = Impl::present_window(&bytes[32..], 32, 10).unwrap();
+    assert_eq!(window.next, 40);
+    assert_eq!(window.records, 1);
+    assert_eq!(window.text, "00000020  20 21 22 23 24 25 26 27                           | !\"#$%&'|\n");
+
+    let first = Impl::present_window(&bytes, 0, 2).unwrap();
+    assert_eq!(first.next, 32);
+    assert_eq!(first.text + &window.text, Impl::present(&bytes).unwrap());
+    assert_eq!(Impl::present_window(&[], 0, 1).unwrap(), Window { text: "".to_string(), records: 0, next: 0 });
+
+    // beyond 4 GiB
+    let offset = u32::MAX as u64 + 1;
+    let window = Impl::present_window(&bytes[..20], offset, 2).unwrap();
+    assert_eq!(window.next, offset + 20);
+    assert!(window.text.starts_with("100000000  00 01"), "{}", window.text);
+    assert!(window.text.contains("\n100000010  10 11"), "{}", window.text);
+    assert_eq!(Impl::store(&window.text), Ok(bytes[..20].to_vec()));
+}
+
+#[test]
+fn test_store_edited() {
+    use format_galaxy_core::GalaxyFormat;
+    // stale offsets and gutters, xxd style offsets and lines without offset
+    let s = "00000000  01 02 03  |stale|\n\n00000010: 04 05 |.|\nff\n";
+    assert_eq!(Impl::store(s), Ok(vec!(1, 2, 3, 4, 5, 255)));
+    assert_eq!(Impl::store("00000000  01 zz"), Err("Line 1: Invalid byte 'zz'".to_string()));
+    assert_eq!(Impl::store("01 002"), Err("Line 1: Expected a byte (two hex digits), found '002'".to_string()));
+    assert_eq!(Impl::store("0x0000 01"), Err("Line 1: Invalid offset '0x0000'".to_string()));
+}
+
+#[test]
+fn test_round_trip() {
+    use format_galaxy_core::testing::{check_round_trip, check_arbitrary_input, proptest::prelude::*};
+    check_round_trip::<Impl, _>(any::<Vec<u8>>()).unwrap();
+    check_arbitrary_input::<Impl>().unwrap();
+}
@@ -1,25 +1,74 @@
 
 use format_galaxy_core::gen_plugin;
-use json_like_value::Value;
+use format_galaxy_core::location::StoreError;
+use format_galaxy_core::options::Options;
+use format_galaxy_core::styled::StyledText;
+use json_like_value::{PrintOptions, Value};
 
+#[cfg(target_arch="wasm32")]
 #[global_allocator]
 static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 
-struct Impl {}
+pub struct Impl {}
+
+fn print_options(options: &Options) -> Result<PrintOptions, String> {
+    let default = PrintOptions::default();
+    Ok(PrintOptions {
+        // without indentation, nesting would get lost
+        indent: options.int_in("indent", default.indent as i64, 1..=16)? as usize,
+        sort_keys: options.bool("sort_keys", default.sort_keys)?,
+        ..default
+    })
+}
 
 impl format_galaxy_core::GalaxyFormat for Impl {
     fn present(bytes: &[u8]) -> Result<String, String> {
+        Self::present_with_options(bytes, &Options::new())
+    }
+
+    fn present_styled(bytes: &[u8]) -> Result<StyledText, String> {
+        Self::present_styled_with_options(bytes, &Options::new())
+    }
+
+    fn present_with_options(bytes: &[u8], options: &Options) -> Result<String, String> {
+        Self::present_styled_with_options(bytes, options).map(|styled| styled.text)
+    }
+
+    fn present_styled_with_options(bytes: &[u8], options: &Options) -> Result<StyledText, String> {
+        let options = print_options(options)?;
         let val = Value::deserialize(bytes).map_err(|x| x.to_string())?;
-        Ok(val.pretty_print_2())
+        Ok(val.pretty_print_2_styled_with(&options))
     }
 
     fn store(s: &str) -> Result<Vec<u8>, String> {
-        let val = Value::parse_indented(s)?;
+        Self::store_with_options(s, &Options::new())
+    }
+
+    fn store_with_options(s: &str, options: &Options) -> Result<Vec<u8>, String> {
+        Self::store_located(s, options).map_err(|e| e.message)
+    }
+
+    fn store_located(s: &str, options: &Options) -> Result<Vec<u8>, StoreError> {
+        print_options(options)?;
+        let val = Value::parse_indented_located(s)?;
         Ok(val.serialize())
     }
 }
 
-gen_plugin!{Impl}
+gen_plugin!{Impl, styled, options, locations}
+
+
+#[test]
+fn test_location() {
+    use format_galaxy_core::GalaxyFormat;
+
+    // locations are in the whole text, not the line
+    let err = Impl::store_located("[]\n  1\n  x", &Options::new()).unwrap_err();
+    assert_eq!(err.location, Some(9..10));
+    // or the whole line
+    let err = Impl::store_located("[]\n  1\n  {}\n    \"a\" 2", &Options::new()).unwrap_err();
+    assert_eq!(err.location, Some(16..21));
+}
 
 
 #[test]
@@ -79,4 +128,25 @@ fn test() {
         let val2 = Value::parse_indented(&s).expect("parsing led to error!");
         assert_eq!(val, val2);
     }
-}
\ No newline at end of file
+}
+#[test]
+fn test_round_trip() {
+    use format_galaxy_core::testing::{check_round_trip, check_arbitrary_input, proptest::prelude::*};
+    use json_like_value::arb_value;
+
+    check_round_trip::<Impl, _>(arb_value().prop_map(|v| v.serialize())).unwrap();
+    check_arbitrary_input::<Impl>().unwrap();
+}
+
+#[test]
+fn test_options() {
+    use format_galaxy_core::GalaxyFormat;
+
+    let bytes = Value::Array(vec!(Value::Number(1))).serialize();
+    let mut options = Options::new();
+    options.set("indent", "4").unwrap();
+    assert_eq!(Impl::present_with_options(&bytes, &options), Ok("[]\n    1".to_string()));
+    // without indentation, nesting would get lost
+    options.set("indent", "0").unwrap();
+    assert_eq!(Impl::present_with_options(&bytes, &options), Err("Option 'indent' must be in the range 1..=16, not 0".to_string()));
+}
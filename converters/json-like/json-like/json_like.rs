@@ -1,27 +1,62 @@
 
 use format_galaxy_core::{GalaxyFormat, gen_plugin};
-use json_like_value::Value;
+use format_galaxy_core::location::StoreError;
+use format_galaxy_core::options::Options;
+use format_galaxy_core::styled::StyledText;
+use json_like_value::{PrintOptions, Value};
 
 
+#[cfg(target_arch="wasm32")]
 #[global_allocator]
 static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 
 
-struct Impl {}
+pub struct Impl {}
+
+fn print_options(options: &Options) -> Result<PrintOptions, String> {
+    let default = PrintOptions::default();
+    Ok(PrintOptions {
+        indent: options.int_in("indent", default.indent as i64, 0..=16)? as usize,
+        sort_keys: options.bool("sort_keys", default.sort_keys)?,
+        trailing_commas: options.bool("trailing_commas", default.trailing_commas)?,
+    })
+}
 
 impl format_galaxy_core::GalaxyFormat for Impl {
     fn present(bytes: &[u8]) -> Result<String, String> {
+        Self::present_with_options(bytes, &Options::new())
+    }
+
+    fn present_styled(bytes: &[u8]) -> Result<StyledText, String> {
+        Self::present_styled_with_options(bytes, &Options::new())
+    }
+
+    fn present_with_options(bytes: &[u8], options: &Options) -> Result<String, String> {
+        Self::present_styled_with_options(bytes, options).map(|styled| styled.text)
+    }
+
+    fn present_styled_with_options(bytes: &[u8], options: &Options) -> Result<StyledText, String> {
+        let options = print_options(options)?;
         let val = Value::deserialize(bytes).map_err(|x| x.to_string())?;
-        Ok(val.pretty_print())
+        Ok(val.pretty_print_styled_with(&options))
     }
 
     fn store(s: &str) -> Result<Vec<u8>, String> {
-        let val = Value::parse(s)?;
+        Self::store_with_options(s, &Options::new())
+    }
+
+    fn store_with_options(s: &str, options: &Options) -> Result<Vec<u8>, String> {
+        Self::store_located(s, options).map_err(|e| e.message)
+    }
+
+    fn store_located(s: &str, options: &Options) -> Result<Vec<u8>, StoreError> {
+        print_options(options)?;
+        let val = Value::parse_located(s)?;
         Ok(val.serialize())
     }
 }
 
-gen_plugin!{Impl}
+gen_plugin!{Impl, styled, options, locations}
 
 #[test]
 fn test_diagnostics() {
@@ -41,6 +76,15 @@ fn test_diagnostics() {
     
 }
 
+#[test]
+fn test_location() {
+    let err = Impl::store_located("[1, x]", &Options::new()).unwrap_err();
+    assert_eq!(err.location, Some(4..5));
+    assert_eq!(Impl::store("[1, x]"), Err(err.message));
+    // the end of the input for errors past it
+    assert_eq!(Impl::store_located("\n{ tr", &Options::new()).unwrap_err().location, Some(5..5));
+}
+
 #[test]
 fn test() {
     let values = vec!(
@@ -97,4 +141,81 @@ fn test() {
         let val2 = Value::parse(&s).expect("parsing led to error!");
         assert_eq!(val, val2);
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_escapes() {
+    // non-printable characters are escaped like `Debug` does
+    let val = Value::String("\0\u{1b}\u{7f}\u{200b}'\"\\".to_string());
+    let s = val.pretty_print();
+    assert_eq!(s, r#""\0\u{1b}\u{7f}\u{200b}'\"\\""#);
+    assert_eq!(Value::parse(&s), Ok(val));
+
+    for invalid in [r#""\u{}""#, r#""\u{110000}""#, r#""\u{1234567}""#, r#""\u1b""#] {
+        assert!(Impl::store_located(invalid, &Options::new()).unwrap_err().location.is_some(), "{}", invalid);
+    }
+}
+
+#[test]
+fn test_round_trip() {
+    use format_galaxy_core::testing::{check_round_trip, check_arbitrary_input, proptest::prelude::*};
+    use json_like_value::arb_value;
+
+    check_round_trip::<Impl, _>(arb_value().prop_map(|v| v.serialize())).unwrap();
+    check_arbitrary_input::<Impl>().unwrap();
+}
+
+#[test]
+fn test_present_styled() {
+    use format_galaxy_core::styled::Style;
+
+    let val = Value::Object([
+        ("a".to_string(), Value::Array(vec!(Value::Number(1), Value::Null))),
+    ].iter().cloned().collect());
+    let bytes = val.serialize();
+    let styled = Impl::present_styled(&bytes).unwrap();
+    assert_eq!(styled.text, Impl::present(&bytes).unwrap());
+
+    let tokens: Vec<_> = styled.segments().into_iter().filter(|(_s, style)| *style != Style::Plain).collect();
+    assert_eq!(tokens, vec!(
+        ("{", Style::Punctuation),
+        ("\"a\"", Style::Key),
+        (":", Style::Punctuation),
+        ("[", Style::Punctuation),
+        ("1", Style::Number),
+        (",", Style::Punctuation),
+        ("null", Style::Literal),
+        (",", Style::Punctuation),
+        ("]", Style::Punctuation),
+        (",", Style::Punctuation),
+        ("}", Style::Punctuation),
+    ));
+}
+
+#[test]
+fn test_options() {
+    let val = Value::Object([
+        ("b".to_string(), Value::Array(vec!(Value::Number(1), Value::Null))),
+        ("a".to_string(), Value::Bool(true)),
+    ].iter().cloned().collect());
+    let bytes = val.serialize();
+
+    let mut options = Options::new();
+    options.set("indent", "4").unwrap();
+    options.set("sort_keys", "true").unwrap();
+    options.set("trailing_commas", "false").unwrap();
+    let s = Impl::present_with_options(&bytes, &options).unwrap();
+    assert_eq!(s, "{\n    \"a\": true,\n    \"b\": [\n        1,\n        null\n    ]\n}");
+    assert_eq!(Impl::present_styled_with_options(&bytes, &options).unwrap().text, s);
+    assert_eq!(Impl::store(&s), Ok(Value::parse(&s).unwrap().serialize()));
+
+    assert_eq!(Impl::present_with_options(&bytes, &Options::new()), Impl::present(&bytes));
+    options.set("indent", "wide").unwrap();
+    assert!(Impl::present_with_options(&bytes, &options).is_err());
+    // the range declared in the index
+    options.set("indent", "17").unwrap();
+    assert_eq!(Impl::present_with_options(&bytes, &options), Err("Option 'indent' must be in the range 0..=16, not 17".to_string()));
+    assert!(Impl::store_with_options(&s, &options).is_err());
+    options.set("indent", "0").unwrap();
+    assert!(Impl::present_with_options(&bytes, &options).is_ok());
+}
@@ -1,7 +1,9 @@
 use codespan_reporting::{diagnostic::{self, Diagnostic, Label}, files::SimpleFile, term::{self, termcolor::{ColorChoice, StandardStream}}};
 use indexmap::IndexMap;
-use std::{io::{Error, ErrorKind, Read}, iter::Peekable};
+use std::{io::{Error, ErrorKind, Read}, iter::Peekable, ops::Range};
 
+use format_galaxy_core::location::StoreError;
+use format_galaxy_core::styled::{Style, StyledText};
 use str_tree::StrTree;
 
 #[derive(Default)]
@@ -38,9 +40,12 @@ where T: Read
 
     pub fn read_string(&mut self) -> Result<String, Error> {
         let len = self.read_u64()?;
+        // don't trust the length prefix for allocating, it may be arbitrarily large
         let mut buf = Vec::new();
-        buf.resize(len as usize, 0u8);
-        self.r.read_exact(&mut buf)?;
+        (&mut self.r).take(len).read_to_end(&mut buf)?;
+        if (buf.len() as u64) < len {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "String is shorter than its length prefix."));
+        }
         String::from_utf8(buf).map_err(|e| Error::new(ErrorKind::InvalidData, e))
     }
 
@@ -57,24 +62,61 @@ where T: Read
     }
 }
 
-static INDENT_SIZE: usize = 2;
+/// Layout choices of the pretty printers.
+///
+/// They only affect printing, e.g. parsing keeps the keys in the order of the text even if it
+/// was printed with `sort_keys`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PrintOptions {
+    /// Spaces per indentation level.
+    pub indent: usize,
+    /// Print object keys in sorted order instead of insertion order.
+    pub sort_keys: bool,
+    /// Put a comma after the last element of arrays and objects (`pretty_print` only).
+    pub trailing_commas: bool,
+}
+
+impl Default for PrintOptions {
+    fn default() -> Self {
+        PrintOptions {
+            indent: 2,
+            sort_keys: false,
+            trailing_commas: true,
+        }
+    }
+}
 
 #[derive(Default)]
 struct ValuePrinter {
     indent: usize,
-    s: String
+    options: PrintOptions,
+    s: StyledText,
 }
 
 impl ValuePrinter {
-    fn print_newline(&mut self) {
-        self.s.push('\n');
-        for _ in 0..self.indent*INDENT_SIZE {
-            self.s.push(' ');
+    fn new(options: &PrintOptions) -> Self {
+        ValuePrinter {
+            options: options.clone(),
+            ..Default::default()
+        }
+    }
+
+    fn entries<'a>(&self, o: &'a IndexMap<String, Value>) -> Vec<(&'a String, &'a Value)> {
+        let mut entries: Vec<_> = o.iter().collect();
+        if self.options.sort_keys {
+            entries.sort_by(|a, b| a.0.cmp(b.0));
         }
+        entries
+    }
+
+    fn print_newline(&mut self) {
+        let indent = " ".repeat(self.indent*self.options.indent);
+        self.s.push("\n", Style::Plain);
+        self.s.push(&indent, Style::Plain);
     }
 
     fn print_str(&mut self, s: &str) {
-        self.s.push_str(&format!("{:?}", s));
+        self.s.push(&format!("{:?}", s), Style::String);
         /*self.s.push('"');
         for c in s.escape_default() {
             self.s.push(c);
@@ -82,21 +124,25 @@ impl ValuePrinter {
         self.s.push('"');*/
     }
 
+    fn print_key(&mut self, s: &str) {
+        self.s.push(&format!("{:?}", s), Style::Key);
+    }
+
     fn print_bool(&mut self, b: bool) {
         let s = if b {
             "true"
         } else {
             "false"
         };
-        self.s.push_str(s);
+        self.s.push(s, Style::Literal);
     }
 
     fn print_null(&mut self) {
-        self.s.push_str("null");
+        self.s.push("null", Style::Literal);
     }
 
     fn print_u64(&mut self, n: u64) {
-        self.s.push_str(&n.to_string());
+        self.s.push(&n.to_string(), Style::Number);
     }
 
     fn indent(&mut self) {
@@ -108,7 +154,8 @@ impl ValuePrinter {
     }
 
     fn print(&mut self, c: char) {
-        self.s.push(c);
+        let style = if c.is_whitespace() { Style::Plain } else { Style::Punctuation };
+        self.s.push(c.encode_utf8(&mut [0; 4]), style);
     }
 }
 
@@ -127,7 +174,7 @@ enum Token {
 }
 
 struct Tokenizer<'a> {
-    iter: std::iter::Peekable<std::iter::Enumerate<std::str::Chars<'a>>>,
+    iter: std::iter::Peekable<std::str::CharIndices<'a>>,
     tokens: Vec<Token>,
     input: &'a str,
 }
@@ -135,7 +182,7 @@ struct Tokenizer<'a> {
 impl<'a> Tokenizer<'a> {
     fn new(s: &'a str) -> Self {
         Tokenizer {
-            iter: s.chars().enumerate().peekable(),
+            iter: s.char_indices().peekable(),
             tokens: vec!(),
             input: s,
         }
@@ -149,37 +196,36 @@ impl<'a> Tokenizer<'a> {
         String::from_utf8(writer.into_inner()).unwrap()
     }
 
-    fn consume(&mut self, c: char) -> Result<(), String> {
+    // the diagnostic as message, located at `range` (the end of the input for ranges past it)
+    fn error(&self, message: &str, range: Range<usize>, label: String) -> StoreError {
+        let diag = Diagnostic::error()
+            .with_message(message)
+            .with_labels(vec!(Label::primary((), range.clone()).with_message(label)));
+        let len = self.input.len();
+        StoreError::at(self.format_diagnostic(diag), range.start.min(len)..range.end.min(len))
+    }
+
+    fn consume(&mut self, c: char) -> Result<(), StoreError> {
         match self.iter.next() {
             None => {
-                let num_chars = self.input.chars().count();
-                let diag = Diagnostic::error()
-                    .with_message("Unexpected end of input")
-                    .with_labels(vec!(
-                        Label::primary((), num_chars..num_chars+1).with_message(format!("Expected character `{}`", c)),
-                    ));
-                Err(self.format_diagnostic(diag))
+                let len = self.input.len();
+                Err(self.error("Unexpected end of input", len..len+1, format!("Expected character `{}`", c)))
             },
             Some((_idx, x)) if x == c => Ok(()),
             Some((idx, other)) => {
-                let diag = Diagnostic::error()
-                    .with_message("Unexpected input")
-                    .with_labels(vec!(
-                        Label::primary((), idx..idx+1).with_message(format!("Expected character `{}`, found `{}`", c, other)),
-                    ));
-                Err(self.format_diagnostic(diag))
+                Err(self.error("Unexpected input", idx..idx+other.len_utf8(), format!("Expected character `{}`, found `{}`", c, other)))
             }
         }
     }
 
-    fn consume_str(&mut self, s: &str) -> Result<(), String> {
+    fn consume_str(&mut self, s: &str) -> Result<(), StoreError> {
         for c in s.chars() {
             self.consume(c)?;
         }
         Ok(())
     }
 
-    fn tok_string(&mut self) -> Result<String, String> {
+    fn tok_string(&mut self) -> Result<String, StoreError> {
         self.consume('"')?;
         let mut s = String::new();
         let mut escape = false;
@@ -187,21 +233,23 @@ impl<'a> Tokenizer<'a> {
             // handle string escapes
             match (self.iter.next(), escape) {
                 (None, _) => { 
-                    let num_chars = self.input.chars().count();
-                    let diag = Diagnostic::error()
-                        .with_message("Unexpected end of input")
-                        .with_labels(vec!(
-                            Label::primary((), num_chars..num_chars+1).with_message("Expected more characters "),
-                        ));
-                    return Err(self.format_diagnostic(diag));
+                    let len = self.input.len();
+                    return Err(self.error("Unexpected end of input", len..len+1, "Expected more characters ".to_string()));
                 }
                 (Some((_idx, '\\')), false) => { escape = true; }
+                (Some((idx, 'u')), true) => {
+                    escape = false;
+                    // the backslash is right before the `u`
+                    let c = self.tok_unicode_escape(idx - 1)?;
+                    s.push(c);
+                }
                 (Some((_idx, c)), true) => {
                     escape = false;
                     let c = match c {
                         't' => '\t',
                         'n' => '\n',
                         'r' => '\r',
+                        '0' => '\0',
                         c => c,
                     };
                     s.push(c);
@@ -217,7 +265,29 @@ impl<'a> Tokenizer<'a> {
         Ok(s)
     }
 
-    fn tokenize(mut self) -> Result<Vec<Token>, String> {
+    // the rest of a `\u{1b}` escape starting at `start`, strings are printed like `Debug` does
+    // and that escapes non-printable characters like this
+    fn tok_unicode_escape(&mut self, start: usize) -> Result<char, StoreError> {
+        self.consume('{')?;
+        let mut hex = String::new();
+        let end = loop {
+            match self.iter.next() {
+                Some((idx, '}')) => break idx + 1,
+                Some((_idx, c)) if c.is_ascii_hexdigit() && hex.len() < 6 => hex.push(c),
+                Some((idx, c)) => {
+                    return Err(self.error("Invalid unicode escape", start..idx + c.len_utf8(), "Expected up to six hex digits and `}`".to_string()));
+                }
+                None => {
+                    let len = self.input.len();
+                    return Err(self.error("Unexpected end of input", len..len+1, "Expected character `}`".to_string()));
+                }
+            }
+        };
+        u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32)
+            .ok_or_else(|| self.error("Invalid unicode escape", start..end, format!("`{}` isn't a character", hex)))
+    }
+
+    fn tokenize(mut self) -> Result<Vec<Token>, StoreError> {
         loop {
             match self.iter.peek() {
                 None => { break },
@@ -273,7 +343,7 @@ impl<'a> Tokenizer<'a> {
                             Some((idx, c)) if c.is_numeric() => {
                                 self.iter.next();
                                 s.push(c);
-                                end_idx = idx;
+                                end_idx = idx + c.len_utf8();
                             }
                             _ => {
                                 break;
@@ -285,24 +355,16 @@ impl<'a> Tokenizer<'a> {
                         Ok(n) => n,
                         Err(_) => {
                             // number literal is too large
-                            let diag = Diagnostic::error()
-                                .with_message("Integer literal too large")
-                                .with_labels(vec!(
-                                    Label::primary((), start_idx..end_idx+1).with_message("Provided integer literal is too large"),
-                                ));
-                            return Err(self.format_diagnostic(diag));
+                            return Err(self.error("Integer literal too large", start_idx..end_idx, "Provided integer literal is too large".to_string()));
                         }
                     };
 
                     self.tokens.push(Token::Num(n));
                 }
                 Some((idx, c)) => {
-                    let diag = Diagnostic::error()
-                        .with_message("Unexpected input")
-                        .with_labels(vec!(
-                            Label::primary((), *idx..*idx+1).with_message(format!("Unexpected character `{}`", c)),
-                        ));
-                    return Err(self.format_diagnostic(diag));
+                    let range = *idx..*idx+c.len_utf8();
+                    let label = format!("Unexpected character `{}`", c);
+                    return Err(self.error("Unexpected input", range, label));
                 }
             }
         }
@@ -398,7 +460,7 @@ impl Value {
             }
             4 => {
                 let len = r.read_u64()? as usize;
-                let mut v = Vec::with_capacity(len);
+                let mut v = Vec::new();
                 for _ in 0..len {
                     v.push(Self::deserialize_(r)?);
                 }
@@ -406,7 +468,7 @@ impl Value {
             }
             5 => {
                 let len = r.read_u64()? as usize;
-                let mut m = IndexMap::with_capacity(len);
+                let mut m = IndexMap::new();
                 for _ in 0..len {
                     let key = r.read_string()?;
                     let val = Self::deserialize_(r)?;
@@ -422,7 +484,17 @@ impl Value {
     }
 
     pub fn pretty_print(&self) -> String {
-        let mut printer = ValuePrinter::default();
+        self.pretty_print_styled().text
+    }
+
+    /// Same as `pretty_print`, with styled spans for syntax highlighting.
+    pub fn pretty_print_styled(&self) -> StyledText {
+        self.pretty_print_styled_with(&PrintOptions::default())
+    }
+
+    /// Same as `pretty_print_styled`, with a custom layout.
+    pub fn pretty_print_styled_with(&self, options: &PrintOptions) -> StyledText {
+        let mut printer = ValuePrinter::new(options);
         self.pretty_print_(&mut printer);
         printer.s
     }
@@ -438,10 +510,12 @@ impl Value {
                 p.print('[');
                 if !a.is_empty() {
                     p.indent();
-                    for v in a {
+                    for (i, v) in a.iter().enumerate() {
                         p.print_newline();
                         v.pretty_print_(p);
-                        p.print(',');
+                        if i + 1 < a.len() || p.options.trailing_commas {
+                            p.print(',');
+                        }
                     }
                     p.dedent();
                     p.print_newline();
@@ -452,13 +526,16 @@ impl Value {
                 p.print('{');
                 if !o.is_empty() {
                     p.indent();
-                    for (key, val) in o {
+                    let entries = p.entries(o);
+                    for (i, (key, val)) in entries.iter().enumerate() {
                         p.print_newline();
-                        p.print_str(key);
+                        p.print_key(key);
                         p.print(':');
                         p.print(' ');
                         val.pretty_print_(p);
-                        p.print(',');
+                        if i + 1 < entries.len() || p.options.trailing_commas {
+                            p.print(',');
+                        }
                     }
                     p.dedent();
                     p.print_newline();
@@ -469,13 +546,18 @@ impl Value {
     }
 
     pub fn parse(s: &str) -> Result<Self, String> {
+        Self::parse_located(s).map_err(|e| e.message)
+    }
+
+    /// Like `parse`, with the range of `s` an error is about if it's known.
+    pub fn parse_located(s: &str) -> Result<Self, StoreError> {
         let tokens = Tokenizer::new(s).tokenize()?;
         let mut iter = tokens.into_iter().peekable();
         let res = Self::parse_(&mut iter);
         if iter.next().is_some() {
-            return Err("Unexpected characters".to_string())
+            return Err(StoreError::from("Unexpected characters".to_string()))
         }
-        res
+        Ok(res?)
     }
 
     fn parse_(iter: &mut std::iter::Peekable<std::vec::IntoIter<Token>>) -> Result<Self, String> {
@@ -564,7 +646,17 @@ impl Value {
     }
 
     pub fn pretty_print_2(&self) -> String {
-        let mut printer = ValuePrinter::default();
+        self.pretty_print_2_styled().text
+    }
+
+    /// Same as `pretty_print_2`, with styled spans for syntax highlighting.
+    pub fn pretty_print_2_styled(&self) -> StyledText {
+        self.pretty_print_2_styled_with(&PrintOptions::default())
+    }
+
+    /// Same as `pretty_print_2_styled`, with a custom layout.
+    pub fn pretty_print_2_styled_with(&self, options: &PrintOptions) -> StyledText {
+        let mut printer = ValuePrinter::new(options);
         self.pretty_print_2_(&mut printer);
         printer.s
     }
@@ -577,7 +669,8 @@ impl Value {
             Number(n) => p.print_u64(*n),
             String(s) => p.print_str(s),
             Array(a) => {
-                p.s.push_str("[]");
+                p.print('[');
+                p.print(']');
                 if !a.is_empty() {
                     p.indent();
                     for v in a {
@@ -589,12 +682,13 @@ impl Value {
                 //p.print_newline();
             }
             Object(o) => {
-                p.s.push_str("{}");
+                p.print('{');
+                p.print('}');
                 if !o.is_empty() {
                     p.indent();
-                    for (key, val) in o {
+                    for (key, val) in p.entries(o) {
                         p.print_newline();
-                        p.print_str(key);
+                        p.print_key(key);
                         p.print(':');
                         p.print(' ');
                         val.pretty_print_2_(p);
@@ -607,8 +701,13 @@ impl Value {
     }
 
     pub fn parse_indented(s: &str) -> Result<Self, String> {
+        Self::parse_indented_located(s).map_err(|e| e.message)
+    }
+
+    /// Like `parse_indented`, with the range of `s` an error is about if it's known.
+    pub fn parse_indented_located(s: &str) -> Result<Self, StoreError> {
         let st = parse_single_tree(s)?;
-        parse_str_tree(st)
+        parse_str_tree(s, st)
     }
 }
 
@@ -681,9 +780,20 @@ fn parse_one_root<'a, I: Iterator<Item = (usize, &'a str)>>(line_iter: &mut Peek
     Ok(stack.pop().unwrap().1)
 }
 
-fn parse_line(s: &str) -> Result<Value, String> {
-    let tokens = Tokenizer::new(s).tokenize()?;
-    parse_line_inner(&tokens)
+// range of `line` in `text`, lines of a tree are slices of the text it was parsed from
+fn line_range(text: &str, line: &str) -> Range<usize> {
+    let offset = line.as_ptr() as usize - text.as_ptr() as usize;
+    offset..offset + line.len()
+}
+
+// tokenizes `line` of `text`, errors are located in `text`
+fn tokenize_line(text: &str, line: &str) -> Result<Vec<Token>, StoreError> {
+    Tokenizer::new(line).tokenize().map_err(|e| e.shift(line_range(text, line).start))
+}
+
+fn parse_line(text: &str, line: &str) -> Result<Value, StoreError> {
+    let tokens = tokenize_line(text, line)?;
+    parse_line_inner(&tokens).map_err(|msg| StoreError::at(msg, line_range(text, line)))
 }
 
 fn parse_line_inner(tokens: &[Token]) -> Result<Value, String> {
@@ -700,36 +810,38 @@ fn parse_line_inner(tokens: &[Token]) -> Result<Value, String> {
     Ok(value)
 }
 
-fn parse_str_tree(st: StrTree) -> Result<Value, String> {
-    parse_str_tree_inner(parse_line(st.elmt)?, st.children)
+fn parse_str_tree(text: &str, st: StrTree) -> Result<Value, StoreError> {
+    parse_str_tree_inner(text, parse_line(text, st.elmt)?, st.children)
     
 }
 
-fn parse_str_tree_inner(line_value: Value, children: Vec<StrTree>) -> Result<Value, String> {
+fn parse_str_tree_inner(text: &str, line_value: Value, children: Vec<StrTree>) -> Result<Value, StoreError> {
     let value = match line_value {
         Value::Array(_) => {
-            let res: Result<Vec<_>, _> = children.into_iter().map(|s| parse_str_tree(s)).collect();
+            let res: Result<Vec<_>, _> = children.into_iter().map(|s| parse_str_tree(text, s)).collect();
             Value::Array(res?)
         }
         Value::Object(_) => {
             let mut map = IndexMap::new();
             for child in children {
-                let tokens = Tokenizer::new(child.elmt).tokenize()?;
+                let line = line_range(text, child.elmt);
+                let tokens = tokenize_line(text, child.elmt)?;
                 let key = match &tokens[..2] {
                     [Token::Str(s), Token::Colon] => {
                         s.clone()
                     }
-                    _ => { return Err("Failed to parse line as an object attribute".to_string()); }
+                    _ => { return Err(StoreError::at("Failed to parse line as an object attribute".to_string(), line)); }
                 };
-                let line_value = parse_line_inner(&tokens[2..])?;
-                let value = parse_str_tree_inner(line_value, child.children)?;
+                let line_value = parse_line_inner(&tokens[2..]).map_err(|msg| StoreError::at(msg, line))?;
+                let value = parse_str_tree_inner(text, line_value, child.children)?;
                 map.insert(key, value);
             }
             Value::Object(map)
         }
         val => {
             if !children.is_empty() {
-                return Err("This node may not have children".to_string());
+                let line = line_range(text, children[0].elmt);
+                return Err(StoreError::at("This node may not have children".to_string(), line));
             }
             val
         }
@@ -738,6 +850,23 @@ fn parse_str_tree_inner(line_value: Value, children: Vec<StrTree>) -> Result<Val
     Ok(value)
 }
 
+/// Arbitrary values for property-based tests of the converters, enable the `testing` feature.
+#[cfg(feature = "testing")]
+pub fn arb_value() -> impl format_galaxy_core::testing::proptest::strategy::Strategy<Value = Value> {
+    use format_galaxy_core::testing::proptest::prelude::*;
+
+    let leaf = prop_oneof![
+        Just(Value::Null),
+        any::<bool>().prop_map(Value::Bool),
+        any::<u64>().prop_map(Value::Number),
+        any::<String>().prop_map(Value::String),
+    ];
+    leaf.prop_recursive(4, 64, 8, |inner| prop_oneof![
+        prop::collection::vec(inner.clone(), 0..8).prop_map(Value::Array),
+        prop::collection::vec((any::<String>(), inner), 0..8).prop_map(|v| Value::Object(v.into_iter().collect())),
+    ])
+}
+
 
 #[test]
 fn codespan_test() {
@@ -1,6 +1,7 @@
 
 use format_galaxy_core::gen_plugin;
 
+#[cfg(target_arch="wasm32")]
 #[global_allocator]
 static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 
@@ -31,7 +32,7 @@ unsafe impl GlobalAlloc for MyAllocator {
 static GLOBAL: MyAllocator = MyAllocator;
 */
 
-struct Impl {}
+pub struct Impl {}
 
 impl format_galaxy_core::GalaxyFormat for Impl {
     fn present(bytes: &[u8]) -> Result<String, String> {
@@ -1,8 +1,12 @@
 
 use format_galaxy_core::gen_plugin;
 use anyhow::anyhow;
+use wasmparser::{ExternalKind, ImportSectionEntryType, Parser, Payload};
 
-struct Impl {}
+pub struct Impl {}
+
+// read-only view listing the imports and exports of a module
+const SUMMARY_VIEW: &str = "summary";
 
 fn present_inner(bytes: &[u8]) -> Result<String, anyhow::Error> {
     wasmprinter::print_bytes(bytes)
@@ -12,6 +16,56 @@ fn store_inner(s: &str) -> Result<Vec<u8>, anyhow::Error> {
     wat::parse_str(s).map_err(|e| anyhow!("This didn't work: {}", e))
 }
 
+fn import_kind(ty: &ImportSectionEntryType) -> &'static str {
+    match ty {
+        ImportSectionEntryType::Function(_) => "func",
+        ImportSectionEntryType::Table(_) => "table",
+        ImportSectionEntryType::Memory(_) => "memory",
+        ImportSectionEntryType::Global(_) => "global",
+        ImportSectionEntryType::Module(_) => "module",
+        ImportSectionEntryType::Instance(_) => "instance",
+    }
+}
+
+fn export_kind(kind: ExternalKind) -> &'static str {
+    match kind {
+        ExternalKind::Function => "func",
+        ExternalKind::Table => "table",
+        ExternalKind::Memory => "memory",
+        ExternalKind::Global => "global",
+        ExternalKind::Type => "type",
+        ExternalKind::Module => "module",
+        ExternalKind::Instance => "instance",
+    }
+}
+
+fn present_summary(bytes: &[u8]) -> Result<String, anyhow::Error> {
+    let mut imports = vec!();
+    let mut exports = vec!();
+    for payload in Parser::new(0).parse_all(bytes) {
+        match payload? {
+            Payload::ImportSection(reader) => {
+                for import in reader {
+                    let import = import?;
+                    let name = match import.field {
+                        Some(field) => format!("{}.{}", import.module, field),
+                        None => import.module.to_string(),
+                    };
+                    imports.push(format!("  {} {}\n", import_kind(&import.ty), name));
+                }
+            }
+            Payload::ExportSection(reader) => {
+                for export in reader {
+                    let export = export?;
+                    exports.push(format!("  {} {}\n", export_kind(export.kind), export.field));
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(format!("imports ({}):\n{}exports ({}):\n{}", imports.len(), imports.concat(), exports.len(), exports.concat()))
+}
+
 impl format_galaxy_core::GalaxyFormat for Impl {
     fn present(bytes: &[u8]) -> Result<String, String> {
         present_inner(bytes).map_err(|e| e.to_string())
@@ -20,6 +74,58 @@ impl format_galaxy_core::GalaxyFormat for Impl {
     fn store(s: &str) -> Result<Vec<u8>, String> {
         store_inner(s).map_err(|e| e.to_string())
     }
+
+    fn present_view(view: &str, bytes: &[u8]) -> Result<String, String> {
+        match view {
+            SUMMARY_VIEW => present_summary(bytes).map_err(|e| e.to_string()),
+            _ => Err(format!("Unknown view '{}'", view)),
+        }
+    }
+
+    fn store_view(view: &str, _s: &str) -> Result<Vec<u8>, String> {
+        match view {
+            SUMMARY_VIEW => Err("The summary view is read-only, use the default view to edit the module".to_string()),
+            _ => Err(format!("Unknown view '{}'", view)),
+        }
+    }
+}
+
+gen_plugin!{Impl, views}
+
+#[test]
+fn test_summary() {
+    use format_galaxy_core::GalaxyFormat;
+    let module = wat::parse_str(r#"(module
+        (import "env" "log" (func (param i32)))
+        (memory (export "memory") 1)
+        (func (export "present") (param i32 i32) (result i32) i32.const 0))"#).unwrap();
+    assert_eq!(Impl::present_view("summary", &module), Ok(concat!(
+        "imports (1):\n",
+        "  func env.log\n",
+        "exports (2):\n",
+        "  memory memory\n",
+        "  func present\n",
+    ).to_string()));
+    assert!(Impl::store_view("summary", "").is_err());
+    assert_eq!(Impl::present_view("wat", &module), Err("Unknown view 'wat'".to_string()));
+    assert!(Impl::present_view("summary", b"not a module").is_err());
 }
 
-gen_plugin!{Impl}
+#[test]
+fn test_round_trip() {
+    use format_galaxy_core::testing::{check_round_trip, check_arbitrary_input, proptest::prelude::*};
+
+    // modules as the text format encodes them, with memories, functions and exports
+    let func = (prop::collection::vec(any::<i32>(), 1..8), any::<bool>()).prop_map(|(consts, export)| {
+        let body: String = consts.iter().map(|c| format!("(drop (i32.const {}))", c)).collect();
+        let export = if export { format!("(export \"f{}\")", consts[0]) } else { String::new() };
+        format!("(func {} {})", export, body)
+    });
+    let module = (prop::option::of(0u32..4), prop::collection::vec(func, 0..4)).prop_map(|(memory, funcs)| {
+        let memory = memory.map(|pages| format!("(memory {})", pages)).unwrap_or_default();
+        wat::parse_str(format!("(module {} {})", memory, funcs.concat())).unwrap()
+    });
+
+    check_round_trip::<Impl, _>(module).unwrap();
+    check_arbitrary_input::<Impl>().unwrap();
+}
@@ -0,0 +1,180 @@
+/* Guest bindings of the converter world for converters built as components
+
+`wit/converter.wit` describes the interface between converters and hosts in the component
+model, so neither side has to deal with pointers, `ReturnData` or `alloc`/`free`. Converters
+keep implementing `GalaxyFormat` and export it with `gen_component!` instead of `gen_plugin!`
+(both export e.g. `present`, so a build has only one of them), then build for `wasm32-wasip2`
+with the `component` feature of this crate enabled (see the `byte-sequence` converter):
+
+    cargo build --release --target wasm32-wasip2 -p byte-sequence --features component
+
+The world covers everything the V1 ABI does: styled text, options, windows and views next to
+presenting, storing and sniffing, plus describing the converter. Errors of the `GalaxyFormat`
+defaults for options and windows are `unsupported`, all others are `invalid`. Store errors
+carry the location `store_located` reports.
+*/
+
+use std::sync::Once;
+
+use crate::styled;
+use crate::{is_unsupported, GalaxyFormat};
+
+wit_bindgen::generate!({
+    path: "wit",
+    world: "converter",
+    pub_export_macro: true,
+    export_macro_name: "export_converter",
+    default_bindings_module: "format_galaxy_core::component",
+});
+
+pub use exports::format_galaxy::converter::api::{
+    Error, Guest, Location, Metadata, Options, Span, StoreError, Style, StyledText, Window
+};
+pub use format_galaxy::converter::host;
+
+impl From<crate::log::Level> for host::Level {
+    fn from(level: crate::log::Level) -> Self {
+        use crate::log::Level::*;
+        match level {
+            Error => host::Level::Error,
+            Warn => host::Level::Warn,
+            Info => host::Level::Info,
+            Debug => host::Level::Debug,
+        }
+    }
+}
+
+/// Version of `format-galaxy-core`, reported in the converter's metadata.
+pub const CORE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Installs the panic hook on the first call into the converter, components have no `init`.
+pub fn init() {
+    static INIT: Once = Once::new();
+    INIT.call_once(crate::log::install_panic_hook);
+}
+
+pub fn metadata(name: &str, version: &str) -> Metadata {
+    Metadata {
+        name: name.to_string(),
+        version: version.to_string(),
+        core_version: CORE_VERSION.to_string(),
+    }
+}
+
+fn error(msg: String) -> Error {
+    if is_unsupported(&msg) {
+        Error::Unsupported(msg)
+    } else {
+        Error::Invalid(msg)
+    }
+}
+
+fn core_options(values: Options) -> Result<crate::options::Options, Error> {
+    let mut options = crate::options::Options::new();
+    for (key, value) in values {
+        options.set(&key, &value).map_err(Error::Invalid)?;
+    }
+    Ok(options)
+}
+
+fn style(style: styled::Style) -> Style {
+    match style {
+        styled::Style::Plain => Style::Plain,
+        styled::Style::Key => Style::Key,
+        styled::Style::String => Style::String,
+        styled::Style::Number => Style::Number,
+        styled::Style::Literal => Style::Literal,
+        styled::Style::Punctuation => Style::Punctuation,
+        styled::Style::Comment => Style::Comment,
+    }
+}
+
+pub fn present<T: GalaxyFormat>(bytes: Vec<u8>, values: Options) -> Result<String, Error> {
+    init();
+    T::present_with_options(&bytes, &core_options(values)?).map_err(error)
+}
+
+pub fn present_styled<T: GalaxyFormat>(bytes: Vec<u8>, values: Options) -> Result<StyledText, Error> {
+    init();
+    let styled = T::present_styled_with_options(&bytes, &core_options(values)?).map_err(error)?;
+    Ok(StyledText {
+        spans: styled.spans.iter()
+            .map(|span| Span { start: span.start as u32, end: span.end as u32, style: style(span.style) })
+            .collect(),
+        text: styled.text,
+    })
+}
+
+pub fn store_text<T: GalaxyFormat>(text: String, values: Options) -> Result<Vec<u8>, StoreError> {
+    init();
+    let options = core_options(values).map_err(|error| StoreError { error, location: None })?;
+    T::store_located(&text, &options).map_err(|e| StoreError {
+        error: error(e.message),
+        location: e.location.map(|location| Location { offset: location.start as u32, length: location.len() as u32 }),
+    })
+}
+
+pub fn sniff<T: GalaxyFormat>(bytes: Vec<u8>) -> u8 {
+    init();
+    T::sniff(&bytes).min(100)
+}
+
+pub fn present_window<T: GalaxyFormat>(chunk: Vec<u8>, offset: u64, max_records: u32) -> Result<Window, Error> {
+    init();
+    let window = T::present_window(&chunk, offset, max_records).map_err(error)?;
+    Ok(Window { text: window.text, records: window.records, next: window.next })
+}
+
+pub fn present_view<T: GalaxyFormat>(view: String, bytes: Vec<u8>) -> Result<String, Error> {
+    init();
+    T::present_view(&view, &bytes).map_err(error)
+}
+
+pub fn store_view<T: GalaxyFormat>(view: String, text: String) -> Result<Vec<u8>, Error> {
+    init();
+    T::store_view(&view, &text).map_err(error)
+}
+
+/// Exports a `GalaxyFormat` implementation as a component implementing the converter world.
+#[macro_export]
+macro_rules! gen_component {
+    ($impl_type:ty) => {
+        struct __GalaxyComponent;
+
+        impl format_galaxy_core::component::Guest for __GalaxyComponent {
+            fn describe() -> format_galaxy_core::component::Metadata {
+                format_galaxy_core::component::metadata(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))
+            }
+
+            fn present(bytes: Vec<u8>, options: format_galaxy_core::component::Options) -> Result<String, format_galaxy_core::component::Error> {
+                format_galaxy_core::component::present::<$impl_type>(bytes, options)
+            }
+
+            fn present_styled(bytes: Vec<u8>, options: format_galaxy_core::component::Options) -> Result<format_galaxy_core::component::StyledText, format_galaxy_core::component::Error> {
+                format_galaxy_core::component::present_styled::<$impl_type>(bytes, options)
+            }
+
+            fn store_text(text: String, options: format_galaxy_core::component::Options) -> Result<Vec<u8>, format_galaxy_core::component::StoreError> {
+                format_galaxy_core::component::store_text::<$impl_type>(text, options)
+            }
+
+            fn sniff(bytes: Vec<u8>) -> u8 {
+                format_galaxy_core::component::sniff::<$impl_type>(bytes)
+            }
+
+            fn present_window(chunk: Vec<u8>, offset: u64, max_records: u32) -> Result<format_galaxy_core::component::Window, format_galaxy_core::component::Error> {
+                format_galaxy_core::component::present_window::<$impl_type>(chunk, offset, max_records)
+            }
+
+            fn present_view(view: String, bytes: Vec<u8>) -> Result<String, format_galaxy_core::component::Error> {
+                format_galaxy_core::component::present_view::<$impl_type>(view, bytes)
+            }
+
+            fn store_view(view: String, text: String) -> Result<Vec<u8>, format_galaxy_core::component::Error> {
+                format_galaxy_core::component::store_view::<$impl_type>(view, text)
+            }
+        }
+
+        format_galaxy_core::component::export_converter!(__GalaxyComponent);
+    };
+}
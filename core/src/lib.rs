@@ -1,12 +1,115 @@
 use std::mem::ManuallyDrop;
 
+#[cfg(feature = "component")]
+pub mod component;
+pub mod location;
+pub mod log;
+pub mod options;
+pub mod styled;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod view;
+pub mod window;
+
+/// Reading and writing containers, e.g. for formats that nest them.
+pub use fg_container as container;
+
+use location::StoreError;
+use options::Options;
+use styled::StyledText;
+use window::Window;
+
+/// Whether `msg` is one of the errors of the `GalaxyFormat` defaults for operations a converter
+/// doesn't support (options and windows).
+pub fn is_unsupported(msg: &str) -> bool {
+    msg == options::OPTIONS_UNSUPPORTED || msg == window::WINDOWS_UNSUPPORTED
+}
+
 pub trait GalaxyFormat
 {
     fn present(bytes: &[u8]) -> Result<String, String>;
 
     fn store(s: &str) -> Result<Vec<u8>, String>;
+
+    /// Like `present`, but with styled spans for syntax highlighting.
+    ///
+    /// The text has to be the same as the one returned by `present`.
+    fn present_styled(bytes: &[u8]) -> Result<StyledText, String> {
+        Self::present(bytes).map(StyledText::plain)
+    }
+
+    /// Like `present`, with option values declared for the converter in the index.
+    ///
+    /// Converters with options override this (and `store_with_options`) and implement `present`
+    /// by passing no options. Without an override, options are refused like hosts refuse them
+    /// for converters that don't export the `_with_options` functions.
+    fn present_with_options(bytes: &[u8], options: &Options) -> Result<String, String> {
+        if !options.is_empty() {
+            return Err(options::OPTIONS_UNSUPPORTED.to_string());
+        }
+        Self::present(bytes)
+    }
+
+    /// Like `present_styled`, with option values declared for the converter in the index.
+    fn present_styled_with_options(bytes: &[u8], options: &Options) -> Result<StyledText, String> {
+        if options.is_empty() {
+            return Self::present_styled(bytes);
+        }
+        Self::present_with_options(bytes, options).map(StyledText::plain)
+    }
+
+    /// Like `store`, with option values declared for the converter in the index.
+    ///
+    /// Hosts pass the same options as for presenting, so converters whose options only affect
+    /// presenting still have to accept them here.
+    fn store_with_options(s: &str, options: &Options) -> Result<Vec<u8>, String> {
+        if !options.is_empty() {
+            return Err(options::OPTIONS_UNSUPPORTED.to_string());
+        }
+        Self::store(s)
+    }
+
+    /// Like `store_with_options`, with the range of the text an error is about.
+    ///
+    /// Hosts highlight the range, so converters that know it override this and implement
+    /// `store_with_options` with it. Hosts that don't ask for locations call `store` or
+    /// `store_with_options`.
+    fn store_located(s: &str, options: &Options) -> Result<Vec<u8>, StoreError> {
+        Self::store_with_options(s, options).map_err(StoreError::from)
+    }
+
+    /// Rates how likely it is that `bytes` are in this format, from 0 (no idea) to 100 (certain).
+    ///
+    /// Hosts use this to detect the format of files that don't use the container format. `bytes`
+    /// may only be the beginning of the file.
+    fn sniff(_bytes: &[u8]) -> u8 {
+        0
+    }
+
+    /// Presents up to `max_records` complete records from the start of `chunk`, a part of the
+    /// file beginning at `offset`.
+    ///
+    /// Only converters of formats made up of independent records (e.g. logs) implement this.
+    /// They are marked as `windowed` in the index.
+    fn present_window(_chunk: &[u8], _offset: u64, _max_records: u32) -> Result<Window, String> {
+        Err(window::WINDOWS_UNSUPPORTED.to_string())
+    }
+
+    /// Presents `bytes` in the named view, for formats with more than one textual view.
+    ///
+    /// The views a converter supports are listed in the index.
+    fn present_view(view: &str, _bytes: &[u8]) -> Result<String, String> {
+        Err(format!("Unknown view '{}'", view))
+    }
+
+    /// Parses text in the named view, the counterpart of `present_view`.
+    fn store_view(view: &str, _s: &str) -> Result<Vec<u8>, String> {
+        Err(format!("Unknown view '{}'", view))
+    }
 }
 
+// the raw pointers passed to these functions are handed out by the host via the plugin ABI
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 pub mod __mi {
     use super::*;
 
@@ -16,7 +119,10 @@ pub mod __mi {
         pub ptr: u32,
         pub len: u32,
         pub capacity: u32,
-        pub success: bool
+        pub success: bool,
+        // offset and length in the stored text, see `location`
+        pub location: [u32; 2],
+        pub has_location: bool,
     }
 
     pub fn present<T: GalaxyFormat>(ptr: *mut u8, len: u32) -> *mut ReturnData {
@@ -35,7 +141,102 @@ pub mod __mi {
     pub fn store<T: GalaxyFormat>(ptr: *mut u8, len: u32) -> *mut ReturnData {
         // input is a utf-8 string
         let s = unsafe { String::from_raw_parts(ptr, len as usize, len as usize) };
-        let res = <T as GalaxyFormat>::store(&s);
+        match <T as GalaxyFormat>::store_located(&s, &Options::new()) {
+            Ok(bytes) => alloc_result(bytes, true),
+            Err(e) => alloc_store_error(e),
+        }
+    }
+    
+    pub fn present_styled<T: GalaxyFormat>(ptr: *mut u8, len: u32) -> *mut ReturnData {
+        // input is a byte slice
+        let bytes = unsafe { Vec::from_raw_parts(ptr, len as usize, len as usize) };
+
+        let res = <T as GalaxyFormat>::present_styled(&bytes);
+        let success = res.is_ok();
+        let bytes = match res {
+            Ok(styled) => styled.encode(),
+            Err(s) => s.into_bytes(),
+        };
+        alloc_result(bytes, success)
+    }
+
+    pub fn present_with_options<T: GalaxyFormat>(ptr: *mut u8, len: u32) -> *mut ReturnData {
+        // input is options and a byte slice, see `options::encode_options_call`
+        let bytes = unsafe { Vec::from_raw_parts(ptr, len as usize, len as usize) };
+
+        let res = options::decode_options_call(&bytes)
+            .and_then(|(options, bytes)| <T as GalaxyFormat>::present_with_options(bytes, &options));
+        let success = res.is_ok();
+        let bytes = match res {
+            Ok(s) => s.into_bytes(),
+            Err(s) => s.into_bytes(),
+        };
+        alloc_result(bytes, success)
+    }
+
+    pub fn present_styled_with_options<T: GalaxyFormat>(ptr: *mut u8, len: u32) -> *mut ReturnData {
+        // input is options and a byte slice, see `options::encode_options_call`
+        let bytes = unsafe { Vec::from_raw_parts(ptr, len as usize, len as usize) };
+
+        let res = options::decode_options_call(&bytes)
+            .and_then(|(options, bytes)| <T as GalaxyFormat>::present_styled_with_options(bytes, &options));
+        let success = res.is_ok();
+        let bytes = match res {
+            Ok(styled) => styled.encode(),
+            Err(s) => s.into_bytes(),
+        };
+        alloc_result(bytes, success)
+    }
+
+    pub fn store_with_options<T: GalaxyFormat>(ptr: *mut u8, len: u32) -> *mut ReturnData {
+        // input is options and a utf-8 string, see `options::encode_options_call`
+        let bytes = unsafe { Vec::from_raw_parts(ptr, len as usize, len as usize) };
+
+        let res = options::decode_options_call(&bytes)
+            .and_then(|(options, s)| Ok((options, std::str::from_utf8(s).map_err(|e| e.to_string())?)))
+            .map_err(StoreError::from)
+            .and_then(|(options, s)| <T as GalaxyFormat>::store_located(s, &options));
+        match res {
+            Ok(bytes) => alloc_result(bytes, true),
+            Err(e) => alloc_store_error(e),
+        }
+    }
+
+    pub fn present_window<T: GalaxyFormat>(ptr: *mut u8, len: u32) -> *mut ReturnData {
+        // input is the window's position and a byte slice, see `window::encode_window_call`
+        let bytes = unsafe { Vec::from_raw_parts(ptr, len as usize, len as usize) };
+
+        let res = window::decode_window_call(&bytes)
+            .and_then(|(offset, max_records, chunk)| <T as GalaxyFormat>::present_window(chunk, offset, max_records));
+        let success = res.is_ok();
+        let bytes = match res {
+            Ok(window) => window.encode(),
+            Err(s) => s.into_bytes(),
+        };
+        alloc_result(bytes, success)
+    }
+
+    pub fn present_view<T: GalaxyFormat>(ptr: *mut u8, len: u32) -> *mut ReturnData {
+        // input is the view name and a byte slice, see `view::encode_view_call`
+        let bytes = unsafe { Vec::from_raw_parts(ptr, len as usize, len as usize) };
+
+        let res = view::decode_view_call(&bytes)
+            .and_then(|(view, bytes)| <T as GalaxyFormat>::present_view(view, bytes));
+        let success = res.is_ok();
+        let bytes = match res {
+            Ok(s) => s.into_bytes(),
+            Err(s) => s.into_bytes(),
+        };
+        alloc_result(bytes, success)
+    }
+
+    pub fn store_view<T: GalaxyFormat>(ptr: *mut u8, len: u32) -> *mut ReturnData {
+        // input is the view name and a utf-8 string, see `view::encode_view_call`
+        let bytes = unsafe { Vec::from_raw_parts(ptr, len as usize, len as usize) };
+
+        let res = view::decode_view_call(&bytes)
+            .and_then(|(view, s)| Ok((view, std::str::from_utf8(s).map_err(|e| e.to_string())?)))
+            .and_then(|(view, s)| <T as GalaxyFormat>::store_view(view, s));
         let success = res.is_ok();
         let bytes = match res {
             Ok(bytes) => bytes,
@@ -43,8 +244,26 @@ pub mod __mi {
         };
         alloc_result(bytes, success)
     }
-    
+
+    pub fn sniff<T: GalaxyFormat>(ptr: *mut u8, len: u32) -> u32 {
+        // input is a byte slice
+        let bytes = unsafe { Vec::from_raw_parts(ptr, len as usize, len as usize) };
+        <T as GalaxyFormat>::sniff(&bytes).min(100) as u32
+    }
+
+    pub fn init() {
+        log::install_panic_hook();
+    }
+
     pub fn alloc_result(data: Vec<u8>, success: bool) -> *mut ReturnData {
+        alloc_return(data, success, None)
+    }
+
+    pub fn alloc_store_error(e: StoreError) -> *mut ReturnData {
+        alloc_return(e.message.into_bytes(), false, e.location)
+    }
+
+    fn alloc_return(data: Vec<u8>, success: bool, location: Option<std::ops::Range<usize>>) -> *mut ReturnData {
         // Use `into_raw_parts()` once it's stabilized:
         //     let (ptr, len, capacity) = data.into_raw_parts();
         let mut data = ManuallyDrop::new(data);
@@ -53,22 +272,31 @@ pub mod __mi {
             ptr: ptr as u32, 
             len: len as u32, 
             capacity: capacity as u32, 
-            success
+            success,
+            location: location.as_ref().map_or([0; 2], location::encode_location),
+            has_location: location.is_some(),
         };
         let res_box = Box::new(res_data);
         std::boxed::Box::into_raw(res_box)
     }
 
     pub fn result_get_ptr(ptr: *mut ReturnData) -> u32 {
-        return unsafe { (*ptr).ptr };
+        unsafe { (*ptr).ptr }
     }
 
     pub fn result_get_len(ptr: *mut ReturnData) -> u32 {
-        return unsafe { (*ptr).len };
+        unsafe { (*ptr).len }
     }
 
     pub fn result_get_success(ptr: *mut ReturnData) -> u32 {
-        return unsafe { (*ptr).success as u32 };
+        unsafe { (*ptr).success as u32 }
+    }
+
+    pub fn result_get_location(ptr: *mut ReturnData) -> u32 {
+        // wasm is little endian, so the field already has the layout of an encoded location
+        unsafe {
+            if (*ptr).has_location { &(*ptr).location as *const [u32; 2] as u32 } else { 0 }
+        }
     }
     
     pub fn alloc(n: u32) -> *mut u8 {
@@ -94,9 +322,14 @@ pub mod __mi {
 }
 
 
+/// Exports a `GalaxyFormat` implementation as a V1 module.
+///
+/// Optional features are only exported if they are listed after the type, e.g.
+/// `gen_plugin!{Impl, styled, views}`, so hosts can tell from the exports what a converter
+/// supports: `styled`, `sniff`, `windows`, `views`, `options` and `locations`.
 #[macro_export]
 macro_rules! gen_plugin {
-    ($impl_type:ty) => {
+    ($impl_type:ty $(, $feature:ident)*) => {
         #[cfg(target_arch="wasm32")]
         mod plugin {
             use super::*;
@@ -110,7 +343,12 @@ macro_rules! gen_plugin {
             pub extern "C" fn store(ptr: *mut u8, len: u32) -> *mut format_galaxy_core::__mi::ReturnData {
                 format_galaxy_core::__mi::store::<$impl_type>(ptr, len)
             }
-            
+
+            #[no_mangle]
+            pub extern "C" fn init() {
+                format_galaxy_core::__mi::init()
+            }
+
             #[no_mangle]
             pub extern "C" fn alloc(n: u32) -> *mut u8 {
                 format_galaxy_core::__mi::alloc(n)
@@ -136,6 +374,64 @@ macro_rules! gen_plugin {
                 format_galaxy_core::__mi::result_get_success(ptr)
             }
 
+            $(format_galaxy_core::__gen_feature!{$impl_type, $feature})*
+        }
+    };
+}
+
+/// The exports of an optional feature, see `gen_plugin!`.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __gen_feature {
+    ($impl_type:ty, styled) => {
+        #[no_mangle]
+        pub extern "C" fn present_styled(ptr: *mut u8, len: u32) -> *mut format_galaxy_core::__mi::ReturnData {
+            format_galaxy_core::__mi::present_styled::<$impl_type>(ptr, len)
+        }
+    };
+    ($impl_type:ty, sniff) => {
+        #[no_mangle]
+        pub extern "C" fn sniff(ptr: *mut u8, len: u32) -> u32 {
+            format_galaxy_core::__mi::sniff::<$impl_type>(ptr, len)
         }
     };
-}
\ No newline at end of file
+    ($impl_type:ty, windows) => {
+        #[no_mangle]
+        pub extern "C" fn present_window(ptr: *mut u8, len: u32) -> *mut format_galaxy_core::__mi::ReturnData {
+            format_galaxy_core::__mi::present_window::<$impl_type>(ptr, len)
+        }
+    };
+    ($impl_type:ty, views) => {
+        #[no_mangle]
+        pub extern "C" fn present_view(ptr: *mut u8, len: u32) -> *mut format_galaxy_core::__mi::ReturnData {
+            format_galaxy_core::__mi::present_view::<$impl_type>(ptr, len)
+        }
+
+        #[no_mangle]
+        pub extern "C" fn store_view(ptr: *mut u8, len: u32) -> *mut format_galaxy_core::__mi::ReturnData {
+            format_galaxy_core::__mi::store_view::<$impl_type>(ptr, len)
+        }
+    };
+    ($impl_type:ty, options) => {
+        #[no_mangle]
+        pub extern "C" fn present_with_options(ptr: *mut u8, len: u32) -> *mut format_galaxy_core::__mi::ReturnData {
+            format_galaxy_core::__mi::present_with_options::<$impl_type>(ptr, len)
+        }
+
+        #[no_mangle]
+        pub extern "C" fn present_styled_with_options(ptr: *mut u8, len: u32) -> *mut format_galaxy_core::__mi::ReturnData {
+            format_galaxy_core::__mi::present_styled_with_options::<$impl_type>(ptr, len)
+        }
+
+        #[no_mangle]
+        pub extern "C" fn store_with_options(ptr: *mut u8, len: u32) -> *mut format_galaxy_core::__mi::ReturnData {
+            format_galaxy_core::__mi::store_with_options::<$impl_type>(ptr, len)
+        }
+    };
+    ($impl_type:ty, locations) => {
+        #[no_mangle]
+        pub extern "C" fn result_get_location(ptr: *mut format_galaxy_core::__mi::ReturnData) -> u32 {
+            format_galaxy_core::__mi::result_get_location(ptr)
+        }
+    };
+}
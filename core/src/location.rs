@@ -0,0 +1,73 @@
+/* Store errors with the part of the text they are about
+
+Converters that know where the text they fail to store is wrong report the byte range, so
+hosts can highlight it instead of parsing the message. Across the plugin ABI, the optional
+`result_get_location` export returns 0 for results without a location and otherwise a pointer
+to
+
+    u32 LE offset, u32 LE length
+
+of the range in the stored text.
+*/
+
+use std::ops::Range;
+
+/// Byte length of an encoded location.
+pub const LOCATION_LEN: u32 = 8;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StoreError {
+    pub message: String,
+    /// Byte range of the text the error is about.
+    pub location: Option<Range<usize>>,
+}
+
+impl StoreError {
+    pub fn at(message: String, location: Range<usize>) -> Self {
+        StoreError { message, location: Some(location) }
+    }
+
+    /// The error of a part of the text that starts at `offset`.
+    pub fn shift(self, offset: usize) -> Self {
+        StoreError {
+            message: self.message,
+            location: self.location.map(|location| location.start + offset..location.end + offset),
+        }
+    }
+}
+
+impl From<String> for StoreError {
+    fn from(message: String) -> Self {
+        StoreError { message, location: None }
+    }
+}
+
+pub fn encode_location(location: &Range<usize>) -> [u32; 2] {
+    [location.start as u32, location.len() as u32]
+}
+
+pub fn decode_location(bytes: &[u8]) -> Result<Range<usize>, String> {
+    let field = |i: usize| bytes.get(i * 4..i * 4 + 4)
+        .map(|b| u32::from_le_bytes(b.try_into().unwrap()) as usize)
+        .ok_or_else(|| "Location is truncated".to_string());
+    let offset = field(0)?;
+    Ok(offset..offset + field(1)?)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        let bytes: Vec<u8> = encode_location(&(3..7)).iter().flat_map(|x| x.to_le_bytes()).collect();
+        assert_eq!(bytes.len(), LOCATION_LEN as usize);
+        assert_eq!(decode_location(&bytes), Ok(3..7));
+        assert!(decode_location(&bytes[..6]).is_err());
+
+        let err = StoreError::at("Unexpected input".to_string(), 1..2).shift(10);
+        assert_eq!(err.location, Some(11..12));
+        assert_eq!(StoreError::from("no location".to_string()).shift(10).location, None);
+    }
+}
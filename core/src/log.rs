@@ -0,0 +1,147 @@
+/* Logging from converters and reporting of panics
+
+Converters compiled to wasm can't write to stderr, so they import these functions from the
+host's `fg` module instead:
+
+    log(level: u32, ptr: u32, len: u32)    a message (utf-8) with its `Level`
+    panic(ptr: u32, len: u32)              message and location of a panic, right before the trap
+
+Components import the same functions from the `host` interface of the converter world, see
+`wit/converter.wit`. Hosts decide which levels are shown. When converters run natively,
+messages are written to stderr, filtered by the level set with `set_max_level`.
+*/
+
+use std::sync::atomic::{AtomicU8, Ordering};
+
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Level {
+    Error = 1,
+    Warn = 2,
+    Info = 3,
+    Debug = 4,
+}
+
+impl Level {
+    pub fn from_u32(n: u32) -> Option<Level> {
+        use Level::*;
+        Some(match n {
+            1 => Error,
+            2 => Warn,
+            3 => Info,
+            4 => Debug,
+            _ => return None,
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        use Level::*;
+        match self {
+            Error => "error",
+            Warn => "warn",
+            Info => "info",
+            Debug => "debug",
+        }
+    }
+
+    /// Parses a verbosity like `info`, the most verbose level that is shown.
+    ///
+    /// `off` disables logging and returns `None`.
+    pub fn parse_max(s: &str) -> Result<Option<Level>, String> {
+        use Level::*;
+        match s.trim().to_ascii_lowercase().as_str() {
+            "off" => Ok(None),
+            "error" => Ok(Some(Error)),
+            "warn" => Ok(Some(Warn)),
+            "info" => Ok(Some(Info)),
+            "debug" => Ok(Some(Debug)),
+            _ => Err(format!("Unknown log level '{}', expected off, error, warn, info or debug", s)),
+        }
+    }
+}
+
+/// Logs a message with `format!` arguments, e.g. `log!(Warn, "skipped {} bytes", n)`.
+#[macro_export]
+macro_rules! log {
+    ($level:ident, $($arg:tt)+) => {
+        $crate::log::log($crate::log::Level::$level, &format!($($arg)+))
+    };
+}
+
+#[cfg(all(target_arch = "wasm32", not(feature = "component")))]
+mod imports {
+    #[link(wasm_import_module = "fg")]
+    extern "C" {
+        pub fn log(level: u32, ptr: *const u8, len: u32);
+        pub fn panic(ptr: *const u8, len: u32);
+    }
+}
+
+// only used natively, wasm hosts filter messages themselves
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(Level::Warn as u8);
+
+/// Sets the most verbose level written to stderr by natively running converters.
+pub fn set_max_level(level: Option<Level>) {
+    MAX_LEVEL.store(level.map(|l| l as u8).unwrap_or(0), Ordering::Relaxed);
+}
+
+pub fn log(level: Level, msg: &str) {
+    #[cfg(all(target_arch = "wasm32", feature = "component"))]
+    crate::component::host::log(level.into(), msg);
+    #[cfg(all(target_arch = "wasm32", not(feature = "component")))]
+    unsafe { imports::log(level as u32, msg.as_ptr(), msg.len() as u32) }
+    #[cfg(not(target_arch = "wasm32"))]
+    if level as u8 <= MAX_LEVEL.load(Ordering::Relaxed) {
+        eprintln!("{}", format_message(level, msg));
+    }
+}
+
+/// How hosts show logged messages.
+pub fn format_message(level: Level, msg: &str) -> String {
+    format!("[converter {}] {}", level.name(), msg)
+}
+
+/// Message and location of a panic, e.g. `index out of bounds at src/lib.rs:10:5`.
+pub fn panic_message(info: &std::panic::PanicHookInfo) -> String {
+    let payload = info.payload();
+    let msg = payload.downcast_ref::<&str>().map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string());
+    match info.location() {
+        Some(location) => format!("{} at {}:{}:{}", msg, location.file(), location.line(), location.column()),
+        None => msg,
+    }
+}
+
+/// Installs a panic hook passing panics on to the host.
+///
+/// Called by the `init` export generated by `gen_plugin!`, hosts call it after instantiation.
+/// Components install it on their first call.
+pub fn install_panic_hook() {
+    #[cfg(target_arch = "wasm32")]
+    std::panic::set_hook(Box::new(|info| {
+        let msg = panic_message(info);
+        #[cfg(feature = "component")]
+        crate::component::host::panic(&msg);
+        #[cfg(not(feature = "component"))]
+        unsafe { imports::panic(msg.as_ptr(), msg.len() as u32) }
+    }));
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_levels() {
+        for level in [Level::Error, Level::Warn, Level::Info, Level::Debug] {
+            assert_eq!(Level::from_u32(level as u32), Some(level));
+            assert_eq!(Level::parse_max(level.name()), Ok(Some(level)));
+        }
+        assert_eq!(Level::from_u32(0), None);
+        assert_eq!(Level::parse_max("OFF"), Ok(None));
+        assert!(Level::parse_max("verbose").is_err());
+        assert!(Level::Debug > Level::Warn);
+    }
+}
@@ -0,0 +1,162 @@
+/* Option values passed to converters, e.g. the indentation width of a printer
+
+Converters declare the options they understand in the index, hosts check values against that
+schema before passing them on. Converters only see the values that were set and apply their
+own defaults for the others. Across the plugin ABI, options are encoded as lines of
+`key=value` in front of the payload, with the same layout as view calls:
+
+    u32 LE length of the options text, options text (utf-8), payload
+*/
+
+use std::ops::RangeInclusive;
+
+use crate::view::{decode_view_call, encode_view_call};
+
+/// Error of converters and hosts for option values passed to converters without options.
+pub const OPTIONS_UNSUPPORTED: &str = "The converter doesn't support options";
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Options {
+    values: Vec<(String, String)>,
+}
+
+impl Options {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets `key` to `value`, replacing an earlier value.
+    ///
+    /// Keys must not contain `=` and neither keys nor values may contain line breaks.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        if key.is_empty() || key.contains(['=', '\n']) || value.contains('\n') {
+            return Err(format!("Invalid option '{}={}'", key, value));
+        }
+        match self.values.iter_mut().find(|(k, _v)| k == key) {
+            Some((_k, v)) => *v = value.to_string(),
+            None => self.values.push((key.to_string(), value.to_string())),
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.iter().find(|(k, _v)| k == key).map(|(_k, v)| v.as_str())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.values.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn bool(&self, key: &str, default: bool) -> Result<bool, String> {
+        match self.get(key) {
+            None => Ok(default),
+            Some("true") => Ok(true),
+            Some("false") => Ok(false),
+            Some(v) => Err(format!("Option '{}' must be true or false, not '{}'", key, v)),
+        }
+    }
+
+    pub fn int(&self, key: &str, default: i64) -> Result<i64, String> {
+        match self.get(key) {
+            None => Ok(default),
+            Some(v) => v.parse().map_err(|_| format!("Option '{}' must be an integer, not '{}'", key, v)),
+        }
+    }
+
+    /// Like `int`, for options with `min` and `max` in the index.
+    pub fn int_in(&self, key: &str, default: i64, range: RangeInclusive<i64>) -> Result<i64, String> {
+        let n = self.int(key, default)?;
+        if !range.contains(&n) {
+            return Err(format!("Option '{}' must be in the range {}..={}, not {}", key, range.start(), range.end(), n));
+        }
+        Ok(n)
+    }
+
+    pub fn str<'a>(&'a self, key: &str, default: &'a str) -> &'a str {
+        self.get(key).unwrap_or(default)
+    }
+
+    pub fn encode(&self) -> String {
+        self.values.iter().map(|(k, v)| format!("{}={}\n", k, v)).collect()
+    }
+
+    pub fn decode(s: &str) -> Result<Self, String> {
+        let mut options = Options::new();
+        for line in s.lines() {
+            let (key, value) = line.split_once('=')
+                .ok_or_else(|| format!("Invalid option '{}'", line))?;
+            options.set(key, value)?;
+        }
+        Ok(options)
+    }
+}
+
+pub fn encode_options_call(options: &Options, payload: &[u8]) -> Vec<u8> {
+    encode_view_call(&options.encode(), payload)
+}
+
+pub fn decode_options_call(bytes: &[u8]) -> Result<(Options, &[u8]), String> {
+    let (options, payload) = decode_view_call(bytes)?;
+    Ok((Options::decode(options)?, payload))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_options() {
+        let mut options = Options::new();
+        options.set("indent", "4").unwrap();
+        options.set("sort_keys", "true").unwrap();
+        options.set("mode", "a=b").unwrap();
+        options.set("indent", "3").unwrap();
+        assert!(options.set("a=b", "c").is_err());
+        assert!(options.set("a", "b\nc").is_err());
+
+        assert_eq!(options.int("indent", 2), Ok(3));
+        assert_eq!(options.int("width", 80), Ok(80));
+        assert_eq!(options.bool("sort_keys", false), Ok(true));
+        assert!(options.int("sort_keys", 0).is_err());
+        assert_eq!(options.int_in("indent", 2, 0..=16), Ok(3));
+        assert_eq!(options.int_in("indent", 2, 4..=16), Err("Option 'indent' must be in the range 4..=16, not 3".to_string()));
+        assert!(options.bool("indent", false).is_err());
+        assert_eq!(options.str("mode", ""), "a=b");
+
+        let bytes = encode_options_call(&options, &[1, 2]);
+        assert_eq!(decode_options_call(&bytes), Ok((options, &[1u8, 2][..])));
+        assert_eq!(decode_options_call(&encode_options_call(&Options::new(), &[])), Ok((Options::new(), &[][..])));
+        assert!(Options::decode("no value").is_err());
+    }
+
+    #[test]
+    fn test_unsupported() {
+        use crate::GalaxyFormat;
+
+        struct NoOptions;
+
+        impl GalaxyFormat for NoOptions {
+            fn present(_bytes: &[u8]) -> Result<String, String> {
+                Ok("text".to_string())
+            }
+
+            fn store(_s: &str) -> Result<Vec<u8>, String> {
+                Ok(vec!())
+            }
+        }
+
+        // converters without options refuse them, like hosts do for plugins without them
+        let mut options = Options::new();
+        assert_eq!(NoOptions::present_with_options(&[], &options), Ok("text".to_string()));
+        options.set("indent", "4").unwrap();
+        let unsupported = Err(OPTIONS_UNSUPPORTED.to_string());
+        assert_eq!(NoOptions::present_with_options(&[], &options), unsupported);
+        assert_eq!(NoOptions::present_styled_with_options(&[], &options).map(|s| s.text), unsupported);
+        assert_eq!(NoOptions::store_with_options("", &options), Err(OPTIONS_UNSUPPORTED.to_string()));
+    }
+}
@@ -0,0 +1,188 @@
+/* Presented text with styled spans, used by hosts for syntax highlighting
+
+Spans mark byte ranges of the text with the kind of token they contain. Text outside of
+any span is plain. Across the plugin ABI, styled text is encoded as
+
+    u32 LE text length, text (utf-8), then per span: u32 LE start, u32 LE end, u8 style
+*/
+
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Style {
+    Plain = 0,
+    Key = 1,
+    String = 2,
+    Number = 3,
+    /// Keywords and constants like `true` or `null`
+    Literal = 4,
+    Punctuation = 5,
+    Comment = 6,
+}
+
+impl Style {
+    pub fn from_u8(n: u8) -> Option<Style> {
+        use Style::*;
+        Some(match n {
+            0 => Plain,
+            1 => Key,
+            2 => String,
+            3 => Number,
+            4 => Literal,
+            5 => Punctuation,
+            6 => Comment,
+            _ => return None,
+        })
+    }
+
+    /// Lowercase name of the style, e.g. for CSS classes.
+    pub fn name(&self) -> &'static str {
+        use Style::*;
+        match self {
+            Plain => "plain",
+            Key => "key",
+            String => "string",
+            Number => "number",
+            Literal => "literal",
+            Punctuation => "punctuation",
+            Comment => "comment",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub style: Style,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StyledText {
+    pub text: String,
+    /// Sorted and non-overlapping.
+    pub spans: Vec<Span>,
+}
+
+impl StyledText {
+    pub fn plain(text: String) -> Self {
+        StyledText {
+            text,
+            spans: vec!(),
+        }
+    }
+
+    /// Appends `s` with the given style.
+    pub fn push(&mut self, s: &str, style: Style) {
+        let start = self.text.len();
+        self.text.push_str(s);
+        if style != Style::Plain && !s.is_empty() {
+            self.spans.push(Span {
+                start,
+                end: self.text.len(),
+                style,
+            });
+        }
+    }
+
+    /// Splits the text into consecutive segments, covering all of it.
+    pub fn segments(&self) -> Vec<(&str, Style)> {
+        let mut segments = vec!();
+        let mut pos = 0;
+        for span in &self.spans {
+            if span.start > pos {
+                segments.push((&self.text[pos..span.start], Style::Plain));
+            }
+            segments.push((&self.text[span.start..span.end], span.style));
+            pos = span.end;
+        }
+        if pos < self.text.len() {
+            segments.push((&self.text[pos..], Style::Plain));
+        }
+        segments
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(4 + self.text.len() + 9 * self.spans.len());
+        bytes.extend_from_slice(&(self.text.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(self.text.as_bytes());
+        for span in &self.spans {
+            bytes.extend_from_slice(&(span.start as u32).to_le_bytes());
+            bytes.extend_from_slice(&(span.end as u32).to_le_bytes());
+            bytes.push(span.style as u8);
+        }
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, String> {
+        fn read_u32(bytes: &[u8], pos: usize) -> Result<usize, String> {
+            bytes.get(pos..pos + 4)
+                .map(|b| u32::from_le_bytes(b.try_into().unwrap()) as usize)
+                .ok_or_else(|| "Styled text is truncated".to_string())
+        }
+
+        let len = read_u32(bytes, 0)?;
+        let text = bytes.get(4..).and_then(|b| b.get(..len))
+            .ok_or_else(|| "Styled text is truncated".to_string())?;
+        let text = String::from_utf8(text.to_vec()).map_err(|e| e.to_string())?;
+
+        let rest = &bytes[4 + len..];
+        if !rest.len().is_multiple_of(9) {
+            return Err("Styled text is truncated".to_string());
+        }
+        let mut spans = vec!();
+        for chunk in rest.chunks(9) {
+            spans.push(Span {
+                start: read_u32(chunk, 0)?,
+                end: read_u32(chunk, 4)?,
+                style: Style::from_u8(chunk[8]).ok_or_else(|| format!("Unknown style {}", chunk[8]))?,
+            });
+        }
+        let styled = StyledText { text, spans };
+        styled.validate()?;
+        Ok(styled)
+    }
+
+    /// Checks that the spans are sorted, don't overlap and don't split characters, which
+    /// `segments` relies on. Hosts check styled text they get from converters.
+    pub fn validate(&self) -> Result<(), String> {
+        let mut pos = 0;
+        for span in &self.spans {
+            if span.start < pos || span.end < span.start || !self.text.is_char_boundary(span.start) || !self.text.is_char_boundary(span.end) {
+                return Err(format!("Invalid span {}..{}", span.start, span.end));
+            }
+            pos = span.end;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        let mut t = StyledText::default();
+        t.push("{", Style::Punctuation);
+        t.push("\"ä\"", Style::Key);
+        t.push(": ", Style::Plain);
+        t.push("1", Style::Number);
+        assert_eq!(t.segments(), vec!(("{", Style::Punctuation), ("\"ä\"", Style::Key), (": ", Style::Plain), ("1", Style::Number)));
+        assert_eq!(StyledText::decode(&t.encode()), Ok(t.clone()));
+
+        let mut bytes = t.encode();
+        bytes.pop();
+        assert!(StyledText::decode(&bytes).is_err());
+
+        // spans must not split characters
+        let bad = StyledText { text: "ä".to_string(), spans: vec!(Span { start: 0, end: 1, style: Style::Key }) };
+        assert!(StyledText::decode(&bad.encode()).is_err());
+        let unsorted = StyledText { text: "ab".to_string(), spans: vec!(
+            Span { start: 1, end: 2, style: Style::Key },
+            Span { start: 0, end: 1, style: Style::Key },
+        ) };
+        assert_eq!(unsorted.validate(), Err("Invalid span 0..1".to_string()));
+        assert!(StyledText { text: "ab".to_string(), spans: vec!(Span { start: 2, end: 3, style: Style::Key }) }.validate().is_err());
+    }
+}
@@ -0,0 +1,140 @@
+/* Property-based checks for `GalaxyFormat` implementations
+
+These run natively (outside of wasm) and are meant to be called from the
+unit tests of a converter. Enable the `testing` feature to use them.
+*/
+
+use crate::GalaxyFormat;
+use crate::options::Options;
+
+pub use proptest;
+use proptest::prelude::*;
+use proptest::test_runner::{Config, TestError, TestRunner};
+
+/// Checks the round-trip invariants of `T` for every byte sequence generated by `strategy`:
+///
+/// - `present` succeeds for valid input
+/// - `store` turns the presented text back into the original bytes
+/// - presenting the stored bytes again yields the same text
+///
+/// `strategy` must only generate bytes that are valid in the binary domain of the format.
+pub fn check_round_trip<T, S>(strategy: S) -> Result<(), TestError<Vec<u8>>>
+where
+    T: GalaxyFormat,
+    S: Strategy<Value = Vec<u8>>,
+{
+    check_round_trip_with_config::<T, S>(Config::default(), strategy)
+}
+
+/// Same as `check_round_trip`, but with a custom proptest configuration (e.g. number of cases).
+pub fn check_round_trip_with_config<T, S>(config: Config, strategy: S) -> Result<(), TestError<Vec<u8>>>
+where
+    T: GalaxyFormat,
+    S: Strategy<Value = Vec<u8>>,
+{
+    run_round_trip(config, strategy, T::present, T::store)
+}
+
+/// Same as `check_round_trip`, but presenting and storing with `options`.
+pub fn check_round_trip_with_options<T, S>(options: &Options, strategy: S) -> Result<(), TestError<Vec<u8>>>
+where
+    T: GalaxyFormat,
+    S: Strategy<Value = Vec<u8>>,
+{
+    run_round_trip(
+        Config::default(),
+        strategy,
+        |bytes| T::present_with_options(bytes, options),
+        |s| T::store_with_options(s, options),
+    )
+}
+
+fn run_round_trip<S, P, St>(config: Config, strategy: S, present: P, store: St) -> Result<(), TestError<Vec<u8>>>
+where
+    S: Strategy<Value = Vec<u8>>,
+    P: Fn(&[u8]) -> Result<String, String>,
+    St: Fn(&str) -> Result<Vec<u8>, String>,
+{
+    let mut runner = TestRunner::new(config);
+    runner.run(&strategy, |bytes| {
+        let s = present(&bytes)
+            .map_err(|e| TestCaseError::fail(format!("present failed: {}", e)))?;
+        let stored = store(&s)
+            .map_err(|e| TestCaseError::fail(format!("store failed for {:?}: {}", s, e)))?;
+        prop_assert_eq!(&stored, &bytes, "store(present(bytes)) != bytes");
+        let s2 = present(&stored)
+            .map_err(|e| TestCaseError::fail(format!("present failed on stored bytes: {}", e)))?;
+        prop_assert_eq!(s2, s, "present(store(s)) != s");
+        Ok(())
+    })
+}
+
+/// Checks that `present` and `store` of `T` never panic, no matter what input they get.
+///
+/// Arbitrary input is usually invalid, so errors are expected and ignored here. If
+/// `store` succeeds however, presenting the result has to succeed as well.
+pub fn check_arbitrary_input<T: GalaxyFormat>() -> Result<(), TestError<(Vec<u8>, String)>> {
+    let mut runner = TestRunner::default();
+    runner.run(&(any::<Vec<u8>>(), any::<String>()), |(bytes, s)| {
+        let _ = T::present(&bytes);
+        if let Ok(stored) = T::store(&s) {
+            if let Err(e) = T::present(&stored) {
+                return Err(TestCaseError::fail(format!("stored bytes could not be presented: {}", e)));
+            }
+        }
+        Ok(())
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Hex {}
+
+    impl GalaxyFormat for Hex {
+        fn present(bytes: &[u8]) -> Result<String, String> {
+            Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+        }
+
+        fn store(s: &str) -> Result<Vec<u8>, String> {
+            if !s.len().is_multiple_of(2) || !s.is_ascii() {
+                return Err("Invalid hex string".to_string());
+            }
+            (0..s.len()).step_by(2)
+                .map(|i| u8::from_str_radix(&s[i..i+2], 16).map_err(|e| e.to_string()))
+                .collect()
+        }
+    }
+
+    // drops the last byte on store
+    struct Lossy {}
+
+    impl GalaxyFormat for Lossy {
+        fn present(bytes: &[u8]) -> Result<String, String> {
+            Hex::present(bytes)
+        }
+
+        fn store(s: &str) -> Result<Vec<u8>, String> {
+            let mut v = Hex::store(s)?;
+            v.pop();
+            Ok(v)
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        check_round_trip::<Hex, _>(any::<Vec<u8>>()).unwrap();
+        check_arbitrary_input::<Hex>().unwrap();
+    }
+
+    #[test]
+    fn test_round_trip_detects_loss() {
+        let res = check_round_trip::<Lossy, _>(proptest::collection::vec(any::<u8>(), 1..16));
+        match res {
+            Err(TestError::Fail(_, bytes)) => assert_eq!(bytes.len(), 1),  // minimal failing input after shrinking
+            other => panic!("Expected round trip to fail, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,40 @@
+/* Calls of named views across the plugin ABI
+
+`present_view` and `store_view` take a single buffer holding both the view name and the
+payload (the bytes to present or the string to store):
+
+    u32 LE name length, name (utf-8), payload
+*/
+
+pub fn encode_view_call(view: &str, payload: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(4 + view.len() + payload.len());
+    bytes.extend_from_slice(&(view.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(view.as_bytes());
+    bytes.extend_from_slice(payload);
+    bytes
+}
+
+pub fn decode_view_call(bytes: &[u8]) -> Result<(&str, &[u8]), String> {
+    let len = bytes.get(..4)
+        .map(|b| u32::from_le_bytes(b.try_into().unwrap()) as usize)
+        .ok_or_else(|| "View call is truncated".to_string())?;
+    let name = bytes.get(4..).and_then(|b| b.get(..len))
+        .ok_or_else(|| "View call is truncated".to_string())?;
+    let name = std::str::from_utf8(name).map_err(|e| e.to_string())?;
+    Ok((name, &bytes[4 + len..]))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        let bytes = encode_view_call("summary", &[1, 2, 3]);
+        assert_eq!(decode_view_call(&bytes), Ok(("summary", &[1u8, 2, 3][..])));
+        assert_eq!(decode_view_call(&encode_view_call("", &[])), Ok(("", &[][..])));
+        assert!(decode_view_call(&bytes[..6]).is_err());
+        assert!(decode_view_call(&[1, 0]).is_err());
+    }
+}
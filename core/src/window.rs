@@ -0,0 +1,78 @@
+/* Presenting a window of records instead of the whole file
+
+Hosts pass a chunk of the file together with the offset of its first byte. The converter
+presents up to a maximum number of complete records from the start of the chunk and returns
+the offset where the next window starts, the continuation token. Across the plugin ABI, calls
+and results are encoded as
+
+    call:   u64 LE offset, u32 LE max records, chunk
+    result: u64 LE offset of the next window, u32 LE number of records, text (utf-8)
+*/
+
+/// Error of converters and hosts for windows of converters that aren't `windowed`.
+pub const WINDOWS_UNSUPPORTED: &str = "The converter can't present parts of a file";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Window {
+    pub text: String,
+    /// Number of records presented, at most the requested maximum.
+    pub records: u32,
+    /// Offset of the first record that wasn't presented.
+    ///
+    /// Equal to the offset of the chunk if not even one record fits into it.
+    pub next: u64,
+}
+
+impl Window {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(12 + self.text.len());
+        bytes.extend_from_slice(&self.next.to_le_bytes());
+        bytes.extend_from_slice(&self.records.to_le_bytes());
+        bytes.extend_from_slice(self.text.as_bytes());
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() < 12 {
+            return Err("Window is truncated".to_string());
+        }
+        let next = u64::from_le_bytes(bytes[..8].try_into().unwrap());
+        let records = u32::from_le_bytes(bytes[8..12].try_into().unwrap());
+        let text = String::from_utf8(bytes[12..].to_vec()).map_err(|e| e.to_string())?;
+        Ok(Window { text, records, next })
+    }
+}
+
+pub fn encode_window_call(offset: u64, max_records: u32, chunk: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(12 + chunk.len());
+    bytes.extend_from_slice(&offset.to_le_bytes());
+    bytes.extend_from_slice(&max_records.to_le_bytes());
+    bytes.extend_from_slice(chunk);
+    bytes
+}
+
+pub fn decode_window_call(bytes: &[u8]) -> Result<(u64, u32, &[u8]), String> {
+    if bytes.len() < 12 {
+        return Err("Window call is truncated".to_string());
+    }
+    let offset = u64::from_le_bytes(bytes[..8].try_into().unwrap());
+    let max_records = u32::from_le_bytes(bytes[8..12].try_into().unwrap());
+    Ok((offset, max_records, &bytes[12..]))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        let window = Window { text: "ä".to_string(), records: 3, next: 1 << 40 };
+        assert_eq!(Window::decode(&window.encode()), Ok(window));
+        assert!(Window::decode(&[0; 11]).is_err());
+
+        let bytes = encode_window_call(16, 3, &[1, 2]);
+        assert_eq!(decode_window_call(&bytes), Ok((16, 3, &[1u8, 2][..])));
+        assert!(decode_window_call(&bytes[..11]).is_err());
+    }
+}
@@ -0,0 +1,192 @@
+/* The container format of format galaxy files (`.fg`), see `docs/FileContainerFormat.md`
+
+    prelude:    b"FMTGALv" followed by the version as an ascii digit, e.g. b"FMTGALv1"
+    format id:  u64 LE
+    payload:    the bytes of the file in its format
+
+Works on byte slices, needs nothing but `alloc` and has no dependencies, so that the host tools,
+the web host and converters reading nested containers all parse containers the same way.
+*/
+
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::fmt;
+
+/// The start of every prelude, the version follows it.
+pub const MAGIC: &[u8; 7] = b"FMTGALv";
+
+/// Prelude of containers written with the current version.
+pub const PRELUDE: &[u8; 8] = b"FMTGALv1";
+
+/// Version of the container format written by `encode`, as encoded in the prelude.
+pub const VERSION: u32 = 1;
+
+/// Size of the header (prelude and format id) in front of the payload.
+pub const HEADER_LEN: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ContainerError {
+    /// Fewer bytes than a header.
+    Truncated,
+    /// The bytes don't start with `MAGIC`.
+    InvalidPrelude,
+    /// A prelude of a version this crate can't read (newer or not a digit).
+    UnsupportedVersion(u8),
+    /// A header of a version that can't be written (not from 1 to `VERSION`).
+    InvalidVersion(u32),
+}
+
+impl fmt::Display for ContainerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ContainerError::Truncated => write!(f, "The file is too short for a container header"),
+            ContainerError::InvalidPrelude => write!(f, "Invalid prelude, the file isn't a container"),
+            ContainerError::UnsupportedVersion(v) if v.is_ascii_digit() =>
+                write!(f, "Unsupported container version {}, the newest supported version is {}", *v as char, VERSION),
+            ContainerError::UnsupportedVersion(v) => write!(f, "Invalid container version byte {:#04x}", v),
+            ContainerError::InvalidVersion(v) => write!(f, "Invalid container version {}, versions go from 1 to {}", v, VERSION),
+        }
+    }
+}
+
+impl core::error::Error for ContainerError {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Header {
+    // a single digit in the prelude, always from 1 to `VERSION`
+    version: u32,
+    pub format_id: u64,
+}
+
+impl Header {
+    /// Header of the current version.
+    pub fn new(format_id: u64) -> Self {
+        Header { version: VERSION, format_id }
+    }
+
+    /// Header of an older version, e.g. for tools that write containers for older readers.
+    pub fn with_version(version: u32, format_id: u64) -> Result<Self, ContainerError> {
+        if !(1..=VERSION).contains(&version) {
+            return Err(ContainerError::InvalidVersion(version));
+        }
+        Ok(Header { version, format_id })
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn encode(&self) -> [u8; HEADER_LEN] {
+        let mut header = [0u8; HEADER_LEN];
+        header[..MAGIC.len()].copy_from_slice(MAGIC);
+        // versions are single digits
+        header[MAGIC.len()] = b'0' + self.version as u8;
+        header[PRELUDE.len()..].copy_from_slice(&self.format_id.to_le_bytes());
+        header
+    }
+
+    /// Parses and validates the header at the start of `bytes`, which may contain more than it.
+    pub fn decode(bytes: &[u8]) -> Result<Self, ContainerError> {
+        let magic_len = MAGIC.len().min(bytes.len());
+        if bytes[..magic_len] != MAGIC[..magic_len] {
+            return Err(ContainerError::InvalidPrelude);
+        }
+        if bytes.len() < HEADER_LEN {
+            return Err(ContainerError::Truncated);
+        }
+        let version = match bytes[MAGIC.len()] {
+            v @ b'1'..=b'9' if (v - b'0') as u32 <= VERSION => (v - b'0') as u32,
+            v => return Err(ContainerError::UnsupportedVersion(v)),
+        };
+        let mut format_id = [0u8; 8];
+        format_id.copy_from_slice(&bytes[PRELUDE.len()..HEADER_LEN]);
+        Ok(Header {
+            version,
+            format_id: u64::from_le_bytes(format_id),
+        })
+    }
+}
+
+/// A decoded container, borrowing its payload.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Container<'a> {
+    pub header: Header,
+    pub payload: &'a [u8],
+}
+
+impl<'a> Container<'a> {
+    pub fn decode(bytes: &'a [u8]) -> Result<Self, ContainerError> {
+        let header = Header::decode(bytes)?;
+        Ok(Container { header, payload: &bytes[HEADER_LEN..] })
+    }
+
+    pub fn format_id(&self) -> u64 {
+        self.header.format_id
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_LEN + self.payload.len());
+        bytes.extend_from_slice(&self.header.encode());
+        bytes.extend_from_slice(self.payload);
+        bytes
+    }
+}
+
+/// Whether `bytes` start like a container (of any version), so that they should be decoded
+/// rather than treated as a raw file.
+pub fn is_container(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// A container of the current version holding `payload` in the format `format_id`.
+pub fn encode(format_id: u64, payload: &[u8]) -> Vec<u8> {
+    Container { header: Header::new(format_id), payload }.encode()
+}
+
+/// Format id and payload of a container.
+pub fn decode(bytes: &[u8]) -> Result<(u64, &[u8]), ContainerError> {
+    let container = Container::decode(bytes)?;
+    Ok((container.format_id(), container.payload))
+}
+
+
+#[test]
+fn test_container() {
+    let bytes = encode(300, &[1, 2, 3]);
+    assert_eq!(&bytes[..8], PRELUDE);
+    assert_eq!(decode(&bytes), Ok((300, &[1, 2, 3][..])));
+    assert_eq!(decode(&bytes[..HEADER_LEN]), Ok((300, &[][..])));
+    assert_eq!(Header::decode(&bytes), Ok(Header::new(300)));
+    assert_eq!(Header::decode(&bytes).unwrap().version(), 1);
+
+    // nested containers
+    let nested = encode(5, &bytes);
+    let (format_id, payload) = decode(&nested).unwrap();
+    assert_eq!((format_id, decode(payload)), (5, Ok((300, &[1, 2, 3][..]))));
+}
+
+#[test]
+fn test_validation() {
+    let bytes = encode(300, &[1, 2, 3]);
+    assert_eq!(decode(&bytes[..HEADER_LEN - 1]), Err(ContainerError::Truncated));
+    assert_eq!(decode(&bytes[..4]), Err(ContainerError::Truncated));
+    assert_eq!(decode(b"FMTX"), Err(ContainerError::InvalidPrelude));
+    assert_eq!(decode(b"{\"a\": 1}"), Err(ContainerError::InvalidPrelude));
+    assert!(!is_container(b"{\"a\": 1}"));
+
+    // containers of other versions are recognized, but can't be read
+    let v2 = b"FMTGALv2\x05\0\0\0\0\0\0\0";
+    assert!(is_container(v2));
+    assert_eq!(decode(v2), Err(ContainerError::UnsupportedVersion(b'2')));
+    assert_eq!(decode(b"FMTGALv0\x05\0\0\0\0\0\0\0"), Err(ContainerError::UnsupportedVersion(b'0')));
+    assert_eq!(decode(b"FMTGALvx\x05\0\0\0\0\0\0\0"), Err(ContainerError::UnsupportedVersion(b'x')));
+    assert!(ContainerError::UnsupportedVersion(b'2').to_string().contains("version 2"));
+
+    // only versions that fit into the prelude can be written
+    assert_eq!(Header::with_version(1, 5).map(|h| h.encode()), Ok(Header::new(5).encode()));
+    assert_eq!(Header::with_version(0, 5), Err(ContainerError::InvalidVersion(0)));
+    assert_eq!(Header::with_version(10, 5), Err(ContainerError::InvalidVersion(10)));
+}
@@ -1,7 +1,7 @@
 
 use std::collections::HashMap;
 use serde::{Deserialize, Serialize};
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Copy, Debug)]
 pub struct FormatId(pub u64);
@@ -9,7 +9,11 @@ pub struct FormatId(pub u64);
 #[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Copy, Debug)]
 pub struct ConverterId(pub u64);
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+/// Hosts offer a converter that works for any format under this id (e.g. a hex dump), so
+/// indexes can't use it.
+pub const RESERVED_CONVERTER_ID: ConverterId = ConverterId(0);
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub struct ConverterHash(pub String); // should be an IPFS Multihash
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -23,22 +27,225 @@ pub struct FileFormat {
     pub desc: String, // should be valid markdown
     pub extensions: Vec<String>, // list of possible file extensions for that file type
     pub converters: HashMap<ConverterId, Converter>,
+    #[serde(default)]
+    pub detect: Detection, // how to recognize files of this format outside of the container format
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Detection {
+    #[serde(default)]
+    pub magic: Vec<Magic>, // the file is of this format if any of these match
+    #[serde(default)]
+    pub sniff: Option<ConverterId>, // converter whose `sniff` function rates how likely a file is of this format
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Magic {
+    #[serde(default)]
+    pub offset: usize,
+    pub bytes: String, // hex encoded, e.g. "0061736d"
+}
+
+impl Magic {
+    pub fn decode(&self) -> Option<Vec<u8>> {
+        if !self.bytes.len().is_multiple_of(2) || !self.bytes.is_ascii() {
+            return None;
+        }
+        (0..self.bytes.len()).step_by(2)
+            .map(|i| u8::from_str_radix(&self.bytes[i..i+2], 16).ok())
+            .collect()
+    }
+
+    pub fn matches(&self, data: &[u8]) -> bool {
+        match self.decode() {
+            Some(magic) => data.get(self.offset..).is_some_and(|data| data.starts_with(&magic)),
+            None => false,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Converter {
     pub name: String,
     pub desc: String, // should be valid markdown
-    pub versions: Vec<(String, ConverterHash)>,  // version string (e.g. "1.0.0-alpha") and hash of the wasm module
+    pub versions: Vec<Version>,
+    /// Named views besides the default one, e.g. a summary (see `present_view` in the plugin ABI).
+    #[serde(default)]
+    pub views: Vec<String>,
+    /// Whether the converter can present parts of a file (see `present_window` in the plugin ABI).
+    #[serde(default)]
+    pub windowed: bool,
+    /// Capabilities of the sandboxed WASI environment the converter needs, none by default.
+    ///
+    /// Hosts refuse converters that import WASI functions of capabilities not listed here.
+    #[serde(default)]
+    pub wasi: Vec<WasiCapability>,
+}
+
+/// A published build of a converter.
+///
+/// In the index, versions without options are `["1.0.0", "<hash>"]` and versions with options
+/// are objects with a `version`, `hash` and `options`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(from = "VersionEntry", into = "VersionEntry")]
+pub struct Version {
+    pub version: String, // e.g. "1.0.0-alpha"
+    pub hash: ConverterHash, // hash of the wasm module
+    /// Options hosts may pass to this build (see `present_with_options` in the plugin ABI).
+    ///
+    /// They are declared per version, builds from before a converter had options don't export
+    /// the `_with_options` functions.
+    pub options: Vec<ConverterOption>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum VersionEntry {
+    Plain(String, ConverterHash),
+    WithOptions {
+        version: String,
+        hash: ConverterHash,
+        #[serde(default)]
+        options: Vec<ConverterOption>,
+    },
+}
+
+impl From<VersionEntry> for Version {
+    fn from(entry: VersionEntry) -> Self {
+        match entry {
+            VersionEntry::Plain(version, hash) => Version { version, hash, options: vec!() },
+            VersionEntry::WithOptions { version, hash, options } => Version { version, hash, options },
+        }
+    }
+}
+
+impl From<Version> for VersionEntry {
+    fn from(v: Version) -> Self {
+        match v.options.is_empty() {
+            true => VersionEntry::Plain(v.version, v.hash),
+            false => VersionEntry::WithOptions { version: v.version, hash: v.hash, options: v.options },
+        }
+    }
+}
+
+/// Parts of WASI converters may use. No files can be accessed and there is never a network.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum WasiCapability {
+    /// Closed stdin, stdout and stderr that discard output, and exiting.
+    ///
+    /// Includes the filesystem interfaces of WASI preview 2, which the standard library imports
+    /// regardless. Nothing is preopened, so they can't open any file.
+    Stdio,
+    /// Clocks, deterministic: they start at zero and advance a bit with every reading.
+    Clock,
+    /// Random numbers, deterministic: the same sequence on every instantiation.
+    Random,
+    /// Environment variables and arguments, always empty.
+    Env,
+}
+
+impl WasiCapability {
+    pub fn name(&self) -> &'static str {
+        match self {
+            WasiCapability::Stdio => "stdio",
+            WasiCapability::Clock => "clock",
+            WasiCapability::Random => "random",
+            WasiCapability::Env => "env",
+        }
+    }
+
+    /// The capability with the given `name()`.
+    pub fn parse(name: &str) -> Option<WasiCapability> {
+        [WasiCapability::Stdio, WasiCapability::Clock, WasiCapability::Random, WasiCapability::Env]
+            .into_iter()
+            .find(|capability| capability.name() == name)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ConverterOption {
+    pub name: String,
+    pub desc: String,
+    #[serde(flatten)]
+    pub kind: OptionKind,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum OptionKind {
+    Bool {
+        default: bool,
+    },
+    Int {
+        default: i64,
+        #[serde(default)]
+        min: Option<i64>,
+        #[serde(default)]
+        max: Option<i64>,
+    },
+    Choice {
+        choices: Vec<String>,
+        default: String,
+    },
+}
+
+impl ConverterOption {
+    pub fn default_value(&self) -> String {
+        match &self.kind {
+            OptionKind::Bool { default } => default.to_string(),
+            OptionKind::Int { default, .. } => default.to_string(),
+            OptionKind::Choice { default, .. } => default.clone(),
+        }
+    }
+
+    /// Checks that `value` (e.g. given on the command line) is valid for this option.
+    pub fn check(&self, value: &str) -> Result<()> {
+        match &self.kind {
+            OptionKind::Bool { .. } => {
+                if value != "true" && value != "false" {
+                    return Err(anyhow!("Option '{}' must be true or false, not '{}'", self.name, value));
+                }
+            }
+            OptionKind::Int { min, max, .. } => {
+                let n: i64 = value.parse()
+                    .map_err(|_| anyhow!("Option '{}' must be an integer, not '{}'", self.name, value))?;
+                if min.is_some_and(|min| n < min) || max.is_some_and(|max| n > max) {
+                    let min = min.map(|n| n.to_string()).unwrap_or_default();
+                    let max = max.map(|n| n.to_string()).unwrap_or_default();
+                    return Err(anyhow!("Option '{}' must be in the range {}..={}, not {}", self.name, min, max, n));
+                }
+            }
+            OptionKind::Choice { choices, .. } => {
+                if !choices.iter().any(|c| c == value) {
+                    return Err(anyhow!("Option '{}' must be one of {}, not '{}'", self.name, choices.join(", "), value));
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 impl Galaxy {
     pub fn from_json(path: &std::path::Path) -> Result<Galaxy> {
-        Ok(serde_json::from_slice(&std::fs::read(path)?)?)
+        let galaxy: Galaxy = serde_json::from_slice(&std::fs::read(path)?)?;
+        galaxy.check()?;
+        Ok(galaxy)
     }
 
     pub fn from_json_str(s: &str) -> Result<Galaxy> {
-        Ok(serde_json::from_str(s)?)
+        let galaxy: Galaxy = serde_json::from_str(s)?;
+        galaxy.check()?;
+        Ok(galaxy)
+    }
+
+    fn check(&self) -> Result<()> {
+        for format in self.formats.values() {
+            if format.converters.contains_key(&RESERVED_CONVERTER_ID) {
+                return Err(anyhow!("Format '{}' has a converter with the reserved id {}", format.name, RESERVED_CONVERTER_ID.0));
+            }
+        }
+        Ok(())
     }
 }
 
@@ -49,9 +256,20 @@ fn test() {
         name: "conv1".into(),
         desc: "....".into(),
         versions: vec!(
-            ("0.1.0".to_string(), ConverterHash("blabla my hash".to_string())),
-            ("0.1.1".to_string(), ConverterHash("blabla my hash 2".to_string())),
-        )
+            Version { version: "0.1.0".to_string(), hash: ConverterHash("blabla my hash".to_string()), options: vec!() },
+            Version {
+                version: "0.1.1".to_string(),
+                hash: ConverterHash("blabla my hash 2".to_string()),
+                options: vec!(ConverterOption {
+                    name: "indent".to_string(),
+                    desc: "...".to_string(),
+                    kind: OptionKind::Int { default: 2, min: Some(0), max: None },
+                }),
+            },
+        ),
+        views: vec!(),
+        windowed: false,
+        wasi: vec!(WasiCapability::Clock),
     };
     let mut converters = HashMap::new();
     converters.insert(ConverterId(1), conv1.clone());
@@ -62,13 +280,18 @@ fn test() {
         name: "Foo".to_string(),
         desc: "...".to_string(),
         extensions: vec!("json".to_string()),
-        converters
+        converters,
+        detect: Detection {
+            magic: vec!(Magic { offset: 4, bytes: "7b22".to_string() }),
+            sniff: Some(ConverterId(1)),
+        },
     });
     formats.insert(FormatId(55555), FileFormat {
         name: "Bar".to_string(),
         desc: "...".to_string(),
         extensions: vec!(),
-        converters: HashMap::new()
+        converters: HashMap::new(),
+        detect: Detection::default(),
     });
     let g = Galaxy {
         formats
@@ -76,13 +299,77 @@ fn test() {
 
     let s = serde_json::to_string_pretty(&g).unwrap();
     println!("{}", s);
+    let g2 = Galaxy::from_json_str(&s).unwrap();
+    assert_eq!(g2.formats[&FormatId(123)].converters[&ConverterId(1)].versions, g.formats[&FormatId(123)].converters[&ConverterId(1)].versions);
     //assert!(false)
 }
 
+#[test]
+fn test_reserved_id() {
+    let index = |id| format!(r#"{{"formats": {{"1": {{"name": "Foo", "desc": "", "extensions": [],
+        "converters": {{"{}": {{"name": "c", "desc": "", "versions": []}}}}}}}}}}"#, id);
+    assert!(Galaxy::from_json_str(&index(1)).is_ok());
+    let err = Galaxy::from_json_str(&index(0)).unwrap_err();
+    assert_eq!(err.to_string(), "Format 'Foo' has a converter with the reserved id 0");
+}
+
 #[test]
 fn test_read() {
-    let galaxy: Galaxy = serde_json::from_slice(&std::fs::read("../test_index.json").unwrap()).unwrap();
+    let galaxy: Galaxy = serde_json::from_slice(&std::fs::read("../../fg-index/test_index.json").unwrap()).unwrap();
 
     println!("{:#?}", galaxy);
     //assert!(false);
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_magic() {
+    let magic = Magic { offset: 1, bytes: "0061736D".to_string() };
+    assert!(magic.matches(b"x\0asm\x01"));
+    assert!(!magic.matches(b"\0asm"));
+    assert!(!magic.matches(b"x\0as"));
+    assert!(!Magic { offset: 0, bytes: "0g".to_string() }.matches(b"\0"));
+}
+
+#[test]
+fn test_wasi() {
+    let converter: Converter = serde_json::from_str(r#"{"name": "c", "desc": "", "versions": [], "wasi": ["clock", "random"]}"#).unwrap();
+    assert_eq!(converter.wasi, vec!(WasiCapability::Clock, WasiCapability::Random));
+    assert!(serde_json::from_str::<Converter>(r#"{"name": "c", "desc": "", "versions": [], "wasi": ["filesystem"]}"#).is_err());
+    assert_eq!(WasiCapability::parse("env"), Some(WasiCapability::Env));
+    assert_eq!(WasiCapability::parse("filesystem"), None);
+}
+
+#[test]
+fn test_options() {
+    let option: ConverterOption = serde_json::from_str(r#"{"name": "indent", "desc": "", "type": "int", "default": 2, "min": 0, "max": 8}"#).unwrap();
+    assert_eq!(option.kind, OptionKind::Int { default: 2, min: Some(0), max: Some(8) });
+    assert_eq!(option.default_value(), "2");
+    option.check("8").unwrap();
+    assert!(option.check("9").is_err());
+    assert!(option.check("-1").is_err());
+    assert!(option.check("two").is_err());
+
+    let option: ConverterOption = serde_json::from_str(r#"{"name": "mode", "desc": "", "type": "choice", "choices": ["a", "b"], "default": "a"}"#).unwrap();
+    option.check("b").unwrap();
+    assert!(option.check("c").is_err());
+
+    let option: ConverterOption = serde_json::from_str(r#"{"name": "sort", "desc": "", "type": "bool", "default": false}"#).unwrap();
+    option.check("true").unwrap();
+    assert!(option.check("yes").is_err());
+}
+
+#[test]
+fn test_versions() {
+    let converter: Converter = serde_json::from_str(r#"{"name": "c", "desc": "", "versions": [
+        ["0.1.0", "aa"],
+        {"version": "0.2.0", "hash": "bb", "options": [{"name": "sort", "desc": "", "type": "bool", "default": false}]},
+        {"version": "0.2.1", "hash": "cc"}
+    ]}"#).unwrap();
+    let options: Vec<_> = converter.versions.iter().map(|v| v.options.len()).collect();
+    assert_eq!(options, vec!(0, 1, 0));
+    assert_eq!(converter.versions[1].hash, ConverterHash("bb".to_string()));
+
+    // without options, versions are written like before
+    let v = serde_json::to_string(&converter.versions[2]).unwrap();
+    assert_eq!(v, r#"["0.2.1","cc"]"#);
+}
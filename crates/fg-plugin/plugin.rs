@@ -1,4 +1,76 @@
-use anyhow::Result;
+use std::fmt;
+use std::ops::Range;
+
+use anyhow::{anyhow, Result};
+pub use format_galaxy_core::location::StoreError;
+use format_galaxy_core::location::{decode_location, LOCATION_LEN};
+pub use format_galaxy_core::options::Options;
+pub use format_galaxy_core::styled::{Span, Style, StyledText};
+use format_galaxy_core::options::{encode_options_call, OPTIONS_UNSUPPORTED};
+use format_galaxy_core::view::encode_view_call;
+pub use format_galaxy_core::window::Window;
+use format_galaxy_core::window::{encode_window_call, WINDOWS_UNSUPPORTED};
+
+mod window;
+mod world;
+
+pub use window::{next_window, MAX_CHUNK_SIZE};
+pub use world::{
+    ConverterError, ConverterWorld, LocatedError, Metadata, ModuleAdapter, WorldPlugin, is_component
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemoryAccess {
+    Read,
+    Write,
+}
+
+/// An access to guest memory outside of its bounds, e.g. with a bogus pointer or length returned
+/// by a misbehaving converter.
+///
+/// `memory_read` and `memory_write` fail with this error, hosts can downcast to it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MemoryAccessError {
+    pub access: MemoryAccess,
+    pub ptr: u32,
+    pub len: usize,
+    /// Size of the guest's memory at the time of the access.
+    pub memory_size: usize,
+}
+
+impl fmt::Display for MemoryAccessError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let verb = match self.access {
+            MemoryAccess::Read => "read",
+            MemoryAccess::Write => "write",
+        };
+        write!(f, "Tried to {} {} bytes at {:#x}, outside of the converter's {} bytes of memory", verb, self.len, self.ptr, self.memory_size)
+    }
+}
+
+impl std::error::Error for MemoryAccessError {}
+
+/// The range of `len` bytes at `ptr` in guest memory of `memory_size` bytes, an error if it
+/// doesn't fit.
+pub fn memory_range(access: MemoryAccess, ptr: u32, len: usize, memory_size: usize) -> Result<Range<usize>, MemoryAccessError> {
+    let start = ptr as usize;
+    match start.checked_add(len) {
+        Some(end) if end <= memory_size => Ok(start..end),
+        _ => Err(MemoryAccessError { access, ptr, len, memory_size }),
+    }
+}
+
+/// The optional exports taking a pointer and length, see `GalaxyFormatPluginV1_::call_optional`.
+pub const OPTIONAL_CALLS: &[&str] = &[
+    "present_styled",
+    "sniff",
+    "present_window",
+    "present_view",
+    "store_view",
+    "present_with_options",
+    "present_styled_with_options",
+    "store_with_options",
+];
 
 pub trait GalaxyFormatPluginV1_ {
     fn alloc(&mut self, size: u32) -> Result<u32>;
@@ -9,17 +81,96 @@ pub trait GalaxyFormatPluginV1_ {
     fn result_get_len(&mut self, res_ptr: u32) -> Result<u32>;
     fn result_get_success(&mut self, res_ptr: u32) -> Result<bool>;
     
+    // pointers and lengths come from the guest, implementations check them with `memory_range`
     fn memory_write(&mut self, ptr: u32, bytes: &[u8]) -> Result<()>;
     fn memory_read(&mut self, ptr: u32, len: u32) -> Result<Vec<u8>>;
 
+    // `sniff`, `present_styled`, `present_window`, the view functions and the `_with_options`
+    // functions (`OPTIONAL_CALLS`) are optional, plugins built before they were added don't
+    // export them. Hosts implement these two for all of them.
+    fn has_optional(&self, _name: &str) -> bool {
+        false
+    }
+
+    fn call_optional(&mut self, name: &str, _ptr: u32, _size: u32) -> Result<u32> {
+        Err(anyhow!("The plugin doesn't export `{}`", name))
+    }
+
+    fn has_sniff(&self) -> bool {
+        self.has_optional("sniff")
+    }
+
+    fn has_present_styled(&self) -> bool {
+        self.has_optional("present_styled")
+    }
+
+    fn present_styled(&mut self, ptr: u32, size: u32) -> Result<u32> {
+        self.call_optional("present_styled", ptr, size)
+    }
+
+    fn sniff(&mut self, ptr: u32, size: u32) -> Result<u32> {
+        self.call_optional("sniff", ptr, size)
+    }
+
+    fn has_present_window(&self) -> bool {
+        self.has_optional("present_window")
+    }
+
+    fn present_window(&mut self, ptr: u32, size: u32) -> Result<u32> {
+        self.call_optional("present_window", ptr, size)
+    }
+
+    fn has_views(&self) -> bool {
+        self.has_optional("present_view") && self.has_optional("store_view")
+    }
+
+    fn present_view(&mut self, ptr: u32, size: u32) -> Result<u32> {
+        self.call_optional("present_view", ptr, size)
+    }
+
+    fn store_view(&mut self, ptr: u32, size: u32) -> Result<u32> {
+        self.call_optional("store_view", ptr, size)
+    }
+
+    fn has_options(&self) -> bool {
+        ["present_with_options", "present_styled_with_options", "store_with_options"].iter()
+            .all(|name| self.has_optional(name))
+    }
+
+    fn present_with_options(&mut self, ptr: u32, size: u32) -> Result<u32> {
+        self.call_optional("present_with_options", ptr, size)
+    }
+
+    fn present_styled_with_options(&mut self, ptr: u32, size: u32) -> Result<u32> {
+        self.call_optional("present_styled_with_options", ptr, size)
+    }
+
+    fn store_with_options(&mut self, ptr: u32, size: u32) -> Result<u32> {
+        self.call_optional("store_with_options", ptr, size)
+    }
+
+    // `result_get_location` is optional as well
+    fn has_location(&self) -> bool {
+        false
+    }
+
+    fn result_get_location(&mut self, _res_ptr: u32) -> Result<u32> {
+        Err(anyhow!("The plugin doesn't export `result_get_location`"))
+    }
+
     fn handle_call<T: FnMut(&mut Self, u32, u32) -> Result<u32>>(&mut self, bytes: &[u8], f: &mut T) -> anyhow::Result<Result<Vec<u8>, String>> {
+        Ok(self.handle_call_located(bytes, f)?.map_err(|e| e.message))
+    }
+
+    /// Like `handle_call`, with the location of errors if the plugin reports them.
+    fn handle_call_located<T: FnMut(&mut Self, u32, u32) -> Result<u32>>(&mut self, bytes: &[u8], f: &mut T) -> anyhow::Result<Result<Vec<u8>, StoreError>> {
         // allocate memory and store bytes
         let len =bytes.len();
         let ptr = self.alloc(len as u32)?;
         self.memory_write(ptr, bytes)?;
 
         // main call
-        let res_ptr = f(self, ptr as u32, len as u32)?;
+        let res_ptr = f(self, ptr, len as u32)?;
 
         // get result
         let ptr = self.result_get_ptr(res_ptr)?;
@@ -28,18 +179,95 @@ pub trait GalaxyFormatPluginV1_ {
 
         let v = self.memory_read(ptr, len)?;
 
+        let location = if !success && self.has_location() {
+            match self.result_get_location(res_ptr)? {
+                0 => None,
+                ptr => Some(decode_location(&self.memory_read(ptr, LOCATION_LEN)?).map_err(|e| anyhow!(e))?),
+            }
+        } else {
+            None
+        };
+
         // free result memory
         self.free(res_ptr)?;
 
         Ok(if success {
             Ok(v)
         } else {
-            Err(String::from_utf8(v)?)
+            Err(StoreError { message: String::from_utf8(v)?, location })
         })
     }
 }
 
-pub trait GalaxyFormatPluginV1 : GalaxyFormatPluginV1_ {
+
+/// High-level plugin interface used by the hosts.
+///
+/// Plugins running in a wasm runtime get this for free by implementing `GalaxyFormatPluginV1_`,
+/// other backends (e.g. native converters) implement it directly.
+pub trait GalaxyFormatPluginV1 {
+    fn present(&mut self, bytes: &[u8]) -> Result<Result<String, String>>;
+
+    fn store(&mut self, s: &str) -> Result<Result<Vec<u8>, String>>;
+
+    /// Like `present`, but with styled spans for syntax highlighting (if the plugin supports it).
+    fn present_styled(&mut self, bytes: &[u8]) -> Result<Result<StyledText, String>> {
+        Ok(self.present(bytes)?.map(StyledText::plain))
+    }
+
+    /// Confidence (0 to 100) that `bytes` are in the plugin's format, `None` if the plugin can't tell.
+    fn sniff(&mut self, _bytes: &[u8]) -> Result<Option<u8>> {
+        Ok(None)
+    }
+
+    /// Like `present`, with option values declared for the converter in the index.
+    fn present_with_options(&mut self, bytes: &[u8], options: &Options) -> Result<Result<String, String>> {
+        if !options.is_empty() {
+            return Ok(Err(OPTIONS_UNSUPPORTED.to_string()));
+        }
+        self.present(bytes)
+    }
+
+    /// Like `present_styled`, with option values declared for the converter in the index.
+    fn present_styled_with_options(&mut self, bytes: &[u8], options: &Options) -> Result<Result<StyledText, String>> {
+        if !options.is_empty() {
+            return Ok(Err(OPTIONS_UNSUPPORTED.to_string()));
+        }
+        self.present_styled(bytes)
+    }
+
+    /// Like `store`, with option values declared for the converter in the index.
+    fn store_with_options(&mut self, s: &str, options: &Options) -> Result<Result<Vec<u8>, String>> {
+        if !options.is_empty() {
+            return Ok(Err(OPTIONS_UNSUPPORTED.to_string()));
+        }
+        self.store(s)
+    }
+
+    /// Like `store_with_options`, with the range of the text an error is about if the plugin
+    /// reports it.
+    fn store_located(&mut self, s: &str, options: &Options) -> Result<Result<Vec<u8>, StoreError>> {
+        Ok(self.store_with_options(s, options)?.map_err(StoreError::from))
+    }
+
+    /// Presents up to `max_records` complete records from the start of `chunk`, which begins at
+    /// `offset` in the file (only for converters that are `windowed` in the index).
+    fn present_window(&mut self, _chunk: &[u8], _offset: u64, _max_records: u32) -> Result<Result<Window, String>> {
+        Ok(Err(WINDOWS_UNSUPPORTED.to_string()))
+    }
+
+    /// Presents `bytes` in the named view.
+    fn present_view(&mut self, view: &str, _bytes: &[u8]) -> Result<Result<String, String>> {
+        Ok(Err(format!("Unknown view '{}'", view)))
+    }
+
+    /// Parses text in the named view.
+    fn store_view(&mut self, view: &str, _s: &str) -> Result<Result<Vec<u8>, String>> {
+        Ok(Err(format!("Unknown view '{}'", view)))
+    }
+}
+
+impl<T> GalaxyFormatPluginV1 for T
+where T: GalaxyFormatPluginV1_ {
     fn present(&mut self, bytes: &[u8]) -> Result<Result<String, String>> {
         Ok(match self.handle_call(bytes, &mut <Self as GalaxyFormatPluginV1_>::present)? {
             Ok(bytes) => Ok(String::from_utf8(bytes)?),
@@ -50,7 +278,98 @@ pub trait GalaxyFormatPluginV1 : GalaxyFormatPluginV1_ {
     fn store(&mut self, s: &str) -> Result<Result<Vec<u8>, String>> {
         self.handle_call(s.as_bytes(), &mut <Self as GalaxyFormatPluginV1_>::store)
     }
-}
 
-impl<T> GalaxyFormatPluginV1 for T
-where T: GalaxyFormatPluginV1_ {}
+    fn present_styled(&mut self, bytes: &[u8]) -> Result<Result<StyledText, String>> {
+        if !self.has_present_styled() {
+            return Ok(GalaxyFormatPluginV1::present(self, bytes)?.map(StyledText::plain));
+        }
+        Ok(match self.handle_call(bytes, &mut <Self as GalaxyFormatPluginV1_>::present_styled)? {
+            Ok(bytes) => Ok(StyledText::decode(&bytes).map_err(|e| anyhow!(e))?),
+            Err(s) => Err(s)
+        })
+    }
+
+    fn sniff(&mut self, bytes: &[u8]) -> Result<Option<u8>> {
+        if !self.has_sniff() {
+            return Ok(None);
+        }
+        // the plugin takes ownership of the input, so there's nothing to free afterwards
+        let ptr = self.alloc(bytes.len() as u32)?;
+        self.memory_write(ptr, bytes)?;
+        let confidence = <Self as GalaxyFormatPluginV1_>::sniff(self, ptr, bytes.len() as u32)?;
+        Ok(Some(confidence.min(100) as u8))
+    }
+
+    fn present_window(&mut self, chunk: &[u8], offset: u64, max_records: u32) -> Result<Result<Window, String>> {
+        if !self.has_present_window() {
+            return Ok(Err(WINDOWS_UNSUPPORTED.to_string()));
+        }
+        Ok(match self.handle_call(&encode_window_call(offset, max_records, chunk), &mut <Self as GalaxyFormatPluginV1_>::present_window)? {
+            Ok(bytes) => Ok(Window::decode(&bytes).map_err(|e| anyhow!(e))?),
+            Err(s) => Err(s)
+        })
+    }
+
+    fn present_view(&mut self, view: &str, bytes: &[u8]) -> Result<Result<String, String>> {
+        if !self.has_views() {
+            return Ok(Err(format!("Unknown view '{}'", view)));
+        }
+        Ok(match self.handle_call(&encode_view_call(view, bytes), &mut <Self as GalaxyFormatPluginV1_>::present_view)? {
+            Ok(bytes) => Ok(String::from_utf8(bytes)?),
+            Err(s) => Err(s)
+        })
+    }
+
+    fn store_view(&mut self, view: &str, s: &str) -> Result<Result<Vec<u8>, String>> {
+        if !self.has_views() {
+            return Ok(Err(format!("Unknown view '{}'", view)));
+        }
+        self.handle_call(&encode_view_call(view, s.as_bytes()), &mut <Self as GalaxyFormatPluginV1_>::store_view)
+    }
+
+    fn present_with_options(&mut self, bytes: &[u8], options: &Options) -> Result<Result<String, String>> {
+        if options.is_empty() {
+            return GalaxyFormatPluginV1::present(self, bytes);
+        }
+        if !self.has_options() {
+            return Ok(Err(OPTIONS_UNSUPPORTED.to_string()));
+        }
+        Ok(match self.handle_call(&encode_options_call(options, bytes), &mut <Self as GalaxyFormatPluginV1_>::present_with_options)? {
+            Ok(bytes) => Ok(String::from_utf8(bytes)?),
+            Err(s) => Err(s)
+        })
+    }
+
+    fn present_styled_with_options(&mut self, bytes: &[u8], options: &Options) -> Result<Result<StyledText, String>> {
+        if options.is_empty() {
+            return GalaxyFormatPluginV1::present_styled(self, bytes);
+        }
+        if !self.has_options() {
+            return Ok(Err(OPTIONS_UNSUPPORTED.to_string()));
+        }
+        Ok(match self.handle_call(&encode_options_call(options, bytes), &mut <Self as GalaxyFormatPluginV1_>::present_styled_with_options)? {
+            Ok(bytes) => Ok(StyledText::decode(&bytes).map_err(|e| anyhow!(e))?),
+            Err(s) => Err(s)
+        })
+    }
+
+    fn store_with_options(&mut self, s: &str, options: &Options) -> Result<Result<Vec<u8>, String>> {
+        if options.is_empty() {
+            return GalaxyFormatPluginV1::store(self, s);
+        }
+        if !self.has_options() {
+            return Ok(Err(OPTIONS_UNSUPPORTED.to_string()));
+        }
+        self.handle_call(&encode_options_call(options, s.as_bytes()), &mut <Self as GalaxyFormatPluginV1_>::store_with_options)
+    }
+
+    fn store_located(&mut self, s: &str, options: &Options) -> Result<Result<Vec<u8>, StoreError>> {
+        if options.is_empty() {
+            return self.handle_call_located(s.as_bytes(), &mut <Self as GalaxyFormatPluginV1_>::store);
+        }
+        if !self.has_options() {
+            return Ok(Err(StoreError::from(OPTIONS_UNSUPPORTED.to_string())));
+        }
+        self.handle_call_located(&encode_options_call(options, s.as_bytes()), &mut <Self as GalaxyFormatPluginV1_>::store_with_options)
+    }
+}
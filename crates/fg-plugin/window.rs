@@ -0,0 +1,58 @@
+/* Presenting an input window by window
+
+Converters present the complete records at the start of a chunk and report where the next
+window starts (the continuation token), so records are never split between windows. If not even
+one record fits into a chunk, the chunk is enlarged until it does. Hosts read the chunks from
+wherever their input is, e.g. a file or bytes in memory.
+*/
+
+use anyhow::{anyhow, Result};
+
+use crate::{GalaxyFormatPluginV1, Window};
+
+// records that don't fit into this are rejected instead of passing ever larger chunks
+pub const MAX_CHUNK_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Presents up to `max_records` records from `pos` of an input that ends at `end`.
+///
+/// `read(pos, len)` returns the `len` bytes of the input at `pos`, the first chunk read is
+/// `chunk_size` bytes long. Errors of the converter, incomplete records and records larger
+/// than `MAX_CHUNK_SIZE` are returned as the inner error, invalid windows as the outer one.
+pub fn next_window<P, B>(
+    plugin: &mut P,
+    mut read: impl FnMut(u64, u64) -> Result<B>,
+    pos: u64,
+    end: u64,
+    mut chunk_size: u64,
+    max_records: u32,
+) -> Result<Result<Window, String>>
+where
+    P: GalaxyFormatPluginV1 + ?Sized,
+    B: AsRef<[u8]>,
+{
+    loop {
+        let len = chunk_size.min(end - pos);
+        let chunk = read(pos, len)?;
+        let window = match plugin.present_window(chunk.as_ref(), pos, max_records)? {
+            Ok(window) => window,
+            Err(e) => return Ok(Err(e)),
+        };
+        if window.next < pos || window.next > pos + len {
+            return Err(anyhow!("The converter returned an invalid continuation token ({})", window.next));
+        }
+        if window.records > max_records {
+            return Err(anyhow!("The converter returned {} records, only {} were requested", window.records, max_records));
+        }
+        if window.next > pos || max_records == 0 {
+            return Ok(Ok(window));
+        }
+        // not even one record fits into the chunk
+        if pos + len == end {
+            return Ok(Err(format!("Incomplete record at offset {}", pos)));
+        }
+        if chunk_size >= MAX_CHUNK_SIZE {
+            return Ok(Err(format!("The record at offset {} is larger than {} bytes", pos, MAX_CHUNK_SIZE)));
+        }
+        chunk_size *= 2;
+    }
+}
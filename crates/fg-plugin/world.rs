@@ -0,0 +1,195 @@
+/* The converter world on the host side
+
+`core/wit/converter.wit` is the interface of converters built as components. Hosts call every
+wasm converter through `ConverterWorld`: components implement it with their bindings and V1
+modules are put behind a `ModuleAdapter`, which maps the optional exports of the V1 ABI onto
+the world. `WorldPlugin` turns either into the `GalaxyFormatPluginV1` the rest of a host works
+with.
+*/
+
+use std::ops::Range;
+
+use anyhow::{anyhow, Result};
+use format_galaxy_core::is_unsupported;
+
+use crate::{GalaxyFormatPluginV1, Options, StoreError, StyledText, Window};
+
+/// Whether `bytes` are a component rather than a core module.
+pub fn is_component(bytes: &[u8]) -> bool {
+    // the version and layer fields of the preamble, core modules are version 1 and layer 0
+    bytes.starts_with(b"\0asm") && bytes.get(4..8) == Some(&[0x0d, 0x00, 0x01, 0x00])
+}
+
+/// Name and version of a converter and the `format-galaxy-core` version it was built with.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Metadata {
+    pub name: String,
+    pub version: String,
+    pub core_version: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConverterError {
+    /// The input isn't valid, e.g. bytes that aren't in the format or text that can't be parsed.
+    Invalid(String),
+    /// The converter doesn't support the operation.
+    Unsupported(String),
+}
+
+impl ConverterError {
+    /// The error for a message of a V1 module, unsupported if it's one of the errors of the
+    /// `GalaxyFormat` defaults.
+    pub fn from_message(msg: String) -> Self {
+        if is_unsupported(&msg) {
+            ConverterError::Unsupported(msg)
+        } else {
+            ConverterError::Invalid(msg)
+        }
+    }
+
+    pub fn into_message(self) -> String {
+        match self {
+            ConverterError::Invalid(msg) | ConverterError::Unsupported(msg) => msg,
+        }
+    }
+}
+
+/// An error of `store`, with the byte range of the text it's about if the converter knows it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LocatedError {
+    pub error: ConverterError,
+    pub location: Option<Range<usize>>,
+}
+
+impl From<StoreError> for LocatedError {
+    fn from(e: StoreError) -> Self {
+        LocatedError { error: ConverterError::from_message(e.message), location: e.location }
+    }
+}
+
+impl From<LocatedError> for StoreError {
+    fn from(e: LocatedError) -> Self {
+        StoreError { message: e.error.into_message(), location: e.location }
+    }
+}
+
+/// The exports of the converter world, see `core/wit/converter.wit`.
+pub trait ConverterWorld {
+    fn describe(&mut self) -> Result<Metadata>;
+
+    fn present(&mut self, bytes: &[u8], options: &Options) -> Result<Result<String, ConverterError>>;
+
+    fn present_styled(&mut self, bytes: &[u8], options: &Options) -> Result<Result<StyledText, ConverterError>>;
+
+    fn store(&mut self, text: &str, options: &Options) -> Result<Result<Vec<u8>, LocatedError>>;
+
+    fn sniff(&mut self, bytes: &[u8]) -> Result<u8>;
+
+    fn present_window(&mut self, chunk: &[u8], offset: u64, max_records: u32) -> Result<Result<Window, ConverterError>>;
+
+    fn present_view(&mut self, view: &str, bytes: &[u8]) -> Result<Result<String, ConverterError>>;
+
+    fn store_view(&mut self, view: &str, text: &str) -> Result<Result<Vec<u8>, ConverterError>>;
+}
+
+/// A V1 module (or any other `GalaxyFormatPluginV1`) as a converter world.
+///
+/// Missing optional exports behave like the `GalaxyFormat` defaults of components: no styles,
+/// a sniff of 0 and `unsupported` errors for options and windows. V1 modules don't describe
+/// themselves, their name and version are only in the index.
+pub struct ModuleAdapter<P> {
+    plugin: P,
+}
+
+impl<P> ModuleAdapter<P> {
+    pub fn new(plugin: P) -> Self {
+        ModuleAdapter { plugin }
+    }
+
+    pub fn into_inner(self) -> P {
+        self.plugin
+    }
+}
+
+impl<P: GalaxyFormatPluginV1> ConverterWorld for ModuleAdapter<P> {
+    fn describe(&mut self) -> Result<Metadata> {
+        Err(anyhow!("V1 modules don't describe themselves, their name and version are in the index"))
+    }
+
+    fn present(&mut self, bytes: &[u8], options: &Options) -> Result<Result<String, ConverterError>> {
+        Ok(self.plugin.present_with_options(bytes, options)?.map_err(ConverterError::from_message))
+    }
+
+    fn present_styled(&mut self, bytes: &[u8], options: &Options) -> Result<Result<StyledText, ConverterError>> {
+        Ok(self.plugin.present_styled_with_options(bytes, options)?.map_err(ConverterError::from_message))
+    }
+
+    fn store(&mut self, text: &str, options: &Options) -> Result<Result<Vec<u8>, LocatedError>> {
+        Ok(self.plugin.store_located(text, options)?.map_err(LocatedError::from))
+    }
+
+    fn sniff(&mut self, bytes: &[u8]) -> Result<u8> {
+        Ok(self.plugin.sniff(bytes)?.unwrap_or(0))
+    }
+
+    fn present_window(&mut self, chunk: &[u8], offset: u64, max_records: u32) -> Result<Result<Window, ConverterError>> {
+        Ok(self.plugin.present_window(chunk, offset, max_records)?.map_err(ConverterError::from_message))
+    }
+
+    fn present_view(&mut self, view: &str, bytes: &[u8]) -> Result<Result<String, ConverterError>> {
+        Ok(self.plugin.present_view(view, bytes)?.map_err(ConverterError::from_message))
+    }
+
+    fn store_view(&mut self, view: &str, text: &str) -> Result<Result<Vec<u8>, ConverterError>> {
+        Ok(self.plugin.store_view(view, text)?.map_err(ConverterError::from_message))
+    }
+}
+
+/// A converter world as the plugin interface used by the hosts.
+pub struct WorldPlugin<W>(pub W);
+
+impl<W: ConverterWorld> GalaxyFormatPluginV1 for WorldPlugin<W> {
+    fn present(&mut self, bytes: &[u8]) -> Result<Result<String, String>> {
+        self.present_with_options(bytes, &Options::new())
+    }
+
+    fn store(&mut self, s: &str) -> Result<Result<Vec<u8>, String>> {
+        self.store_with_options(s, &Options::new())
+    }
+
+    fn present_styled(&mut self, bytes: &[u8]) -> Result<Result<StyledText, String>> {
+        self.present_styled_with_options(bytes, &Options::new())
+    }
+
+    fn sniff(&mut self, bytes: &[u8]) -> Result<Option<u8>> {
+        Ok(Some(self.0.sniff(bytes)?.min(100)))
+    }
+
+    fn present_with_options(&mut self, bytes: &[u8], options: &Options) -> Result<Result<String, String>> {
+        Ok(self.0.present(bytes, options)?.map_err(ConverterError::into_message))
+    }
+
+    fn present_styled_with_options(&mut self, bytes: &[u8], options: &Options) -> Result<Result<StyledText, String>> {
+        Ok(self.0.present_styled(bytes, options)?.map_err(ConverterError::into_message))
+    }
+
+    fn store_with_options(&mut self, s: &str, options: &Options) -> Result<Result<Vec<u8>, String>> {
+        Ok(self.0.store(s, options)?.map_err(|e| e.error.into_message()))
+    }
+
+    fn store_located(&mut self, s: &str, options: &Options) -> Result<Result<Vec<u8>, StoreError>> {
+        Ok(self.0.store(s, options)?.map_err(StoreError::from))
+    }
+
+    fn present_window(&mut self, chunk: &[u8], offset: u64, max_records: u32) -> Result<Result<Window, String>> {
+        Ok(self.0.present_window(chunk, offset, max_records)?.map_err(ConverterError::into_message))
+    }
+
+    fn present_view(&mut self, view: &str, bytes: &[u8]) -> Result<Result<String, String>> {
+        Ok(self.0.present_view(view, bytes)?.map_err(ConverterError::into_message))
+    }
+
+    fn store_view(&mut self, view: &str, s: &str) -> Result<Result<Vec<u8>, String>> {
+        Ok(self.0.store_view(view, s)?.map_err(ConverterError::into_message))
+    }
+}
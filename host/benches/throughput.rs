@@ -0,0 +1,51 @@
+/* Conversion throughput of the json-like and bson converters
+
+Compares creating a fresh plugin for every file (what fg-cat does) with reusing
+instances from a `WasmtimePluginPool`. Run with `cargo bench -p format-galaxy-host`.
+*/
+
+use std::path::Path;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use lib::{GalaxyFormatPluginV1, WasmtimeConverterModule, WasmtimeGalaxyFormatPlugin, WasmtimePluginPool};
+use wasmtime::Engine;
+
+const JSON_LIKE_PLUGIN: &str = "../fg-index/converters/18961930fcf30830b2d2ddc22d6465542e15b96fc38860e8b3568ea05da4c017.wasm";
+const BSON_PLUGIN: &str = "../fg-index/converters/c01667cb43f981eec40ba0801efc72f5f3ba37810179ee08df5f0a1e831312d7.wasm";
+
+fn sample_text(num_entries: usize) -> String {
+    let entries: Vec<_> = (0..num_entries)
+        .map(|i| format!("\"key{}\": [{}, \"value {}\", true, null]", i, i, i))
+        .collect();
+    format!("{{{}}}", entries.join(", "))
+}
+
+fn bench_converter(c: &mut Criterion, name: &str, plugin_path: &str) {
+    let path = Path::new(plugin_path);
+    let module = WasmtimeConverterModule::new(&Engine::default(), path).unwrap();
+    let pool = WasmtimePluginPool::new(module, 1);
+
+    // both converters accept json-like text, use them to create the binary input
+    let bytes = pool.get().unwrap().store(&sample_text(100)).unwrap().unwrap();
+
+    let mut group = c.benchmark_group(name);
+    group.throughput(Throughput::Bytes(bytes.len() as u64));
+    group.bench_with_input(BenchmarkId::new("present", "new instance"), &bytes, |b, bytes| {
+        b.iter(|| {
+            let mut plugin = WasmtimeGalaxyFormatPlugin::new(path).unwrap();
+            plugin.present(bytes).unwrap().unwrap()
+        })
+    });
+    group.bench_with_input(BenchmarkId::new("present", "pooled"), &bytes, |b, bytes| {
+        b.iter(|| pool.get().unwrap().present(bytes).unwrap().unwrap())
+    });
+    group.finish();
+}
+
+fn throughput(c: &mut Criterion) {
+    bench_converter(c, "json-like", JSON_LIKE_PLUGIN);
+    bench_converter(c, "bson", BSON_PLUGIN);
+}
+
+criterion_group!(benches, throughput);
+criterion_main!(benches);
@@ -0,0 +1,57 @@
+/* Rendering of styled text with ANSI escape codes, for terminals
+*/
+
+use fg_plugin::{Style, StyledText};
+
+const RESET: &str = "\x1b[0m";
+
+fn color(style: Style) -> Option<&'static str> {
+    match style {
+        Style::Plain | Style::Punctuation => None,
+        Style::Key => Some("\x1b[34m"),         // blue
+        Style::String => Some("\x1b[32m"),      // green
+        Style::Number => Some("\x1b[36m"),      // cyan
+        Style::Literal => Some("\x1b[35m"),     // magenta
+        Style::Comment => Some("\x1b[90m"),     // bright black
+    }
+}
+
+pub fn to_ansi(text: &StyledText) -> String {
+    let mut s = String::with_capacity(text.text.len());
+    for (segment, style) in text.segments() {
+        match color(style) {
+            Some(color) => {
+                s.push_str(color);
+                s.push_str(segment);
+                s.push_str(RESET);
+            }
+            None => s.push_str(segment),
+        }
+    }
+    s
+}
+
+/// Whether colored output should be written to stdout.
+///
+/// Colors are disabled if stdout isn't a terminal or `NO_COLOR` is set.
+pub fn use_color() -> bool {
+    use std::io::IsTerminal;
+    std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_ansi() {
+        let mut text = StyledText::default();
+        text.push("{", Style::Punctuation);
+        text.push("\"a\"", Style::Key);
+        text.push(": ", Style::Plain);
+        text.push("1", Style::Number);
+        assert_eq!(to_ansi(&text), "{\x1b[34m\"a\"\x1b[0m: \x1b[36m1\x1b[0m");
+        assert_eq!(to_ansi(&StyledText::plain("plain".to_string())), "plain");
+    }
+}
@@ -0,0 +1,248 @@
+/* Batch conversion of many files using a pool of worker threads
+
+`present` turns `<name>.fg` containers into `<name>.fg.txt` text files, `store` turns them back.
+*/
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use anyhow::{anyhow, Result};
+use fg_index::{ConverterId, FormatId};
+use fg_plugin::GalaxyFormatPluginV1;
+use glob::Pattern;
+
+use crate::config::Config;
+use crate::{read_file, read_format_id, write_file, ConverterQuery, ConverterRegistry};
+
+pub const TEXT_EXTENSION: &str = "txt";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Present,
+    Store,
+}
+
+impl Direction {
+    /// Glob pattern matching the input files of this direction.
+    pub fn default_pattern(&self) -> &'static str {
+        match self {
+            Direction::Present => "*.fg",
+            Direction::Store => "*.fg.txt",
+        }
+    }
+
+    pub fn output_path(&self, input: &Path) -> Result<PathBuf> {
+        match self {
+            Direction::Present => {
+                let mut name = input.as_os_str().to_owned();
+                name.push(".");
+                name.push(TEXT_EXTENSION);
+                Ok(PathBuf::from(name))
+            }
+            Direction::Store => {
+                if input.extension().and_then(|s| s.to_str()) != Some(TEXT_EXTENSION) {
+                    return Err(anyhow!("Expected a file with extension .{}", TEXT_EXTENSION));
+                }
+                Ok(input.with_extension(""))
+            }
+        }
+    }
+}
+
+/// Which converter to use. Parts that aren't set are resolved per file.
+#[derive(Clone, Debug, Default)]
+pub struct ConvertOptions {
+    pub converter_id: Option<ConverterId>,
+    pub version: Option<String>,
+    /// Format of newly created containers when storing. Existing containers keep their format.
+    pub format_id: Option<FormatId>,
+    /// Provides the pinned converters, which are used unless `converter_id` is set.
+    pub config: Config,
+}
+
+pub struct ConvertResult {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    pub result: Result<()>,
+}
+
+/// Collects the input files from `paths`.
+///
+/// Files given explicitly are always used, directories are searched for files matching
+/// `pattern`. Searching directories requires `recursive` to be set.
+pub fn collect_files(paths: &[PathBuf], recursive: bool, pattern: &Pattern) -> Result<Vec<PathBuf>> {
+    let mut files = vec!();
+    for path in paths {
+        if path.is_dir() {
+            if !recursive {
+                return Err(anyhow!("{} is a directory (use --recursive to convert its contents)", path.display()));
+            }
+            collect_dir(path, pattern, &mut files)?;
+        } else if path.is_file() {
+            files.push(path.clone());
+        } else {
+            return Err(anyhow!("File not found: {}", path.display()));
+        }
+    }
+    Ok(files)
+}
+
+fn collect_dir(dir: &Path, pattern: &Pattern, files: &mut Vec<PathBuf>) -> Result<()> {
+    let mut entries = std::fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|e| e.path());
+    for entry in entries {
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            collect_dir(&path, pattern, files)?;
+        } else if path.file_name().and_then(|s| s.to_str()).is_some_and(|name| pattern.matches(name)) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Converts all `files` using `num_workers` threads.
+///
+/// Each worker loads its own plugin instances. `on_done` is called after every file, in the
+/// order the files finish.
+pub fn convert_all<R, F>(registry: &R, files: Vec<PathBuf>, direction: Direction, options: &ConvertOptions, num_workers: usize, on_done: F) -> Vec<ConvertResult>
+where
+    R: ConverterRegistry + Sync,
+    F: Fn(&ConvertResult) + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(vec!());
+
+    std::thread::scope(|scope| {
+        for _ in 0..num_workers.max(1) {
+            scope.spawn(|| {
+                let mut worker = Worker {
+                    registry,
+                    options,
+                    plugins: HashMap::new(),
+                };
+                loop {
+                    let idx = next.fetch_add(1, Ordering::SeqCst);
+                    let input = match files.get(idx) {
+                        Some(input) => input.clone(),
+                        None => break,
+                    };
+                    let (output, result) = match direction.output_path(&input) {
+                        Ok(output) => {
+                            let result = worker.convert(direction, &input, &output);
+                            (output, result)
+                        }
+                        Err(e) => (PathBuf::new(), Err(e)),
+                    };
+                    let res = ConvertResult { input, output, result };
+                    on_done(&res);
+                    results.lock().unwrap().push(res);
+                }
+            });
+        }
+    });
+
+    results.into_inner().unwrap()
+}
+
+struct Worker<'a, R: ConverterRegistry> {
+    registry: &'a R,
+    options: &'a ConvertOptions,
+    // plugin instances of this worker, by format
+    plugins: HashMap<FormatId, Box<dyn GalaxyFormatPluginV1>>,
+}
+
+impl<'a, R: ConverterRegistry> Worker<'a, R> {
+    fn plugin(&mut self, format_id: FormatId) -> Result<&mut Box<dyn GalaxyFormatPluginV1>> {
+        if !self.plugins.contains_key(&format_id) {
+            let mut query = match self.options.converter_id {
+                Some(converter_id) => ConverterQuery {
+                    converter_id: Some(converter_id),
+                    ..ConverterQuery::new(format_id)
+                },
+                None => self.options.config.query(format_id),
+            };
+            if self.options.version.is_some() {
+                query.version = self.options.version.clone();
+            }
+            let plugin = self.registry.load_query(&query)?;
+            self.plugins.insert(format_id, plugin);
+        }
+        Ok(self.plugins.get_mut(&format_id).unwrap())
+    }
+
+    fn convert(&mut self, direction: Direction, input: &Path, output: &Path) -> Result<()> {
+        match direction {
+            Direction::Present => {
+                let (format_id, bytes) = read_file(input)?;
+                let s = self.plugin(format_id)?.present(&bytes)?.map_err(|e| anyhow!(e))?;
+                std::fs::write(output, s)?;
+            }
+            Direction::Store => {
+                let format_id = if output.is_file() {
+                    read_format_id(output)?
+                } else {
+                    self.options.format_id
+                        .ok_or_else(|| anyhow!("{} doesn't exist yet, its format needs to be specified", output.display()))?
+                };
+                let s = std::fs::read_to_string(input)?;
+                let bytes = self.plugin(format_id)?.store(&s)?.map_err(|e| anyhow!(e))?;
+                write_file(output, format_id, &bytes)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LocalRegistry;
+    use fg_index::Galaxy;
+
+    #[test]
+    fn test_output_path() {
+        assert_eq!(Direction::Present.output_path(Path::new("a/b.fg")).unwrap(), PathBuf::from("a/b.fg.txt"));
+        assert_eq!(Direction::Store.output_path(Path::new("a/b.fg.txt")).unwrap(), PathBuf::from("a/b.fg"));
+        assert!(Direction::Store.output_path(Path::new("a/b.fg")).is_err());
+    }
+
+    #[test]
+    fn test_convert_all() {
+        let dir = std::env::temp_dir().join(format!("fg-batch-test-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("nested")).unwrap();
+        for (i, name) in ["a.fg", "nested/b.fg", "nested/c.fg"].iter().enumerate() {
+            write_file(&dir.join(name), FormatId(2), &[i as u8, 42]).unwrap();
+        }
+        std::fs::write(dir.join("nested/ignored.bin"), [1, 2, 3]).unwrap();
+        write_file(&dir.join("unknown.fg"), FormatId(12345), &[]).unwrap();
+
+        let galaxy = Galaxy::from_json(Path::new("../fg-index/test_index.json")).unwrap();
+        let mut registry = LocalRegistry::new(galaxy, "../fg-index/converters/");
+        let hash = registry.converter_hash(&registry.resolve(&ConverterQuery::new(FormatId(2))).unwrap()).unwrap().clone();
+        registry.register_native::<byte_sequence::Impl>(&hash);
+
+        let pattern = Pattern::new(Direction::Present.default_pattern()).unwrap();
+        assert!(collect_files(std::slice::from_ref(&dir), false, &pattern).is_err());
+        let files = collect_files(std::slice::from_ref(&dir), true, &pattern).unwrap();
+        assert_eq!(files.len(), 4);
+
+        let results = convert_all(&registry, files, Direction::Present, &ConvertOptions::default(), 3, |_| {});
+        let failed: Vec<_> = results.iter().filter(|r| r.result.is_err()).map(|r| r.input.clone()).collect();
+        assert_eq!(failed, vec!(dir.join("unknown.fg")));
+        assert_eq!(std::fs::read_to_string(dir.join("nested/b.fg.txt")).unwrap(), "1,42");
+
+        // edit and store back
+        std::fs::write(dir.join("nested/c.fg.txt"), "7,8,9").unwrap();
+        let pattern = Pattern::new(Direction::Store.default_pattern()).unwrap();
+        let files = collect_files(&[dir.join("nested")], true, &pattern).unwrap();
+        let results = convert_all(&registry, files, Direction::Store, &ConvertOptions::default(), 2, |_| {});
+        assert!(results.iter().all(|r| r.result.is_ok()));
+        assert_eq!(read_file(&dir.join("nested/c.fg")).unwrap(), (FormatId(2), vec!(7, 8, 9)));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,303 @@
+/* Converters built as components implementing the converter world
+
+See `core/wit/converter.wit`. Components are called through bindings generated from the world
+instead of the pointer-based V1 ABI, and implement `ConverterWorld` with them. V1 modules are put
+behind a `ModuleAdapter` implementing the same interface, so hosts don't have to care which kind
+a converter is (see `LocalRegistry`).
+*/
+
+use std::path::Path;
+
+use anyhow::Result;
+use fg_index::WasiCapability;
+use fg_plugin::{ConverterError, ConverterWorld, LocatedError};
+use wasmtime::component::{Component, Linker};
+use wasmtime::{Engine, Store};
+
+use crate::guest::{guest_result, Guest};
+use crate::{inspect, wasi, WasmtimeConverterModule};
+
+wasmtime::component::bindgen!({
+    path: "../core/wit",
+    world: "converter",
+});
+
+use exports::format_galaxy::converter::api::{Error, StoreError, Style, StyledText};
+use format_galaxy::converter::host;
+
+impl host::Host for Guest {
+    fn log(&mut self, level: host::Level, message: String) {
+        use format_galaxy_core::log::Level;
+        let level = match level {
+            host::Level::Error => Level::Error,
+            host::Level::Warn => Level::Warn,
+            host::Level::Info => Level::Info,
+            host::Level::Debug => Level::Debug,
+        };
+        Guest::log(self, level, &message);
+    }
+
+    fn panic(&mut self, message: String) {
+        self.set_panic(message);
+    }
+}
+
+fn converter_error(e: Error) -> ConverterError {
+    match e {
+        Error::Invalid(msg) => ConverterError::Invalid(msg),
+        Error::Unsupported(msg) => ConverterError::Unsupported(msg),
+    }
+}
+
+fn located_error(e: StoreError) -> LocatedError {
+    LocatedError {
+        error: converter_error(e.error),
+        location: e.location.map(|location| location.offset as usize..(location.offset + location.length) as usize),
+    }
+}
+
+fn option_values(options: &fg_plugin::Options) -> Vec<(String, String)> {
+    options.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+}
+
+// spans are checked like the ones decoded from V1 modules, hosts slice the text with them
+fn styled_text(styled: StyledText) -> Result<fg_plugin::StyledText, ConverterError> {
+    use fg_plugin::Style as S;
+    let spans = styled.spans.into_iter()
+        .map(|span| fg_plugin::Span {
+            start: span.start as usize,
+            end: span.end as usize,
+            style: match span.style {
+                Style::Plain => S::Plain,
+                Style::Key => S::Key,
+                Style::String => S::String,
+                Style::Number => S::Number,
+                Style::Literal => S::Literal,
+                Style::Punctuation => S::Punctuation,
+                Style::Comment => S::Comment,
+            },
+        })
+        .collect();
+    let styled = fg_plugin::StyledText { text: styled.text, spans };
+    styled.validate().map_err(ConverterError::Invalid)?;
+    Ok(styled)
+}
+
+/// A compiled converter component, ready to be instantiated.
+///
+/// Cloning is cheap, all clones share the compiled code.
+#[derive(Clone)]
+pub struct WasmtimeConverterComponent {
+    engine: Engine,
+    pre: ConverterPre<Guest>,
+    // whether the component imports WASI interfaces
+    wasi: bool,
+}
+
+impl WasmtimeConverterComponent {
+    /// Compiles the component at `path`, which must not import WASI interfaces.
+    pub fn new(engine: &Engine, path: &Path) -> Result<Self> {
+        Self::with_wasi(engine, path, &[])
+    }
+
+    /// Compiles the component at `path`, which may import the WASI interfaces of the
+    /// capabilities declared for it in the index.
+    pub fn with_wasi(engine: &Engine, path: &Path, declared: &[WasiCapability]) -> Result<Self> {
+        let bytes = std::fs::read(path)?;
+        // compiled components are cached next to compiled modules
+        let cache_path = WasmtimeConverterModule::cache_path(&bytes);
+        let component = match std::fs::read(&cache_path).ok()
+            .and_then(|serialized| unsafe { Component::deserialize(engine, &serialized).ok() }) {
+            Some(component) => component,
+            None => {
+                let component = Component::new(engine, &bytes)?;
+                if let Some(dir) = cache_path.parent() {
+                    std::fs::create_dir_all(dir)?;
+                }
+                std::fs::write(&cache_path, component.serialize()?)?;
+                component
+            }
+        };
+        Self::from_component(engine, &component, declared)
+    }
+
+    /// Compiles the component `bytes` (binary or text format) like `with_wasi`, but without
+    /// caching the compiled code.
+    pub fn from_bytes(engine: &Engine, bytes: &[u8], declared: &[WasiCapability]) -> Result<Self> {
+        Self::from_component(engine, &Component::new(engine, bytes)?, declared)
+    }
+
+    fn from_component(engine: &Engine, component: &Component, declared: &[WasiCapability]) -> Result<Self> {
+        let inspection = inspect::inspect_component(engine, component, declared);
+        inspection.check()?;
+
+        let mut linker = Linker::new(engine);
+        Converter::add_to_linker(&mut linker, |guest: &mut Guest| guest)?;
+        if !inspection.wasi.is_empty() {
+            wasmtime_wasi::add_to_linker_sync(&mut linker)?;
+        }
+        let pre = ConverterPre::new(linker.instantiate_pre(component)?)?;
+
+        Ok(WasmtimeConverterComponent {
+            engine: engine.clone(),
+            pre,
+            wasi: !inspection.wasi.is_empty(),
+        })
+    }
+
+    pub fn instantiate(&self) -> Result<WasmtimeComponentPlugin> {
+        let mut guest = Guest::new();
+        if self.wasi {
+            guest.wasi = Some(wasi::sandbox().build());
+        }
+        let mut store = Store::new(&self.engine, guest);
+        let converter = self.pre.instantiate(&mut store)?;
+        Ok(WasmtimeComponentPlugin { store, converter })
+    }
+}
+
+pub struct WasmtimeComponentPlugin {
+    store: Store<Guest>,
+    converter: Converter,
+}
+
+impl WasmtimeComponentPlugin {
+    pub fn new(path: &Path) -> Result<Self> {
+        WasmtimeConverterComponent::new(&Engine::default(), path)?.instantiate()
+    }
+
+    /// Instantiates the component `bytes` (binary or text format) without caching its compiled
+    /// code.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        WasmtimeConverterComponent::from_bytes(&Engine::default(), bytes, &[])?.instantiate()
+    }
+}
+
+impl ConverterWorld for WasmtimeComponentPlugin {
+    fn describe(&mut self) -> Result<fg_plugin::Metadata> {
+        let res = self.converter.format_galaxy_converter_api().call_describe(&mut self.store);
+        let metadata = guest_result(res, &mut self.store)?;
+        Ok(fg_plugin::Metadata {
+            name: metadata.name,
+            version: metadata.version,
+            core_version: metadata.core_version,
+        })
+    }
+
+    fn present(&mut self, bytes: &[u8], options: &fg_plugin::Options) -> Result<Result<String, ConverterError>> {
+        let res = self.converter.format_galaxy_converter_api().call_present(&mut self.store, bytes, &option_values(options));
+        Ok(guest_result(res, &mut self.store)?.map_err(converter_error))
+    }
+
+    fn present_styled(&mut self, bytes: &[u8], options: &fg_plugin::Options) -> Result<Result<fg_plugin::StyledText, ConverterError>> {
+        let res = self.converter.format_galaxy_converter_api().call_present_styled(&mut self.store, bytes, &option_values(options));
+        Ok(guest_result(res, &mut self.store)?.map_err(converter_error).and_then(styled_text))
+    }
+
+    fn store(&mut self, text: &str, options: &fg_plugin::Options) -> Result<Result<Vec<u8>, LocatedError>> {
+        let res = self.converter.format_galaxy_converter_api().call_store_text(&mut self.store, text, &option_values(options));
+        Ok(guest_result(res, &mut self.store)?.map_err(located_error))
+    }
+
+    fn sniff(&mut self, bytes: &[u8]) -> Result<u8> {
+        let res = self.converter.format_galaxy_converter_api().call_sniff(&mut self.store, bytes);
+        guest_result(res, &mut self.store)
+    }
+
+    fn present_window(&mut self, chunk: &[u8], offset: u64, max_records: u32) -> Result<Result<fg_plugin::Window, ConverterError>> {
+        let res = self.converter.format_galaxy_converter_api().call_present_window(&mut self.store, chunk, offset, max_records);
+        Ok(guest_result(res, &mut self.store)?
+            .map(|window| fg_plugin::Window { text: window.text, records: window.records, next: window.next })
+            .map_err(converter_error))
+    }
+
+    fn present_view(&mut self, view: &str, bytes: &[u8]) -> Result<Result<String, ConverterError>> {
+        let res = self.converter.format_galaxy_converter_api().call_present_view(&mut self.store, view, bytes);
+        Ok(guest_result(res, &mut self.store)?.map_err(converter_error))
+    }
+
+    fn store_view(&mut self, view: &str, text: &str) -> Result<Result<Vec<u8>, ConverterError>> {
+        let res = self.converter.format_galaxy_converter_api().call_store_view(&mut self.store, view, text);
+        Ok(guest_result(res, &mut self.store)?.map_err(converter_error))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fg_plugin::{is_component, GalaxyFormatPluginV1, ModuleAdapter, Options, WorldPlugin};
+    use crate::WasmtimeGalaxyFormatPlugin;
+
+    // the byte sequence converter of the test index, built with `gen_component!` and as V1 module
+    const BYTES_COMPONENT: &str = "../fg-index/converters/48e60604e3341a36df13ba0a31d46d7d29f9d7d47084cb8ca80c743872a300e5.wasm";
+    const BYTES_MODULE: &str = "../fg-index/converters/d14ea3e27f3235e7488b0af9875d952e1b7c13c267049c89676fcbd4f13f7356.wasm";
+
+    fn component() -> WasmtimeComponentPlugin {
+        let bytes = std::fs::read(BYTES_COMPONENT).unwrap();
+        assert!(is_component(&bytes));
+        assert!(!is_component(&std::fs::read(BYTES_MODULE).unwrap()));
+        // the standard library of `wasm32-wasip2` imports these regardless
+        let wasi = [WasiCapability::Stdio, WasiCapability::Env];
+        WasmtimeConverterComponent::from_bytes(&Engine::default(), &bytes, &wasi).unwrap().instantiate().unwrap()
+    }
+
+    #[test]
+    fn test_component() {
+        let mut plugin = component();
+        let metadata = plugin.describe().unwrap();
+        assert_eq!((metadata.name.as_str(), metadata.version.as_str()), ("byte-sequence", "0.1.0"));
+        assert_eq!(metadata.core_version, "0.1.0");
+
+        let none = Options::new();
+        assert_eq!(plugin.present(&[1, 2], &none).unwrap(), Ok("1,2".to_string()));
+        assert_eq!(plugin.store("3,4", &none).unwrap(), Ok(vec!(3, 4)));
+        let err = ConverterError::Invalid("Could not convert text to byte sequence.".to_string());
+        assert_eq!(plugin.store("3,x", &none).unwrap(), Err(LocatedError { error: err, location: None }));
+        assert_eq!(plugin.present_styled(&[1], &none).unwrap().unwrap().text, "1");
+        assert_eq!(plugin.sniff(&[1]).unwrap(), 0);
+
+        // what the converter doesn't implement is unsupported
+        let mut options = Options::new();
+        options.set("indent", "2").unwrap();
+        assert!(matches!(plugin.present(&[1], &options).unwrap(), Err(ConverterError::Unsupported(_))));
+        assert!(matches!(plugin.present_window(&[1], 0, 1).unwrap(), Err(ConverterError::Unsupported(_))));
+        assert!(matches!(plugin.present_view("summary", &[1]).unwrap(), Err(ConverterError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_styled_text() {
+        use exports::format_galaxy::converter::api::Span;
+        let span = |start, end| Span { start, end, style: Style::Key };
+        let styled = |text: &str, spans| styled_text(StyledText { text: text.to_string(), spans });
+
+        let ok = styled("äb", vec!(span(0, 2), span(2, 3))).unwrap();
+        assert_eq!(ok.segments(), vec!(("ä", fg_plugin::Style::Key), ("b", fg_plugin::Style::Key)));
+        // out of order, in the middle of `ä`, reversed and past the end
+        for spans in [vec!(span(2, 3), span(0, 2)), vec!(span(0, 1)), vec!(span(2, 0)), vec!(span(3, 4))] {
+            assert!(matches!(styled("äb", spans), Err(ConverterError::Invalid(_))));
+        }
+    }
+
+    #[test]
+    fn test_module_adapter() {
+        let module = WasmtimeGalaxyFormatPlugin::new(Path::new(BYTES_MODULE)).unwrap();
+        let mut module = ModuleAdapter::new(module);
+        assert!(module.describe().is_err());
+
+        // V1 modules and components behave the same behind the world
+        let mut module = WorldPlugin(module);
+        let mut component = WorldPlugin(component());
+        let mut options = Options::new();
+        options.set("indent", "2").unwrap();
+        for plugin in [&mut module as &mut dyn GalaxyFormatPluginV1, &mut component] {
+            assert_eq!(plugin.present(&[1, 2]).unwrap(), Ok("1,2".to_string()));
+            assert_eq!(plugin.store("3,4").unwrap(), Ok(vec!(3, 4)));
+            assert!(plugin.store("3,x").unwrap().is_err());
+            assert_eq!(plugin.present_styled(&[1]).unwrap().unwrap().spans, vec!());
+            assert_eq!(plugin.sniff(&[1]).unwrap(), Some(0));
+            assert!(plugin.present_with_options(&[1], &options).unwrap().is_err());
+            assert!(plugin.present_window(&[1], 0, 1).unwrap().is_err());
+        }
+    }
+}
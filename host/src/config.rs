@@ -0,0 +1,81 @@
+/* User configuration of the command line tools
+
+Read from `fg-config.json`, e.g.:
+
+{
+  "pinned": {
+    "200": { "converter": 1100110011, "version": "0.1.0" }
+  },
+  "fallback_indexes": ["other-index.json"]
+}
+
+A missing file is the same as an empty configuration.
+*/
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use fg_index::{ConverterId, FormatId};
+use serde::{Deserialize, Serialize};
+
+use crate::ConverterQuery;
+
+pub const CONFIG_PATH: &str = "fg-config.json";
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Config {
+    /// Converters to use for a format unless another one is requested explicitly.
+    #[serde(default)]
+    pub pinned: HashMap<FormatId, Pin>,
+    /// Indexes to search for formats that are missing in the main index.
+    #[serde(default)]
+    pub fallback_indexes: Vec<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Pin {
+    pub converter: ConverterId,
+    /// Newest version if not set.
+    #[serde(default)]
+    pub version: Option<String>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Config> {
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+        serde_json::from_slice(&std::fs::read(path)?)
+            .map_err(|e| anyhow!("Invalid config file {}: {}", path.display(), e))
+    }
+
+    /// Query for the converter of the given format, using the pinned one if there is one.
+    pub fn query(&self, format_id: FormatId) -> ConverterQuery {
+        match self.pinned.get(&format_id) {
+            Some(pin) => ConverterQuery {
+                format_id,
+                converter_id: Some(pin.converter),
+                version: pin.version.clone(),
+            },
+            None => ConverterQuery::new(format_id),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config() {
+        let config: Config = serde_json::from_str(r#"{"pinned": {"200": {"converter": 1100110011}}}"#).unwrap();
+        let query = config.query(FormatId(200));
+        assert_eq!(query.converter_id, Some(ConverterId(1100110011)));
+        assert_eq!(query.version, None);
+        assert_eq!(config.query(FormatId(2)).converter_id, None);
+
+        assert!(Config::load(Path::new("does-not-exist.json")).unwrap().pinned.is_empty());
+    }
+}
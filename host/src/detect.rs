@@ -0,0 +1,122 @@
+/* Detection of the format of files that don't use the container format
+
+Formats declare magic bytes and/or a converter with a `sniff` function in the index. Together
+with the file extension, these are combined into a confidence per format.
+*/
+
+use std::cmp::Reverse;
+
+use fg_index::{ConverterId, FileFormat, FormatId};
+
+use crate::{ConverterQuery, ConverterRegistry};
+
+// confidences (0 to 100) of the different kinds of evidence
+const MAGIC_CONFIDENCE: u8 = 90;
+const EXTENSION_CONFIDENCE: u8 = 40;
+// upper limit if a format declares magic bytes, but none of them match
+const MAGIC_MISMATCH_CONFIDENCE: u8 = 10;
+
+// sniff functions only get to see the beginning of a file
+pub const MAX_SNIFF_LEN: usize = 64 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FormatCandidate {
+    pub format_id: FormatId,
+    pub confidence: u8,
+}
+
+/// Ranks the formats of the index by how likely `bytes` (with file extension `ext`) are in them.
+///
+/// Only formats with a confidence above zero are returned, the most likely one first. Empty
+/// `bytes` (e.g. a file that doesn't exist yet) are only matched by extension.
+pub fn detect_formats<R: ConverterRegistry + ?Sized>(registry: &R, bytes: &[u8], ext: Option<&str>) -> Vec<FormatCandidate> {
+    let galaxy = registry.galaxy();
+    let mut candidates: Vec<_> = galaxy.formats.iter()
+        .map(|(format_id, format)| FormatCandidate {
+            format_id: *format_id,
+            confidence: confidence(registry, *format_id, format, bytes, ext),
+        })
+        .filter(|c| c.confidence > 0)
+        .collect();
+    candidates.sort_by_key(|c| (Reverse(c.confidence), galaxy.formats[&c.format_id].name.clone()));
+    candidates
+}
+
+fn confidence<R: ConverterRegistry + ?Sized>(registry: &R, format_id: FormatId, format: &FileFormat, bytes: &[u8], ext: Option<&str>) -> u8 {
+    let mut confidence = 0;
+    if ext.is_some_and(|ext| format.extensions.iter().any(|e| e == ext)) {
+        confidence = EXTENSION_CONFIDENCE;
+    }
+    if bytes.is_empty() {
+        return confidence;
+    }
+
+    if let Some(converter_id) = format.detect.sniff {
+        confidence = confidence.max(sniff(registry, format_id, converter_id, bytes).unwrap_or(0));
+    }
+    let magic = &format.detect.magic;
+    if magic.iter().any(|m| m.matches(bytes)) {
+        confidence.max(MAGIC_CONFIDENCE)
+    } else if !magic.is_empty() {
+        confidence.min(MAGIC_MISMATCH_CONFIDENCE)
+    } else {
+        confidence
+    }
+}
+
+// converters that can't be loaded or don't export `sniff` have no opinion
+fn sniff<R: ConverterRegistry + ?Sized>(registry: &R, format_id: FormatId, converter_id: ConverterId, bytes: &[u8]) -> Option<u8> {
+    let query = ConverterQuery {
+        format_id,
+        converter_id: Some(converter_id),
+        version: None,
+    };
+    let mut plugin = registry.load_query(&query).ok()?;
+    plugin.sniff(&bytes[..bytes.len().min(MAX_SNIFF_LEN)]).ok().flatten()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LocalRegistry;
+    use fg_index::Galaxy;
+    use std::path::Path;
+
+    fn registry() -> LocalRegistry {
+        let galaxy = Galaxy::from_json(Path::new("../fg-index/test_index.json")).unwrap();
+        LocalRegistry::new(galaxy, "../fg-index/converters/")
+    }
+
+    fn ranked(candidates: &[FormatCandidate]) -> Vec<(u64, u8)> {
+        candidates.iter().map(|c| (c.format_id.0, c.confidence)).collect()
+    }
+
+    #[test]
+    fn test_detect_magic_and_extension() {
+        let registry = registry();
+        let wasm = b"\0asm\x01\0\0\0";
+
+        // the bson converter takes the header for the beginning of a document
+        assert_eq!(ranked(&detect_formats(&registry, wasm, None)), vec!((300, 90), (200, 20)));
+        // magic bytes outrank the extension
+        assert_eq!(ranked(&detect_formats(&registry, wasm, Some("bson"))), vec!((300, 90), (200, 40), (201, 40)));
+        // wrong magic bytes make the extension unreliable
+        assert_eq!(ranked(&detect_formats(&registry, b"\0\0\0\0", Some("wasm"))), vec!((300, 10)));
+        assert_eq!(ranked(&detect_formats(&registry, &[], Some("wasm"))), vec!((300, 40)));
+        assert!(detect_formats(&registry, b"foo", Some("txt")).is_empty());
+    }
+
+    #[test]
+    fn test_detect_sniff() {
+        let registry = registry();
+
+        // an empty document, the bson converter declares itself as sniffer in the test index
+        let document = [5, 0, 0, 0, 0];
+        assert_eq!(ranked(&detect_formats(&registry, &document, None)), vec!((200, 80)));
+        assert_eq!(ranked(&detect_formats(&registry, &document, Some("bson"))), vec!((200, 80), (201, 40)));
+        // the beginning of a longer document
+        assert_eq!(ranked(&detect_formats(&registry, &[64, 0, 0, 0, 2, b'a'], None)), vec!((200, 20)));
+        assert!(detect_formats(&registry, b"foo bar", None).is_empty());
+    }
+}
@@ -0,0 +1,395 @@
+use std::collections::HashSet;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use anyhow::{anyhow, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+use fg_index::WasiCapability;
+use lib::batch::{self, ConvertOptions, Direction};
+use lib::config::{Config, CONFIG_PATH};
+use lib::info::{self, FileInfo};
+use lib::inspect::{self, ConverterKind, Inspection};
+use lib::wrap::{self, WrapPlan};
+use lib::{ConverterRegistry, LocalRegistry};
+use lib::{ConverterId, FormatId};
+use wasmtime::Engine;
+
+fn download_index() -> Result<lib::Galaxy> {
+    let path = std::path::Path::new("fg-index/test_index.json");
+    let galaxy = lib::Galaxy::from_json(path)?;
+    Ok(galaxy)
+}
+
+#[derive(Parser)]
+#[command(name = "fg", about = "Work with format galaxy files")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Present or store many files at once
+    Convert(ConvertArgs),
+    /// Put raw files (e.g. `data.bson`) into the container format (`data.fg`)
+    Wrap(WrapArgs),
+    /// Turn containers back into raw files, named after the extension of their format
+    Unwrap(UnwrapArgs),
+    /// Show the format and the available converters of a container
+    Info(InfoArgs),
+    /// Work with converter modules
+    #[command(subcommand)]
+    Plugin(PluginCommand),
+}
+
+#[derive(Subcommand)]
+enum PluginCommand {
+    /// Check the imports and exports of a converter against the converter ABI
+    Inspect(InspectArgs),
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ConvertDirection {
+    /// Turn `<name>.fg` containers into `<name>.fg.txt` text files
+    Present,
+    /// Turn `<name>.fg.txt` text files back into `<name>.fg` containers
+    Store,
+}
+
+#[derive(clap::Args)]
+struct ConvertArgs {
+    #[arg(value_enum)]
+    direction: ConvertDirection,
+    /// Files or directories to convert
+    #[arg(required = true)]
+    paths: Vec<PathBuf>,
+    /// Convert the contents of directories recursively
+    #[arg(short, long)]
+    recursive: bool,
+    /// Only convert files in directories whose name matches this pattern (default: `*.fg` or `*.fg.txt`)
+    #[arg(long)]
+    glob: Option<String>,
+    /// Number of worker threads (default: number of CPUs)
+    #[arg(short, long)]
+    jobs: Option<usize>,
+    /// Converter id to use (default: pinned or first converter of the format, 0: built-in hex dump)
+    #[arg(long)]
+    converter: Option<u64>,
+    /// Converter version to use (default: newest)
+    #[arg(long)]
+    version: Option<String>,
+    /// Format id for newly created containers when storing
+    #[arg(long)]
+    format: Option<u64>,
+}
+
+#[derive(clap::Args)]
+struct BulkArgs {
+    /// Files or directories to process
+    #[arg(required = true)]
+    paths: Vec<PathBuf>,
+    /// Process the contents of directories recursively
+    #[arg(short, long)]
+    recursive: bool,
+    /// Only process files in directories whose name matches this pattern
+    #[arg(long)]
+    glob: Option<String>,
+    /// Only print what would be done
+    #[arg(short = 'n', long)]
+    dry_run: bool,
+    /// Overwrite existing output files
+    #[arg(short, long)]
+    force: bool,
+}
+
+#[derive(clap::Args)]
+struct WrapArgs {
+    #[command(flatten)]
+    bulk: BulkArgs,
+    /// Format id of the files (default: detected from extension and content)
+    #[arg(long)]
+    format: Option<u64>,
+}
+
+#[derive(clap::Args)]
+struct UnwrapArgs {
+    #[command(flatten)]
+    bulk: BulkArgs,
+    /// Output file (only for a single input, default: extension of the format)
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(clap::Args)]
+struct InfoArgs {
+    file: PathBuf,
+    /// Print the information as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(clap::Args)]
+struct InspectArgs {
+    /// Compiled converter (`.wasm` module or component)
+    file: PathBuf,
+    /// WASI capabilities declared for the converter, e.g. `clock,random`
+    #[arg(long, value_delimiter = ',', value_parser = parse_wasi_capability)]
+    wasi: Vec<WasiCapability>,
+    /// Print the inspection as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+fn parse_wasi_capability(s: &str) -> Result<WasiCapability, String> {
+    WasiCapability::parse(s).ok_or_else(|| format!("Unknown WASI capability '{}', expected stdio, clock, random or env", s))
+}
+
+fn convert(args: ConvertArgs) -> Result<bool> {
+    let mut registry = LocalRegistry::new(download_index()?, "fg-index/converters/");
+    let config = Config::load(Path::new(CONFIG_PATH))?;
+
+    let direction = match args.direction {
+        ConvertDirection::Present => Direction::Present,
+        ConvertDirection::Store => Direction::Store,
+    };
+    let pattern = glob::Pattern::new(args.glob.as_deref().unwrap_or(direction.default_pattern()))?;
+    let files = batch::collect_files(&args.paths, args.recursive, &pattern)?;
+    let total = files.len();
+
+    // formats that are missing in the index may be found in the fallback indexes
+    for file in &files {
+        let container = match direction {
+            Direction::Present => file.clone(),
+            Direction::Store => match direction.output_path(file) {
+                Ok(output) if output.is_file() => output,
+                _ => continue,
+            },
+        };
+        if let Ok(format_id) = lib::read_format_id(&container) {
+            registry.ensure_format(format_id, &config.fallback_indexes)?;
+        }
+    }
+
+    let options = ConvertOptions {
+        converter_id: args.converter.map(ConverterId),
+        version: args.version,
+        format_id: args.format.map(FormatId),
+        config,
+    };
+    let jobs = args.jobs
+        .unwrap_or_else(|| std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1));
+
+    let show_progress = std::io::stderr().is_terminal();
+    let done = AtomicUsize::new(0);
+    let results = batch::convert_all(&registry, files, direction, &options, jobs, |res| {
+        let n = done.fetch_add(1, Ordering::SeqCst) + 1;
+        if show_progress {
+            let mut stderr = std::io::stderr().lock();
+            let _ = write!(stderr, "\r\x1b[K[{}/{}] {}", n, total, res.input.display());
+            let _ = stderr.flush();
+        }
+    });
+    if show_progress {
+        eprintln!("\r\x1b[K");
+    }
+
+    let mut failed: Vec<_> = results.iter().filter_map(|r| r.result.as_ref().err().map(|e| (&r.input, e))).collect();
+    failed.sort_by_key(|(input, _e)| input.to_path_buf());
+    eprintln!("Converted {} of {} files.", total - failed.len(), total);
+    if !failed.is_empty() {
+        eprintln!("{} files failed:", failed.len());
+        for (input, e) in &failed {
+            eprintln!("  {}: {}", input.display(), e);
+        }
+    }
+    Ok(failed.is_empty())
+}
+
+fn wrap(args: WrapArgs) -> Result<bool> {
+    let registry = LocalRegistry::new(download_index()?, "fg-index/converters/");
+    let format_id = args.format.map(FormatId);
+    let mut files = bulk_files(&args.bulk, "*")?;
+    // containers in directories are skipped, only explicitly named ones are reported
+    files.retain(|f| !lib::is_fg_file(f) || args.bulk.paths.contains(f));
+    let res = bulk(&args.bulk, &registry, files, "Wrapped", |file| wrap::plan_wrap(&registry, file, format_id), wrap::wrap);
+    Ok(res)
+}
+
+fn unwrap(args: UnwrapArgs) -> Result<bool> {
+    let registry = LocalRegistry::new(download_index()?, "fg-index/converters/");
+    let files = bulk_files(&args.bulk, wrap::UNWRAP_PATTERN)?;
+    if args.output.is_some() && files.len() != 1 {
+        return Err(anyhow!("--output can only be used with a single input file"));
+    }
+    let output = args.output.as_deref();
+    let res = bulk(&args.bulk, &registry, files, "Unwrapped", |file| wrap::plan_unwrap(&registry, file, output), wrap::unwrap);
+    Ok(res)
+}
+
+fn bulk_files(args: &BulkArgs, default_pattern: &str) -> Result<Vec<PathBuf>> {
+    let pattern = glob::Pattern::new(args.glob.as_deref().unwrap_or(default_pattern))?;
+    batch::collect_files(&args.paths, args.recursive, &pattern)
+}
+
+// plans and (unless it's a dry run) carries out an operation for every file, returns whether all succeeded
+fn bulk<P, F>(args: &BulkArgs, registry: &LocalRegistry, files: Vec<PathBuf>, verb: &str, plan: P, run: F) -> bool
+where
+    P: Fn(&Path) -> Result<WrapPlan>,
+    F: Fn(&WrapPlan, bool) -> Result<()>,
+{
+    let total = files.len();
+    let mut outputs = HashSet::new();
+    let mut failed = vec!();
+    for file in files {
+        let res = plan(&file).and_then(|plan| {
+            if !outputs.insert(plan.output.clone()) {
+                return Err(anyhow!("{} is also the output of another file", plan.output.display()));
+            }
+            if !args.dry_run {
+                run(&plan, args.force)?;
+            }
+            Ok(plan)
+        });
+        match res {
+            Ok(plan) => {
+                let name = registry.format(plan.format_id).map(|f| f.name.as_str()).unwrap_or("unknown format");
+                println!("{} -> {} ({}, {})", plan.input.display(), plan.output.display(), name, plan.format_id.0);
+            }
+            Err(e) => failed.push((file, e)),
+        }
+    }
+
+    if args.dry_run {
+        eprintln!("Dry run, no files were written.");
+    } else {
+        eprintln!("{} {} of {} files.", verb, total - failed.len(), total);
+    }
+    if !failed.is_empty() {
+        eprintln!("{} files failed:", failed.len());
+        for (input, e) in &failed {
+            eprintln!("  {}: {}", input.display(), e);
+        }
+    }
+    failed.is_empty()
+}
+
+fn info(args: InfoArgs) -> Result<bool> {
+    let mut registry = LocalRegistry::new(download_index()?, "fg-index/converters/");
+    let config = Config::load(Path::new(CONFIG_PATH))?;
+    registry.ensure_format(lib::read_format_id(&args.file)?, &config.fallback_indexes)?;
+    let info = info::file_info(&registry, &config, &args.file)?;
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&info)?);
+    } else {
+        print_info(&info);
+    }
+    Ok(true)
+}
+
+fn yes_no(b: bool) -> &'static str {
+    if b { "yes" } else { "no" }
+}
+
+fn print_info(info: &FileInfo) {
+    println!("File:              {}", info.path.display());
+    println!("Container version: {}", info.container_version);
+    println!("Payload size:      {} bytes", info.payload_size);
+    let format = match &info.format {
+        Some(format) => format,
+        None => {
+            println!("Format:            unknown ({})", info.format_id);
+            return;
+        }
+    };
+    println!("Format:            {} ({})", format.name, info.format_id);
+    println!("Extensions:        {}", format.extensions.join(", "));
+    if let Some(pin) = &info.pinned {
+        let name = format.converters.iter().find(|c| c.id == pin.converter.0).map(|c| c.name.as_str()).unwrap_or("unknown converter");
+        println!("Pinned converter:  {} ({}), version {}", name, pin.converter.0, pin.version.as_deref().unwrap_or("newest"));
+    }
+    println!();
+    println!("{}", format.desc);
+    println!();
+    println!("Converters:");
+    for converter in &format.converters {
+        println!("  {} ({})", converter.name, converter.id);
+        if converter.windowed {
+            println!("    can present parts of files (fg-cat --head/--range/--page)");
+        }
+        if !converter.wasi.is_empty() {
+            let capabilities: Vec<&str> = converter.wasi.iter().map(|c| c.name()).collect();
+            println!("    uses WASI: {}", capabilities.join(", "));
+        }
+        if !converter.views.is_empty() {
+            println!("    views: {}", converter.views.join(", "));
+        }
+        if converter.versions.is_empty() {
+            println!("    no versions");
+        }
+        for v in &converter.versions {
+            println!("    {:<10} {}  downloaded: {}, compiled: {}", v.version, v.hash, yes_no(v.downloaded), yes_no(v.compiled));
+            for option in &v.options {
+                println!("      option {}={}  {}", option.name, option.default_value(), option.desc);
+            }
+        }
+    }
+}
+
+fn inspect(args: InspectArgs) -> Result<bool> {
+    let inspection = inspect::inspect_file(&Engine::default(), &args.file, &args.wasi)?;
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&inspection)?);
+    } else {
+        print_inspection(&args.file, &inspection);
+    }
+    let success = inspection.errors().next().is_none();
+    Ok(success)
+}
+
+fn print_inspection(path: &Path, inspection: &Inspection) {
+    let kind = match inspection.kind {
+        ConverterKind::Module => "core module",
+        ConverterKind::Component => "component",
+    };
+    println!("File:     {} ({})", path.display(), kind);
+    if !inspection.features.is_empty() {
+        println!("Features: {}", inspection.features.join(", "));
+    }
+    if !inspection.wasi.is_empty() {
+        let capabilities: Vec<&str> = inspection.wasi.iter().map(|c| c.name()).collect();
+        println!("WASI:     {}", capabilities.join(", "));
+    }
+    println!();
+    println!("Imports:");
+    for import in &inspection.imports {
+        println!("  {:<40} {}", import.name, import.ty);
+    }
+    println!("Exports:");
+    for export in &inspection.exports {
+        println!("  {:<40} {}", export.name, export.ty);
+    }
+    println!();
+    if inspection.diagnostics.is_empty() {
+        println!("No problems found");
+    }
+    for diagnostic in &inspection.diagnostics {
+        let severity = if diagnostic.is_error() { "error" } else { "warning" };
+        println!("{}: {}", severity, diagnostic);
+    }
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let success = match cli.command {
+        Command::Convert(args) => convert(args)?,
+        Command::Wrap(args) => wrap(args)?,
+        Command::Unwrap(args) => unwrap(args)?,
+        Command::Info(args) => info(args)?,
+        Command::Plugin(PluginCommand::Inspect(args)) => inspect(args)?,
+    };
+    if !success {
+        std::process::exit(1);
+    }
+    Ok(())
+}
@@ -1,7 +1,11 @@
-use lib::WasmtimeGalaxyFormatPlugin;
-use lib::GalaxyFormatPluginV1;
+use lib::config::{Config, CONFIG_PATH};
+use lib::{ConverterRegistry, LocalRegistry};
 use anyhow::Result;
-use std::path::PathBuf;
+use clap::Parser;
+use lib::pager::Output;
+use lib::window::WindowedPresenter;
+use std::io::{IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
 
 use lib::{
     FileType
@@ -13,14 +17,81 @@ fn download_index() -> Result<lib::Galaxy> {
     Ok(galaxy)
 }
 
+#[derive(Parser)]
+#[command(name = "fg-cat", about = "Print the content of a file as text")]
+struct Args {
+    file: PathBuf,
+    /// Show one of the converter's named views instead of the default one
+    #[arg(long, value_name = "NAME")]
+    view: Option<String>,
+    /// Option passed to the converter, see `fg info` for the available ones
+    #[arg(short = 'o', long = "option", value_name = "KEY=VALUE", value_parser = lib::parse_option_arg, conflicts_with = "view")]
+    options: Vec<(String, String)>,
+    /// Only show the first N records (for converters that can present parts of a file)
+    #[arg(long, value_name = "N", conflicts_with_all = ["view", "options"])]
+    head: Option<u32>,
+    /// Only show the records in this byte range of the content, e.g. `0x1000..0x2000`
+    #[arg(long, value_name = "START..END", value_parser = lib::window::parse_range, conflicts_with_all = ["view", "options"])]
+    range: Option<(u64, Option<u64>)>,
+    /// Show one page of records at a time, reading more of the file when asked to
+    #[arg(long, conflicts_with_all = ["view", "options"])]
+    page: bool,
+    /// Don't pipe the output through `$PAGER` (`less -R`) when writing to a terminal
+    #[arg(long)]
+    no_pager: bool,
+}
+
+impl Args {
+    fn is_windowed(&self) -> bool {
+        self.head.is_some() || self.range.is_some() || self.page
+    }
+}
+
+// records presented per window when not paging
+const WINDOW_RECORDS: u32 = 4096;
+
+fn page_size() -> u32 {
+    // leave room for the prompt
+    std::env::var("LINES").ok()
+        .and_then(|lines| lines.parse::<u32>().ok())
+        .unwrap_or(24)
+        .saturating_sub(1)
+        .max(1)
+}
+
+fn ask_for_more() -> bool {
+    eprint!("-- more (Enter: next page, q: quit) --");
+    let mut buf = String::new();
+    let _ = std::io::stdin().read_line(&mut buf);
+    !matches!(buf.trim(), "q" | "quit")
+}
+
+// writes windows until `head` records were shown, in pages if `page` is set
+fn present_windows(presenter: &mut WindowedPresenter, out: &mut Output, head: Option<u32>, page: bool) -> Result<()> {
+    let mut remaining = head.unwrap_or(u32::MAX);
+    let window_size = if page { page_size() } else { WINDOW_RECORDS };
+    while remaining > 0 && !out.is_closed() {
+        let window = match presenter.next_window(remaining.min(window_size))? {
+            Some(window) => window,
+            None => break,
+        };
+        out.write_all(window.text.as_bytes())?;
+        out.flush()?;
+        remaining -= window.records;
+        if page && remaining > 0 && !presenter.is_done() && !ask_for_more() {
+            break;
+        }
+    }
+    Ok(())
+}
+
 fn main() -> Result<()> {
+    let args = Args::parse();
     
-    let galaxy = download_index()?;
+    let mut registry = LocalRegistry::new(download_index()?, "fg-index/converters/");
+    let config = Config::load(Path::new(CONFIG_PATH))?;
 
-    // parse file name from command line
-    let args: Vec<_> = std::env::args().skip(1).collect();
-    let file_name = args.get(0).expect("You need to provide a file path");
-    let file_path = PathBuf::from(file_name);
+    let file_path = args.file.clone();
     if !file_path.is_file() {
         eprintln!("File not found");
         return Ok(());
@@ -31,45 +102,95 @@ fn main() -> Result<()> {
 
     // print warning when using file that doesn't use the fmtgal container format
     if let FileType::Ext(_) = &file_type {
-        eprintln!("WARNING: The file doesn't use the format galaxy container format. The exact format of the file is not known and needs to be selected manually.")
+        eprintln!("WARNING: The file doesn't use the format galaxy container format. The exact format of the file is not known and is guessed from its content and extension.")
     }
 
-    // ask user to select a converter
-    let selection = match lib::select_plugin(&galaxy, &file_type) {
-        Some(x) => x,
-        None => {
-            return Ok(()); // selection was cancelled by user
-        },
-    };
-    
-    // read file content
+    // read file content, only the beginning is needed to detect the format when presenting windows
     // println!("Loading file...");
-    let content_bytes = match file_type {
-        FileType::Ext(_) => {
-            std::fs::read(file_path)?
+    let (content_bytes, candidates) = match &file_type {
+        FileType::Ext(ext) => {
+            let bytes = if args.is_windowed() {
+                let mut bytes = vec!();
+                std::fs::File::open(&file_path)?.take(lib::MAX_SNIFF_LEN as u64).read_to_end(&mut bytes)?;
+                bytes
+            } else {
+                std::fs::read(&file_path)?
+            };
+            let candidates = lib::detect_formats(&registry, &bytes, ext.as_deref());
+            (bytes, candidates)
         }
+        FileType::FormatId(_) if args.is_windowed() => (vec!(), vec!()),
         FileType::FormatId(_) => {
-            let (format_id, bytes) = lib::read_file(&std::path::PathBuf::from(file_name))?;
-            assert_eq!(format_id, selection.format_id);
-            bytes
+            let (_format_id, bytes) = lib::read_file(&file_path)?;
+            (bytes, vec!())
         }
     };
-    
-    let format = &galaxy.formats[&selection.format_id];
-    let converter = &format.converters[&selection.converter_id];
-    let converter_version = &converter.versions[selection.version_idx];
-    let converter_hash = converter_version.1.0.as_str();
+
+    // files of unknown formats can only be shown as raw bytes
+    let unknown_format = match &file_type {
+        FileType::FormatId(fid) if !registry.ensure_format(*fid, &config.fallback_indexes)? => Some(*fid),
+        _ => None,
+    };
 
     // load plugin
+    let mut options = lib::Options::new();
+    let mut windowed_converter = true; // the hex dump
     // println!("Loading Plugin...");
-    let base_path = "fg-index/converters/";
-    let full_path: PathBuf = [base_path, &format!("{}{}", converter_hash, ".wasm")].iter().collect();
-    let mut plugin = WasmtimeGalaxyFormatPlugin::new(&full_path)?;
-
-    // use plugin to present the content
-    match plugin.present(&content_bytes)? {
-        Ok(s) => println!("{}", s),
-        Err(e) => eprintln!("{}", e),
+    let mut plugin = if let Some(fid) = unknown_format {
+        if !lib::offer_raw_view(fid) {
+            return Ok(());
+        }
+        if let Some(view) = &args.view {
+            return Err(anyhow::anyhow!("The hex dump has no view '{}'", view));
+        }
+        if !args.options.is_empty() {
+            return Err(anyhow::anyhow!("The hex dump has no options"));
+        }
+        registry.load_fallback()?
+    } else {
+        // ask user to select a converter
+        let selection = match lib::select_plugin(registry.galaxy(), &file_type, &candidates)? {
+            Some(x) => x,
+            None => {
+                return Ok(()); // selection was cancelled by user
+            },
+        };
+        if let Some(view) = &args.view {
+            registry.check_view(&selection, view)?;
+        }
+        options = registry.check_options(&selection, &args.options)?;
+        windowed_converter = registry.is_windowed(&selection)?;
+        if args.is_windowed() && !windowed_converter {
+            let name = &registry.converter(selection.format_id, selection.converter_id)?.name;
+            return Err(anyhow::anyhow!("Converter '{}' can't present parts of a file, the hex dump can", name));
+        }
+        registry.load(&selection)?
+    };
+
+    // use plugin to present the content, highlighted when writing to a terminal. Terminal output
+    // goes through the pager, which windowed converters feed window by window
+    let use_pager = !args.no_pager && !args.page && std::io::stdout().is_terminal();
+    let progressive = use_pager && windowed_converter && args.view.is_none() && options.is_empty();
+    let mut out = if use_pager { Output::pager() } else { Output::stdout() };
+    if args.is_windowed() || progressive {
+        let (start, end) = args.range.unwrap_or((0, None));
+        let mut presenter = WindowedPresenter::open(&mut *plugin, &file_path, start, end)?;
+        present_windows(&mut presenter, &mut out, args.head, args.page)?;
+    } else if let Some(view) = &args.view {
+        match plugin.present_view(view, &content_bytes)? {
+            Ok(s) => writeln!(out, "{}", s)?,
+            Err(e) => eprintln!("{}", e),
+        }
+    } else if lib::ansi::use_color() {
+        match plugin.present_styled_with_options(&content_bytes, &options)? {
+            Ok(styled) => writeln!(out, "{}", lib::ansi::to_ansi(&styled))?,
+            Err(e) => eprintln!("{}", e),
+        }
+    } else {
+        match plugin.present_with_options(&content_bytes, &options)? {
+            Ok(s) => writeln!(out, "{}", s)?,
+            Err(e) => eprintln!("{}", e),
+        }
     }
     
     Ok(())
@@ -1,10 +1,11 @@
 use lib::FileType;
-use lib::WasmtimeGalaxyFormatPlugin;
-use lib::GalaxyFormatPluginV1;
+use lib::config::{Config, CONFIG_PATH};
+use lib::{ConverterRegistry, LocalRegistry};
 use anyhow::Result;
+use clap::Parser;
 use lib::file_extension;
 use lib::is_fg_file;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 fn download_index() -> Result<lib::Galaxy> {
     let path = std::path::Path::new("fg-index/test_index.json");
@@ -30,23 +31,34 @@ fn ask_yes_no(prompt: &str) -> bool {
     }
 }
 
+#[derive(Parser)]
+#[command(name = "fg-edit", about = "Edit a file as text")]
+struct Args {
+    file: PathBuf,
+    /// Edit one of the converter's named views instead of the default one
+    #[arg(long, value_name = "NAME")]
+    view: Option<String>,
+    /// Option passed to the converter, see `fg info` for the available ones
+    #[arg(short = 'o', long = "option", value_name = "KEY=VALUE", value_parser = lib::parse_option_arg, conflicts_with = "view")]
+    options: Vec<(String, String)>,
+}
+
 fn main() -> Result<()> {
+    let args = Args::parse();
     
-    let galaxy = download_index()?;
+    let mut registry = LocalRegistry::new(download_index()?, "fg-index/converters/");
+    let config = Config::load(Path::new(CONFIG_PATH))?;
 
-    // parse file name from command line
-    let args: Vec<_> = std::env::args().skip(1).collect();
-    let file_name = args.get(0).expect("You need to provide a file path");
-    let file_path = PathBuf::from(file_name);
+    let file_path = args.file;
     
-    let tmp_filename = format!("{}{}", file_name, ".tmp");
+    let tmp_filename = format!("{}{}", file_path.display(), ".tmp");
 
     let (file_type, store_in_container_format) = if file_path.is_file() {
         // check file type and whether it contains format_id
         let file_type = lib::get_file_type(&file_path)?;
         // print warning when using file that doesn't use the fmtgal container format
         if let FileType::Ext(_) = &file_type {
-            eprintln!("WARNING: The file doesn't use the format galaxy container format. The exact format of the file is not known and needs to be selected manually.")
+            eprintln!("WARNING: The file doesn't use the format galaxy container format. The exact format of the file is not known and is guessed from its content and extension.")
         }
         let store_in_container_format = matches!(&file_type, FileType::FormatId(_));
         (file_type, store_in_container_format)
@@ -60,42 +72,61 @@ fn main() -> Result<()> {
     };
 
 
-    // ask user to select a converter
-    let selection = match lib::select_plugin(&galaxy, &file_type) {
-        Some(x) => x,
-        None => {
-            return Ok(()); // selection was cancelled by user
-        },
+    // read file content
+    let content_bytes = match &file_type {
+        _ if !file_path.is_file() => vec!(),
+        FileType::Ext(_) => std::fs::read(&file_path)?,
+        FileType::FormatId(_) => lib::read_file(&file_path)?.1,
+    };
+    let candidates = match &file_type {
+        FileType::Ext(ext) => lib::detect_formats(&registry, &content_bytes, ext.as_deref()),
+        FileType::FormatId(_) => vec!(),
     };
 
-    let format = &galaxy.formats[&selection.format_id];
-    let converter = &format.converters[&selection.converter_id];
-    let converter_version = &converter.versions[selection.version_idx];
-    let converter_hash = converter_version.1.0.as_str();
+    // files of unknown formats can only be edited as raw bytes
+    let unknown_format = match &file_type {
+        FileType::FormatId(fid) if !registry.ensure_format(*fid, &config.fallback_indexes)? => Some(*fid),
+        _ => None,
+    };
 
-    // load plugin
+    // load plugin, the format id is kept when editing raw bytes
     // println!("Loading Plugin...");
-    let base_path = "fg-index/converters/";
-    let full_path: PathBuf = [base_path, &format!("{}{}", converter_hash, ".wasm")].iter().collect();
-    let mut plugin = WasmtimeGalaxyFormatPlugin::new(&full_path)?;
+    let mut options = lib::Options::new();
+    let (mut plugin, format_id) = if let Some(fid) = unknown_format {
+        if !lib::offer_raw_view(fid) {
+            return Ok(());
+        }
+        if let Some(view) = &args.view {
+            return Err(anyhow::anyhow!("The hex dump has no view '{}'", view));
+        }
+        if !args.options.is_empty() {
+            return Err(anyhow::anyhow!("The hex dump has no options"));
+        }
+        (registry.load_fallback()?, fid)
+    } else {
+        // ask user to select a converter
+        let selection = match lib::select_plugin(registry.galaxy(), &file_type, &candidates)? {
+            Some(x) => x,
+            None => {
+                return Ok(()); // selection was cancelled by user
+            },
+        };
+        if let Some(view) = &args.view {
+            registry.check_view(&selection, view)?;
+        }
+        options = registry.check_options(&selection, &args.options)?;
+        (registry.load(&selection)?, selection.format_id)
+    };
 
 
     // convert existing file
     if file_path.is_file() {
-        // read file content
-        let content_bytes = match file_type {
-            FileType::Ext(_) => {
-                std::fs::read(&file_path)?
-            }
-            FileType::FormatId(_) => {
-                let (format_id, bytes) = lib::read_file(&std::path::PathBuf::from(file_name))?;
-                assert_eq!(format_id, selection.format_id);
-                bytes
-            }
-        };
-
         // present
-        let s = match plugin.present(&content_bytes)? {
+        let res = match &args.view {
+            Some(view) => plugin.present_view(view, &content_bytes)?,
+            None => plugin.present_with_options(&content_bytes, &options)?,
+        };
+        let s = match res {
             Ok(s) => s,
             Err(e) => {
                 eprintln!("{}", e);
@@ -118,7 +149,11 @@ fn main() -> Result<()> {
         let s = std::fs::read_to_string(&tmp_filename).expect("Couldn't read tmp file");
 
         // store
-        match plugin.store(&s)? {
+        let res = match &args.view {
+            Some(view) => plugin.store_view(view, &s)?,
+            None => plugin.store_with_options(&s, &options)?,
+        };
+        match res {
             Ok(b) => {
                 break b;
             }
@@ -135,9 +170,9 @@ fn main() -> Result<()> {
 
 
     if store_in_container_format {
-        lib::write_file(&file_path, selection.format_id.clone(), &bytes).expect("Couldn't write result file");
+        lib::write_file(&file_path, format_id, &bytes).expect("Couldn't write result file");
     } else {
-        std::fs::write(&file_name, bytes).expect("Couldn't write result file (non-container)");
+        std::fs::write(&file_path, bytes).expect("Couldn't write result file (non-container)");
     }
 
     // delete tmp file
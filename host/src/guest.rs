@@ -0,0 +1,137 @@
+/* Host functions imported by wasm converters, see `format_galaxy_core::log`
+
+These are the `fg` imports of V1 modules, components get the same functions through the
+`host` interface of the converter world.
+
+Logged messages are written to stderr if their level is enabled by `FG_LOG` (`warn` by
+default). Panic messages are kept until the trap that follows them and replace its message.
+*/
+
+use anyhow::{anyhow, Result};
+use format_galaxy_core::log::{format_message, Level};
+use wasmtime::component::ResourceTable;
+use wasmtime::{Caller, Extern, Linker, Store};
+use wasmtime_wasi::preview1::WasiP1Ctx;
+use wasmtime_wasi::{IoView, WasiCtx, WasiView};
+
+pub const LOG_ENV: &str = "FG_LOG";
+
+/// The most verbose level of messages logged by converters that is shown.
+pub fn max_log_level() -> Option<Level> {
+    match std::env::var(LOG_ENV) {
+        Ok(s) => Level::parse_max(&s).unwrap_or_else(|e| {
+            eprintln!("WARNING: {}", e);
+            Some(Level::Warn)
+        }),
+        Err(_) => Some(Level::Warn),
+    }
+}
+
+/// State of a converter instance's store.
+pub struct Guest {
+    max_level: Option<Level>,
+    panic: Option<String>,
+    // WASI is only linked for converters that use it, see `wasi`
+    pub wasi_p1: Option<WasiP1Ctx>,
+    pub wasi: Option<WasiCtx>,
+    table: ResourceTable,
+}
+
+impl Guest {
+    pub fn new() -> Self {
+        Guest {
+            max_level: max_log_level(),
+            panic: None,
+            wasi_p1: None,
+            wasi: None,
+            table: ResourceTable::new(),
+        }
+    }
+
+    pub fn wasi_p1(&mut self) -> &mut WasiP1Ctx {
+        self.wasi_p1.as_mut().expect("WASI is only linked for converters using it")
+    }
+
+    pub fn log(&self, level: Level, msg: &str) {
+        if Some(level) <= self.max_level {
+            eprintln!("{}", format_message(level, msg));
+        }
+    }
+
+    /// Keeps the message of a panic for the trap that follows.
+    pub fn set_panic(&mut self, msg: String) {
+        self.panic = Some(msg);
+    }
+}
+
+fn read_guest_str(caller: &mut Caller<'_, Guest>, ptr: u32, len: u32) -> Option<String> {
+    let memory = caller.get_export("memory").and_then(Extern::into_memory)?;
+    let bytes = memory.data(&caller).get(ptr as usize..)?.get(..len as usize)?;
+    Some(String::from_utf8_lossy(bytes).into_owned())
+}
+
+impl IoView for Guest {
+    fn table(&mut self) -> &mut ResourceTable {
+        &mut self.table
+    }
+}
+
+impl WasiView for Guest {
+    fn ctx(&mut self) -> &mut WasiCtx {
+        self.wasi.as_mut().expect("WASI is only linked for converters using it")
+    }
+}
+
+pub fn add_to_linker(linker: &mut Linker<Guest>) -> Result<()> {
+    linker.func_wrap("fg", "log", |mut caller: Caller<'_, Guest>, level: u32, ptr: u32, len: u32| {
+        let level = Level::from_u32(level).unwrap_or(Level::Error);
+        let msg = read_guest_str(&mut caller, ptr, len)
+            .unwrap_or_else(|| "<message outside of memory>".to_string());
+        caller.data().log(level, &msg);
+    })?;
+    linker.func_wrap("fg", "panic", |mut caller: Caller<'_, Guest>, ptr: u32, len: u32| {
+        if let Some(msg) = read_guest_str(&mut caller, ptr, len) {
+            caller.data_mut().set_panic(msg);
+        }
+    })?;
+    Ok(())
+}
+
+/// Turns a trap following a panic of the converter into an error with the panic's message.
+pub fn guest_result<T>(res: Result<T>, store: &mut Store<Guest>) -> Result<T> {
+    let panic = store.data_mut().panic.take();
+    match (res, panic) {
+        (Ok(v), _) => Ok(v),
+        (Err(_trap), Some(msg)) => Err(anyhow!("Converter panicked: {}", msg)),
+        (Err(trap), None) => Err(trap),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::testing;
+    use fg_plugin::GalaxyFormatPluginV1_;
+
+    // a converter that reports a panic and traps in `present`, like the hook installed by `init` does
+    const PANICKING_ITEMS: &str = r#"
+        (import "fg" "log" (func $log (param i32 i32 i32)))
+        (import "fg" "panic" (func $panic (param i32 i32)))
+        (data (i32.const 16) "boom at src/lib.rs:1:1")
+        (data (i32.const 64) "initialized")
+        (func (export "init") (call $log (i32.const 4) (i32.const 64) (i32.const 11)))
+    "#;
+
+    #[test]
+    fn test_panic_message() {
+        let mut plugin = testing::stub_plugin(PANICKING_ITEMS, &[
+            ("present", "(call $panic (i32.const 16) (i32.const 22)) unreachable"),
+        ]);
+
+        let err = plugin.present(0, 0).unwrap_err();
+        assert_eq!(err.to_string(), "Converter panicked: boom at src/lib.rs:1:1");
+        // traps without a reported panic keep their message
+        let err = plugin.store(0, 0).unwrap_err();
+        assert!(format!("{:?}", err).contains("unreachable"), "{:?}", err);
+    }
+}
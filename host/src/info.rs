@@ -0,0 +1,134 @@
+/* Information about a container file, as shown by `fg info`
+*/
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use fg_index::{ConverterOption, WasiCapability};
+use serde::Serialize;
+
+use crate::config::{Config, Pin};
+use crate::{read_header, ConverterRegistry, FormatId, LocalRegistry, HEADER_LEN};
+
+#[derive(Serialize, Debug)]
+pub struct FileInfo {
+    pub path: PathBuf,
+    pub container_version: u32,
+    pub format_id: u64,
+    /// `None` if the format isn't part of the index.
+    pub format: Option<FormatInfo>,
+    pub payload_size: u64,
+    pub pinned: Option<Pin>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct FormatInfo {
+    pub name: String,
+    pub desc: String,
+    pub extensions: Vec<String>,
+    /// Sorted by id.
+    pub converters: Vec<ConverterInfo>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ConverterInfo {
+    pub id: u64,
+    pub name: String,
+    pub desc: String,
+    pub versions: Vec<VersionInfo>,
+    /// Named views besides the default one.
+    pub views: Vec<String>,
+    /// Whether the converter can present parts of a file.
+    pub windowed: bool,
+    /// Capabilities of the sandboxed WASI environment the converter gets.
+    pub wasi: Vec<WasiCapability>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct VersionInfo {
+    pub version: String,
+    pub hash: String,
+    pub options: Vec<ConverterOption>,
+    /// Whether the wasm module is available locally.
+    pub downloaded: bool,
+    /// Whether the compiled code of the module is cached.
+    pub compiled: bool,
+}
+
+pub fn file_info(registry: &LocalRegistry, config: &Config, path: &Path) -> Result<FileInfo> {
+    let header = read_header(path)?;
+    let format_id = FormatId(header.format_id);
+    let payload_size = std::fs::metadata(path)?.len().saturating_sub(HEADER_LEN);
+
+    let format = registry.format(format_id).ok().map(|format| {
+        let mut converters: Vec<_> = format.converters.iter()
+            .map(|(id, converter)| ConverterInfo {
+                id: id.0,
+                name: converter.name.clone(),
+                desc: converter.desc.clone(),
+                versions: converter.versions.iter()
+                    .map(|v| VersionInfo {
+                        version: v.version.clone(),
+                        hash: v.hash.0.clone(),
+                        options: v.options.clone(),
+                        downloaded: registry.plugin_path(&v.hash).is_file(),
+                        compiled: registry.is_compiled(&v.hash),
+                    })
+                    .collect(),
+                views: converter.views.clone(),
+                windowed: converter.windowed,
+                wasi: converter.wasi.clone(),
+            })
+            .collect();
+        converters.sort_by_key(|c| c.id);
+        FormatInfo {
+            name: format.name.clone(),
+            desc: format.desc.clone(),
+            extensions: format.extensions.clone(),
+            converters,
+        }
+    });
+
+    Ok(FileInfo {
+        path: path.to_path_buf(),
+        container_version: header.version(),
+        format_id: format_id.0,
+        format,
+        payload_size,
+        pinned: config.pinned.get(&format_id).cloned(),
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::write_file;
+    use fg_index::{ConverterId, FormatId, Galaxy};
+
+    #[test]
+    fn test_file_info() {
+        let path = std::env::temp_dir().join(format!("fg-info-test-{}.fg", std::process::id()));
+        let galaxy = Galaxy::from_json(Path::new("../fg-index/test_index.json")).unwrap();
+        let registry = LocalRegistry::new(galaxy, "../fg-index/converters/");
+        let mut config = Config::default();
+        config.pinned.insert(FormatId(2), Pin { converter: ConverterId(1100110012), version: None });
+
+        write_file(&path, FormatId(2), &[1, 2, 3]).unwrap();
+        let info = file_info(&registry, &config, &path).unwrap();
+        assert_eq!(info.payload_size, 3);
+        assert_eq!(info.pinned.as_ref().unwrap().converter, ConverterId(1100110012));
+        let format = info.format.unwrap();
+        assert_eq!(format.name, "Sequence of bytes");
+        assert_eq!(format.converters.iter().map(|c| c.id).collect::<Vec<_>>(), vec!(1100110011, 1100110012));
+        assert_eq!(format.converters[1].versions.len(), 2);
+        assert!(format.converters[0].versions[0].downloaded);
+
+        write_file(&path, FormatId(12345), &[]).unwrap();
+        let info = file_info(&registry, &config, &path).unwrap();
+        assert!(info.format.is_none());
+        assert!(info.pinned.is_none());
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
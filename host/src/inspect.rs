@@ -0,0 +1,435 @@
+/* Static inspection of converters, before they are instantiated
+
+Linking and looking up exports only fail with generic errors, so converters are checked against
+the ABI after compilation instead: the exports `GalaxyFormatPluginV1_` calls and their
+signatures, and the imports, which may only be the `fg` functions (see `guest`) and WASI functions
+of capabilities declared in the index (see `wasi`). Components are checked against the converter
+world.
+*/
+
+use std::fmt;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use fg_index::WasiCapability;
+use fg_plugin::is_component;
+use serde::Serialize;
+use wasmtime::component::types::ComponentItem;
+use wasmtime::component::Component;
+use wasmtime::{Engine, ExternType, FuncType, Module};
+
+use crate::wasi;
+
+// signatures of the calls taking input bytes and returning a `ReturnData` pointer
+const CALL: &str = "(func (param i32 i32) (result i32))";
+
+const REQUIRED_FUNCS: &[(&str, &str)] = &[
+    ("present", CALL),
+    ("store", CALL),
+    ("alloc", "(func (param i32) (result i32))"),
+    ("free", "(func (param i32))"),
+    ("result_get_ptr", "(func (param i32) (result i32))"),
+    ("result_get_len", "(func (param i32) (result i32))"),
+    ("result_get_success", "(func (param i32) (result i32))"),
+];
+
+const OPTIONAL_FUNCS: &[(&str, &str)] = &[
+    ("present_styled", CALL),
+    ("sniff", CALL),
+    ("present_window", CALL),
+    ("present_view", CALL),
+    ("store_view", CALL),
+    ("present_with_options", CALL),
+    ("present_styled_with_options", CALL),
+    ("store_with_options", CALL),
+    ("result_get_location", "(func (param i32) (result i32))"),
+    ("init", "(func)"),
+    ("_initialize", "(func)"),
+];
+
+// optional parts of the ABI and the exports they need
+const FEATURES: &[(&str, &[&str])] = &[
+    ("styled", &["present_styled"]),
+    ("sniff", &["sniff"]),
+    ("windows", &["present_window"]),
+    ("views", &["present_view", "store_view"]),
+    ("options", &["present_with_options", "present_styled_with_options", "store_with_options"]),
+    ("locations", &["result_get_location"]),
+];
+
+const HOST_MODULE: &str = "fg";
+
+const HOST_FUNCS: &[(&str, &str)] = &[
+    ("log", "(func (param i32 i32 i32))"),
+    ("panic", "(func (param i32 i32))"),
+];
+
+const HOST_INTERFACE: &str = "format-galaxy:converter/host";
+
+const API_INTERFACE: &str = "format-galaxy:converter/api";
+
+const API_FUNCS: &[&str] = &[
+    "describe", "present", "present-styled", "store-text", "sniff", "present-window", "present-view", "store-view"
+];
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConverterKind {
+    /// A core module implementing the V1 ABI.
+    Module,
+    /// A component implementing the converter world.
+    Component,
+}
+
+/// An import or export and its type, e.g. `(func (param i32) (result i32))` or `memory`.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct Item {
+    pub name: String,
+    pub ty: String,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum Diagnostic {
+    MissingExport { name: String },
+    WrongSignature { name: String, expected: String, found: String },
+    UnexpectedImport { name: String },
+    UndeclaredWasi { name: String, capability: WasiCapability },
+    /// Only some of the exports of an optional part of the ABI, which hosts don't use then.
+    IncompleteFeature { feature: String, missing: Vec<String> },
+}
+
+impl Diagnostic {
+    /// Whether the converter can't be used at all, otherwise it's a warning.
+    pub fn is_error(&self) -> bool {
+        !matches!(self, Diagnostic::IncompleteFeature { .. })
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Diagnostic::MissingExport { name } => write!(f, "missing export `{}`", name),
+            Diagnostic::WrongSignature { name, expected, found } =>
+                write!(f, "`{}` has the wrong type, expected `{}`, found `{}`", name, expected, found),
+            Diagnostic::UnexpectedImport { name } =>
+                write!(f, "unexpected import `{}`, converters can only import the `fg` functions and parts of WASI", name),
+            Diagnostic::UndeclaredWasi { name, capability } =>
+                write!(f, "import `{}` needs the WASI capability {}, which isn't declared in the index", name, capability.name()),
+            Diagnostic::IncompleteFeature { feature, missing } =>
+                write!(f, "{} are unavailable, the exports `{}` are missing", feature, missing.join("`, `")),
+        }
+    }
+}
+
+/// What a converter imports and exports and how it deviates from the ABI.
+#[derive(Serialize, Debug)]
+pub struct Inspection {
+    pub kind: ConverterKind,
+    pub imports: Vec<Item>,
+    pub exports: Vec<Item>,
+    /// The WASI capabilities the imports need.
+    pub wasi: Vec<WasiCapability>,
+    /// The optional parts of the ABI the converter implements, e.g. `sniff` or `views`.
+    pub features: Vec<String>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Inspection {
+    fn new(kind: ConverterKind) -> Self {
+        Inspection {
+            kind,
+            imports: vec!(),
+            exports: vec!(),
+            wasi: vec!(),
+            features: vec!(),
+            diagnostics: vec!(),
+        }
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.diagnostics.iter().filter(|d| d.is_error())
+    }
+
+    /// Fails with all errors if the converter can't be used.
+    pub fn check(&self) -> Result<()> {
+        let errors: Vec<String> = self.errors().map(|d| d.to_string()).collect();
+        if errors.is_empty() {
+            return Ok(());
+        }
+        Err(anyhow!("The converter doesn't implement the converter ABI: {}", errors.join("; ")))
+    }
+
+    fn wasi_import(&mut self, name: String, capability: Option<WasiCapability>, declared: &[WasiCapability]) {
+        match capability {
+            Some(capability) => {
+                if !self.wasi.contains(&capability) {
+                    self.wasi.push(capability);
+                }
+                if !declared.contains(&capability) {
+                    self.diagnostics.push(Diagnostic::UndeclaredWasi { name, capability });
+                }
+            }
+            None => self.diagnostics.push(Diagnostic::UnexpectedImport { name }),
+        }
+    }
+}
+
+/// The type in the notation of the text format, e.g. `(func (param i32 i32) (result i32))`.
+pub fn signature(ty: &FuncType) -> String {
+    let mut s = "(func".to_string();
+    if ty.params().len() > 0 {
+        s.push_str(" (param");
+        for param in ty.params() {
+            s.push_str(&format!(" {}", param));
+        }
+        s.push(')');
+    }
+    if ty.results().len() > 0 {
+        s.push_str(" (result");
+        for result in ty.results() {
+            s.push_str(&format!(" {}", result));
+        }
+        s.push(')');
+    }
+    s.push(')');
+    s
+}
+
+fn type_name(ty: &ExternType) -> String {
+    match ty {
+        ExternType::Func(f) => signature(f),
+        ExternType::Global(_) => "global".to_string(),
+        ExternType::Table(_) => "table".to_string(),
+        ExternType::Memory(_) => "memory".to_string(),
+    }
+}
+
+// adds a diagnostic if `ty` isn't the `expected` function
+fn check_func(diagnostics: &mut Vec<Diagnostic>, name: &str, ty: &ExternType, expected: &str) {
+    let found = type_name(ty);
+    if found != expected {
+        diagnostics.push(Diagnostic::WrongSignature {
+            name: name.to_string(),
+            expected: expected.to_string(),
+            found,
+        });
+    }
+}
+
+/// Checks a compiled core module against the V1 ABI, it may import the WASI functions of the
+/// `declared` capabilities.
+pub fn inspect_module(module: &Module, declared: &[WasiCapability]) -> Inspection {
+    let mut inspection = Inspection::new(ConverterKind::Module);
+
+    for import in module.imports() {
+        let name = format!("{}::{}", import.module(), import.name());
+        let ty = import.ty();
+        match import.module() {
+            HOST_MODULE => match HOST_FUNCS.iter().find(|(func, _)| *func == import.name()) {
+                Some((_, expected)) => check_func(&mut inspection.diagnostics, &name, &ty, expected),
+                None => inspection.diagnostics.push(Diagnostic::UnexpectedImport { name: name.clone() }),
+            },
+            wasi::PREVIEW1_MODULE => {
+                inspection.wasi_import(name.clone(), wasi::preview1_capability(import.name()), declared);
+            }
+            _ => inspection.diagnostics.push(Diagnostic::UnexpectedImport { name: name.clone() }),
+        }
+        inspection.imports.push(Item { name, ty: type_name(&ty) });
+    }
+
+    for export in module.exports() {
+        inspection.exports.push(Item { name: export.name().to_string(), ty: type_name(&export.ty()) });
+    }
+
+    match module.get_export("memory") {
+        Some(ExternType::Memory(_)) => {}
+        Some(ty) => inspection.diagnostics.push(Diagnostic::WrongSignature {
+            name: "memory".to_string(),
+            expected: "memory".to_string(),
+            found: type_name(&ty),
+        }),
+        None => inspection.diagnostics.push(Diagnostic::MissingExport { name: "memory".to_string() }),
+    }
+    for (name, expected) in REQUIRED_FUNCS {
+        match module.get_export(name) {
+            Some(ty) => check_func(&mut inspection.diagnostics, name, &ty, expected),
+            None => inspection.diagnostics.push(Diagnostic::MissingExport { name: name.to_string() }),
+        }
+    }
+    for (name, expected) in OPTIONAL_FUNCS {
+        if let Some(ty) = module.get_export(name) {
+            check_func(&mut inspection.diagnostics, name, &ty, expected);
+        }
+    }
+
+    for (feature, exports) in FEATURES {
+        let missing: Vec<String> = exports.iter()
+            .filter(|name| module.get_export(name).is_none())
+            .map(|name| name.to_string())
+            .collect();
+        if missing.is_empty() {
+            inspection.features.push(feature.to_string());
+        } else if missing.len() < exports.len() {
+            inspection.diagnostics.push(Diagnostic::IncompleteFeature { feature: feature.to_string(), missing });
+        }
+    }
+
+    inspection
+}
+
+fn item_name(item: &ComponentItem) -> &'static str {
+    match item {
+        ComponentItem::ComponentFunc(_) => "func",
+        ComponentItem::CoreFunc(_) => "core func",
+        ComponentItem::Module(_) => "module",
+        ComponentItem::Component(_) => "component",
+        ComponentItem::ComponentInstance(_) => "instance",
+        ComponentItem::Type(_) => "type",
+        ComponentItem::Resource(_) => "resource",
+    }
+}
+
+/// Checks a compiled component against the converter world, it may import the WASI interfaces
+/// of the `declared` capabilities.
+///
+/// The types of the world's functions are checked when the component is linked.
+pub fn inspect_component(engine: &Engine, component: &Component, declared: &[WasiCapability]) -> Inspection {
+    let mut inspection = Inspection::new(ConverterKind::Component);
+    let ty = component.component_type();
+
+    for (name, item) in ty.imports(engine) {
+        if name.starts_with("wasi:") {
+            inspection.wasi_import(name.to_string(), wasi::preview2_capability(name), declared);
+        } else if name.split('@').next() != Some(HOST_INTERFACE) {
+            inspection.diagnostics.push(Diagnostic::UnexpectedImport { name: name.to_string() });
+        }
+        inspection.imports.push(Item { name: name.to_string(), ty: item_name(&item).to_string() });
+    }
+
+    let mut api = None;
+    for (name, item) in ty.exports(engine) {
+        inspection.exports.push(Item { name: name.to_string(), ty: item_name(&item).to_string() });
+        if name.split('@').next() == Some(API_INTERFACE) {
+            api = Some((name, item));
+        }
+    }
+    // the functions are listed as `interface#function`, like `wasm-tools` prints them
+    let api = match api {
+        Some((name, ComponentItem::ComponentInstance(instance))) => {
+            for (func, item) in instance.exports(engine) {
+                inspection.exports.push(Item { name: format!("{}#{}", name, func), ty: item_name(&item).to_string() });
+            }
+            name
+        }
+        Some((name, item)) => {
+            inspection.diagnostics.push(Diagnostic::WrongSignature {
+                name: name.to_string(),
+                expected: "instance".to_string(),
+                found: item_name(&item).to_string(),
+            });
+            return inspection;
+        }
+        None => {
+            inspection.diagnostics.push(Diagnostic::MissingExport { name: API_INTERFACE.to_string() });
+            return inspection;
+        }
+    };
+    for func in API_FUNCS {
+        let name = format!("{}#{}", api, func);
+        match inspection.exports.iter().find(|export| export.name == name) {
+            Some(export) if export.ty != "func" => inspection.diagnostics.push(Diagnostic::WrongSignature {
+                name,
+                expected: "func".to_string(),
+                found: export.ty.clone(),
+            }),
+            Some(_) => {}
+            None => inspection.diagnostics.push(Diagnostic::MissingExport { name }),
+        }
+    }
+
+    inspection
+}
+
+/// Compiles the converter (module or component) at `path` and inspects it.
+pub fn inspect_file(engine: &Engine, path: &Path, declared: &[WasiCapability]) -> Result<Inspection> {
+    let bytes = std::fs::read(path)?;
+    if is_component(&bytes) {
+        Ok(inspect_component(engine, &Component::new(engine, &bytes)?, declared))
+    } else {
+        Ok(inspect_module(&Module::new(engine, &bytes)?, declared))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inspect(wat: &str, declared: &[WasiCapability]) -> Inspection {
+        inspect_module(&Module::new(&Engine::default(), wat).unwrap(), declared)
+    }
+
+    // everything but `result_get_len`, `free` takes no argument
+    const BROKEN_WAT: &str = r#"
+        (module
+            (import "fg" "log" (func (param i32 i32 i32)))
+            (import "env" "print_alloc" (func (param i32 i32 i32)))
+            (import "wasi_snapshot_preview1" "random_get" (func (param i32 i32) (result i32)))
+            (memory (export "memory") 1)
+            (func (export "present") (param i32 i32) (result i32) (i32.const 0))
+            (func (export "store") (param i32 i32) (result i32) (i32.const 0))
+            (func (export "present_view") (param i32 i32) (result i32) (i32.const 0))
+            (func (export "sniff") (param i32 i32) (result i32) (i32.const 0))
+            (func (export "alloc") (param i32) (result i32) (i32.const 0))
+            (func (export "free"))
+            (func (export "result_get_ptr") (param i32) (result i32) (i32.const 0))
+            (func (export "result_get_success") (param i32) (result i32) (i32.const 0)))
+    "#;
+
+    #[test]
+    fn test_inspect_module() {
+        let inspection = inspect(BROKEN_WAT, &[]);
+        assert_eq!(inspection.kind, ConverterKind::Module);
+        assert_eq!(inspection.wasi, vec!(WasiCapability::Random));
+        assert_eq!(inspection.features, vec!("sniff".to_string()));
+        assert_eq!(inspection.imports[0], Item { name: "fg::log".to_string(), ty: "(func (param i32 i32 i32))".to_string() });
+        assert_eq!(inspection.diagnostics, vec!(
+            Diagnostic::UnexpectedImport { name: "env::print_alloc".to_string() },
+            Diagnostic::UndeclaredWasi { name: "wasi_snapshot_preview1::random_get".to_string(), capability: WasiCapability::Random },
+            Diagnostic::WrongSignature { name: "free".to_string(), expected: "(func (param i32))".to_string(), found: "(func)".to_string() },
+            Diagnostic::MissingExport { name: "result_get_len".to_string() },
+            Diagnostic::IncompleteFeature { feature: "views".to_string(), missing: vec!("store_view".to_string()) },
+        ));
+        assert_eq!(inspection.errors().count(), 4);
+
+        let err = inspection.check().unwrap_err().to_string();
+        assert!(err.contains("missing export `result_get_len`"), "{}", err);
+        assert!(!err.contains("views"), "{}", err);
+
+        // declaring the capability leaves the other errors
+        let inspection = inspect(BROKEN_WAT, &[WasiCapability::Random]);
+        assert_eq!(inspection.errors().count(), 3);
+    }
+
+    #[test]
+    fn test_inspect_converter() {
+        // converters only export the optional features they implement, json-like 0.1.5 doesn't
+        // sniff and has no windows or views
+        let path = "../fg-index/converters/97f062244cdd588850b77b89b186543477101b1de3d03e86024b1d03633dd17b.wasm";
+        let inspection = inspect_module(&Module::from_file(&Engine::default(), path).unwrap(), &[]);
+        assert_eq!(inspection.features, vec!("styled", "options", "locations"));
+        assert!(inspection.check().is_ok());
+    }
+
+    #[test]
+    fn test_inspect_no_memory() {
+        let inspection = inspect("(module (global (export \"memory\") i32 (i32.const 0)))", &[]);
+        assert!(inspection.diagnostics.contains(&Diagnostic::WrongSignature {
+            name: "memory".to_string(),
+            expected: "memory".to_string(),
+            found: "global".to_string(),
+        }));
+        assert_eq!(inspection.errors().count(), 1 + REQUIRED_FUNCS.len());
+    }
+}
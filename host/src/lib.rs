@@ -1,99 +1,191 @@
-use fg_index::FormatId;
-pub use fg_index::Galaxy;
-use std::{io::{Read, Write}, path::Path};
-pub use fg_plugin::GalaxyFormatPluginV1;
-use fg_plugin::GalaxyFormatPluginV1_;
+pub use fg_container::{ContainerError, Header};
+pub use fg_index::{ConverterId, FormatId, Galaxy};
+use std::{collections::HashMap, io::{Read, Write}, path::{Path, PathBuf}};
+pub use fg_plugin::{GalaxyFormatPluginV1, MemoryAccess, MemoryAccessError, Options};
+use fg_plugin::{memory_range, GalaxyFormatPluginV1_, OPTIONAL_CALLS};
 
 use anyhow::Result;
 use wasmtime::*;
+use guest::{guest_result, Guest};
 
+pub mod ansi;
+pub mod batch;
+mod component;
+pub mod config;
+mod detect;
+mod guest;
+pub mod info;
+pub mod inspect;
+mod native;
+pub mod pager;
+mod pool;
+mod registry;
 mod select;
+#[cfg(test)]
+mod testing;
+pub mod wasi;
+pub mod window;
+pub mod wrap;
 
+pub use component::{
+    WasmtimeComponentPlugin, WasmtimeConverterComponent
+};
+pub use detect::{
+    FormatCandidate, MAX_SNIFF_LEN, detect_formats
+};
+pub use native::NativeGalaxyFormatPlugin;
+pub use pool::{
+    PooledPlugin, WasmtimePluginPool
+};
+pub use registry::{
+    ConverterQuery, ConverterRegistry, FALLBACK_CONVERTER_ID, LocalRegistry, parse_option_arg
+};
 pub use select::{
-    ConverterSelection, select_plugin
+    ConverterSelection, offer_raw_view, select_plugin
 };
 
 
 pub struct WasmtimeGalaxyFormatPlugin {
     memory: Memory,
-    store: Store<()>,
+    store: Store<Guest>,
     present_fn: TypedFunc<(u32, u32), u32>,
     store_fn: TypedFunc<(u32, u32), u32>,
+    // the exports of `OPTIONAL_CALLS` the module has
+    optional_fns: HashMap<&'static str, TypedFunc<(u32, u32), u32>>,
     alloc_fn: TypedFunc<u32, u32>,
     free_fn: TypedFunc<u32, ()>,
     result_get_ptr_fn: TypedFunc<u32, u32>,
     result_get_len_fn: TypedFunc<u32, u32>,
     result_get_success_fn: TypedFunc<u32, u32>,
+    result_get_location_fn: Option<TypedFunc<u32, u32>>,
 }
 
 impl GalaxyFormatPluginV1_ for WasmtimeGalaxyFormatPlugin {
     fn alloc(&mut self, size: u32) -> Result<u32> {
-        Ok(self.alloc_fn.call(&mut self.store, size)?)
+        guest_result(self.alloc_fn.call(&mut self.store, size), &mut self.store)
     }
 
     fn free(&mut self, ptr: u32) -> Result<()> {
-        Ok(self.free_fn.call(&mut self.store, ptr)?)
+        guest_result(self.free_fn.call(&mut self.store, ptr), &mut self.store)
     }
 
     fn present(&mut self, ptr: u32, size: u32) -> Result<u32> {
-        Ok(self.present_fn.call(&mut self.store, (ptr, size))?)
+        guest_result(self.present_fn.call(&mut self.store, (ptr, size)), &mut self.store)
     }
     
     fn store(&mut self, ptr: u32, size: u32) -> Result<u32> {
-        Ok(self.store_fn.call(&mut self.store, (ptr, size))?)
+        guest_result(self.store_fn.call(&mut self.store, (ptr, size)), &mut self.store)
+    }
+
+    fn has_optional(&self, name: &str) -> bool {
+        self.optional_fns.contains_key(name)
+    }
+
+    fn call_optional(&mut self, name: &str, ptr: u32, size: u32) -> Result<u32> {
+        let f = self.optional_fns.get(name).ok_or_else(|| anyhow::anyhow!("The plugin doesn't export `{}`", name))?;
+        guest_result(f.call(&mut self.store, (ptr, size)), &mut self.store)
     }
     
     fn result_get_ptr(&mut self, res_ptr: u32) -> Result<u32> {
-        Ok(self.result_get_ptr_fn.call(&mut self.store, res_ptr)?)
+        guest_result(self.result_get_ptr_fn.call(&mut self.store, res_ptr), &mut self.store)
     }
     
     fn result_get_len(&mut self, res_ptr: u32) -> Result<u32> {
-        Ok(self.result_get_len_fn.call(&mut self.store, res_ptr)?)
+        guest_result(self.result_get_len_fn.call(&mut self.store, res_ptr), &mut self.store)
     }
     
     fn result_get_success(&mut self, res_ptr: u32) -> Result<bool> {
-        Ok(self.result_get_success_fn.call(&mut self.store, res_ptr)? > 0)
+        Ok(guest_result(self.result_get_success_fn.call(&mut self.store, res_ptr), &mut self.store)? > 0)
+    }
+
+    fn has_location(&self) -> bool {
+        self.result_get_location_fn.is_some()
+    }
+
+    fn result_get_location(&mut self, res_ptr: u32) -> Result<u32> {
+        match &self.result_get_location_fn {
+            Some(result_get_location_fn) => guest_result(result_get_location_fn.call(&mut self.store, res_ptr), &mut self.store),
+            None => Err(anyhow::anyhow!("The plugin doesn't export `result_get_location`")),
+        }
     }
     
     
     fn memory_write(&mut self, ptr: u32, bytes: &[u8]) -> Result<()> {
-        self.memory.data_mut(&mut self.store)[ptr as usize .. ptr as usize + bytes.len()].clone_from_slice(bytes);
+        let data = self.memory.data_mut(&mut self.store);
+        let range = memory_range(MemoryAccess::Write, ptr, bytes.len(), data.len())?;
+        data[range].copy_from_slice(bytes);
         Ok(())
     }
     
     fn memory_read(&mut self, ptr: u32, len: u32) -> Result<Vec<u8>> {
-        let s_slice = &self.memory.data(&mut self.store)[ptr as usize..][..len as usize];
-        //let s_slice = &self.memory.data_unchecked()[x.ptr as usize..][..x.len as usize];
-        Ok(s_slice.to_vec())
+        let data = self.memory.data(&self.store);
+        let range = memory_range(MemoryAccess::Read, ptr, len as usize, data.len())?;
+        Ok(data[range].to_vec())
     }
 }
 
 //static mut COUNTER: i32 = 0;
 
-impl WasmtimeGalaxyFormatPlugin {
+const COMPILED_CACHE_DIR: &str = "cache/compiled/";
 
-    pub fn new(path: &Path) -> Result<Self> {
-        let engine = Engine::default();
-        let mut store = Store::new(&engine, ());
+/// A compiled converter module, ready to be instantiated.
+///
+/// Cloning is cheap, all clones share the compiled code. Use this (or a `WasmtimePluginPool`)
+/// instead of `WasmtimeGalaxyFormatPlugin::new` when creating many instances of the same converter.
+#[derive(Clone)]
+pub struct WasmtimeConverterModule {
+    engine: Engine,
+    instance_pre: InstancePre<Guest>,
+    // whether the module imports WASI functions
+    wasi: bool,
+}
+
+impl WasmtimeConverterModule {
+    /// Compiles the module at `path`, which must not import WASI functions.
+    pub fn new(engine: &Engine, path: &Path) -> Result<Self> {
+        Self::with_wasi(engine, path, &[])
+    }
 
+    /// Compiles the module at `path`, which may import the WASI functions of the capabilities
+    /// declared for it in the index.
+    pub fn with_wasi(engine: &Engine, path: &Path, declared: &[fg_index::WasiCapability]) -> Result<Self> {
         let bytes = std::fs::read(path)?;
-        let hash = blake3::hash(&bytes);
-        let module = if let Some(module) = Self::try_load_from_cache(&hash, &engine) {
+        let cache_path = Self::cache_path(&bytes);
+        let module = if let Some(module) = Self::try_load_from_cache(&cache_path, engine) {
             // println!("using cached module");
             module
         } else {
             // println!("cache miss. compiling module");
-            let module = Module::from_file(&engine, path)?;
+            let module = Module::new(engine, &bytes)?;
             // println!("caching module");
             let serialized = module.serialize()?;
-            std::fs::create_dir_all("cache/compiled/")?;
-            std::fs::write(format!("cache/compiled/{}", hash.to_hex()), &serialized)?;
+            std::fs::create_dir_all(COMPILED_CACHE_DIR)?;
+            std::fs::write(&cache_path, &serialized)?;
             module
         };
+        Self::from_module(engine, &module, declared)
+    }
+
+    /// Compiles the module `bytes` (binary or text format) like `with_wasi`, but without caching
+    /// the compiled code.
+    pub fn from_bytes(engine: &Engine, bytes: &[u8], declared: &[fg_index::WasiCapability]) -> Result<Self> {
+        Self::from_module(engine, &Module::new(engine, bytes)?, declared)
+    }
+
+    fn from_module(engine: &Engine, module: &Module, declared: &[fg_index::WasiCapability]) -> Result<Self> {
+        // fails with precise diagnostics instead of linking errors
+        let inspection = inspect::inspect_module(module, declared);
+        inspection.check()?;
+
+        let mut linker = Linker::new(engine);
+        guest::add_to_linker(&mut linker)?;
+        if !inspection.wasi.is_empty() {
+            wasmtime_wasi::preview1::add_to_linker_sync(&mut linker, Guest::wasi_p1)?;
+        }
 
         // uncomment to track allocations
         /*
-        let print_alloc = Func::wrap(&store, |is_alloc: u32, ptr: u32, size: u32| {
+        linker.func_wrap("env", "print_alloc", |is_alloc: u32, ptr: u32, size: u32| {
             unsafe {
                 if is_alloc>0 {
                     COUNTER += size as i32;
@@ -102,36 +194,106 @@ impl WasmtimeGalaxyFormatPlugin {
                 }
                 println!("{}: {} ({}) --- {}", if is_alloc>0 { "Alloc" } else { "Dealloc" }, ptr, size, COUNTER);
             }
-        });
+        })?;
         */
-        
-        let instance = Instance::new(&mut store, &module, &[/*print_alloc.into()*/])?;
+
+        let instance_pre = linker.instantiate_pre(module)?;
+
+        Ok(WasmtimeConverterModule {
+            engine: engine.clone(),
+            instance_pre,
+            wasi: !inspection.wasi.is_empty(),
+        })
+    }
+
+    pub fn instantiate(&self) -> Result<WasmtimeGalaxyFormatPlugin> {
+        let mut guest = Guest::new();
+        if self.wasi {
+            guest.wasi_p1 = Some(wasi::sandbox().build_p1());
+        }
+        let mut store = Store::new(&self.engine, guest);
+        let instance = self.instance_pre.instantiate(&mut store)?;
+
+        // runs the static constructors of modules built for WASI
+        if let Some(initialize_fn) = instance.get_func(&mut store, "_initialize") {
+            let initialize_fn = initialize_fn.typed::<(), ()>(&store)?;
+            guest_result(initialize_fn.call(&mut store, ()), &mut store)?;
+        }
+        // installs the converter's panic hook
+        if let Some(init_fn) = instance.get_func(&mut store, "init") {
+            let init_fn = init_fn.typed::<(), ()>(&store)?;
+            guest_result(init_fn.call(&mut store, ()), &mut store)?;
+        }
     
         let memory = instance
             .get_memory(&mut store, "memory")
             .ok_or(anyhow::format_err!("failed to find `memory` export"))?;
+
+        // optional exports
+        let mut optional_fns = HashMap::new();
+        for name in OPTIONAL_CALLS {
+            if let Some(f) = instance.get_func(&mut store, name) {
+                optional_fns.insert(*name, f.typed(&store)?);
+            }
+        }
+        let result_get_location_fn = match instance.get_func(&mut store, "result_get_location") {
+            Some(f) => Some(f.typed(&store)?),
+            None => None,
+        };
         
         Ok(WasmtimeGalaxyFormatPlugin {
             memory, 
             present_fn: instance.get_typed_func(&mut store, "present")?,
             store_fn: instance.get_typed_func(&mut store, "store")?,
+            optional_fns,
             alloc_fn: instance.get_typed_func(&mut store, "alloc")?,
             free_fn: instance.get_typed_func(&mut store, "free")?,
             result_get_ptr_fn: instance.get_typed_func(&mut store, "result_get_ptr")?,
             result_get_len_fn: instance.get_typed_func(&mut store, "result_get_len")?,
             result_get_success_fn: instance.get_typed_func(&mut store, "result_get_success")?,
+            result_get_location_fn,
             store,
         })
     }
 
-    fn try_load_from_cache(hash: &blake3::Hash, engine: &Engine) -> Option<Module> {
-        std::fs::read(format!("cache/{}", hash.to_hex()))
+    /// Whether the module imports WASI functions, its instances get a sandboxed WASI environment.
+    pub fn imports_wasi(&self) -> bool {
+        self.wasi
+    }
+
+    /// Where the compiled code of the module `wasm_bytes` is cached.
+    pub fn cache_path(wasm_bytes: &[u8]) -> PathBuf {
+        Path::new(COMPILED_CACHE_DIR).join(blake3::hash(wasm_bytes).to_hex().as_str())
+    }
+
+    fn try_load_from_cache(cache_path: &Path, engine: &Engine) -> Option<Module> {
+        std::fs::read(cache_path)
             .ok()
-            .and_then(|serialized| unsafe { Module::deserialize(&engine, &serialized).ok() } )
+            .and_then(|serialized| unsafe { Module::deserialize(engine, &serialized).ok() } )
     }
 }
 
-static PRELUDE: &[u8; 8] = b"FMTGALv1";
+impl WasmtimeGalaxyFormatPlugin {
+
+    pub fn new(path: &Path) -> Result<Self> {
+        WasmtimeConverterModule::new(&Engine::default(), path)?.instantiate()
+    }
+
+    /// Instantiates the module `bytes` (binary or text format) without caching its compiled code.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        WasmtimeConverterModule::from_bytes(&Engine::default(), bytes, &[])?.instantiate()
+    }
+
+    /// Size of the instance's linear memory in bytes.
+    pub fn memory_size(&self) -> usize {
+        self.memory.data_size(&self.store)
+    }
+}
+
+/// Version of the container format written by `write_file`, see `fg_container`.
+pub const CONTAINER_VERSION: u32 = fg_container::VERSION;
+/// Size of the header (prelude and format id) in front of the payload.
+pub const HEADER_LEN: u64 = fg_container::HEADER_LEN as u64;
 
 pub fn read_format_id(path: &Path) -> Result<FormatId> {
     let mut f = std::fs::File::open(path)?;
@@ -146,22 +308,26 @@ pub fn read_file(path: &Path) -> Result<(FormatId, Vec<u8>)> {
     Ok((format_id, bytes))
 }
 
+/// The header of the container at `path`, e.g. for its version.
+pub fn read_header(path: &Path) -> Result<Header> {
+    let mut f = std::fs::File::open(path)?;
+    parse_header(&mut f)
+}
+
 fn parse_format_id<R: Read>(reader: &mut R) -> Result<FormatId> {
-    let mut buf = [0u8; 8];
-    reader.read_exact(&mut buf)?;
-    if buf != *PRELUDE {
-        return Err(anyhow::anyhow!("Invalid prelude!"));
-    }
-    reader.read_exact(&mut buf)?;
-    let format_id = FormatId(u64::from_le_bytes(buf));
-    Ok(format_id)
+    Ok(FormatId(parse_header(reader)?.format_id))
+}
+
+fn parse_header<R: Read>(reader: &mut R) -> Result<Header> {
+    let mut header = vec!();
+    reader.take(HEADER_LEN).read_to_end(&mut header)?;
+    Ok(Header::decode(&header)?)
 }
 
 pub fn write_file(path: &Path, format_id: FormatId, bytes: &[u8]) -> Result<()> {
     let mut f = std::fs::File::create(path)?;
-    f.write(PRELUDE)?;
-    f.write(&format_id.0.to_le_bytes())?;
-    f.write(bytes)?;
+    f.write_all(&Header::new(format_id.0).encode())?;
+    f.write_all(bytes)?;
     Ok(())
 }
 
@@ -187,4 +353,76 @@ pub fn is_fg_file(path: &Path) -> bool {
 pub fn file_extension(path: &Path) -> Option<&str> {
     path.extension()
         .and_then(|s| s.to_str())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fg_plugin::StoreError;
+
+    // a converter returning whatever pointers and lengths it's told to, `alloc` returns `alloc`,
+    // the result is `len` bytes at `ptr`
+    fn bogus_plugin(alloc: u32, ptr: u32, len: u32) -> WasmtimeGalaxyFormatPlugin {
+        let alloc = format!("(i32.const {})", alloc);
+        let ptr = format!("(i32.const {})", ptr);
+        let len = format!("(i32.const {})", len);
+        testing::stub_plugin("", &[
+            ("present", "(i32.const 16)"),
+            ("alloc", &alloc),
+            ("result_get_ptr", &ptr),
+            ("result_get_len", &len),
+            ("result_get_success", "(i32.const 1)"),
+        ])
+    }
+
+    fn present(plugin: &mut WasmtimeGalaxyFormatPlugin) -> Result<Result<String, String>> {
+        GalaxyFormatPluginV1::present(plugin, b"hello")
+    }
+
+    fn memory_error(res: Result<Result<String, String>>) -> MemoryAccessError {
+        res.unwrap_err().downcast::<MemoryAccessError>().unwrap()
+    }
+
+    #[test]
+    fn test_memory_bounds() {
+        // sane pointers work
+        let mut plugin = bogus_plugin(0, 0, 5);
+        assert_eq!(present(&mut plugin).unwrap(), Ok("hello".to_string()));
+        // the end of memory is in bounds
+        let mut plugin = bogus_plugin(65531, 65531, 5);
+        assert_eq!(present(&mut plugin).unwrap(), Ok("hello".to_string()));
+
+        // input that doesn't fit where `alloc` put it
+        let mut plugin = bogus_plugin(65532, 0, 0);
+        let err = memory_error(present(&mut plugin));
+        assert_eq!(err, MemoryAccessError { access: MemoryAccess::Write, ptr: 65532, len: 5, memory_size: 65536 });
+
+        // results outside of memory
+        let mut plugin = bogus_plugin(0, 0xffff_fff0, 32);
+        assert_eq!(memory_error(present(&mut plugin)).ptr, 0xffff_fff0);
+        let mut plugin = bogus_plugin(0, 16, 0xffff_ffff);
+        let err = memory_error(present(&mut plugin));
+        assert_eq!((err.access, err.len), (MemoryAccess::Read, 0xffff_ffff));
+        assert!(err.to_string().contains("outside of the converter's 65536 bytes of memory"), "{}", err);
+    }
+
+    #[test]
+    fn test_store_location() {
+        // the error message is the input, located at offset 1 with length 3
+        let location = r#"
+            (func (export "result_get_location") (param i32) (result i32) (i32.const 64))
+            (data (i32.const 64) "\01\00\00\00\03\00\00\00")
+        "#;
+        let bodies = [("store", "(i32.const 16)"), ("result_get_len", "(i32.const 5)")];
+        let mut plugin = testing::stub_plugin(location, &bodies);
+        let err = plugin.store_located("hello", &Options::new()).unwrap().unwrap_err();
+        assert_eq!(err, StoreError::at("hello".to_string(), 1..4));
+
+        // converters without the export and errors without a location
+        let mut plugin = testing::stub_plugin("", &bodies);
+        assert_eq!(plugin.store_located("hello", &Options::new()).unwrap(), Err(StoreError::from("hello".to_string())));
+        let no_location = r#"(func (export "result_get_location") (param i32) (result i32) (i32.const 0))"#;
+        let mut plugin = testing::stub_plugin(no_location, &bodies);
+        assert_eq!(plugin.store_located("hello", &Options::new()).unwrap().unwrap_err().location, None);
+    }
+}
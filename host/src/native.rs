@@ -0,0 +1,123 @@
+/* Plugin backend calling a `GalaxyFormat` implementation in-process
+
+This skips the wasm runtime entirely, which is useful for testing the host and
+for trusted built-in converters.
+*/
+
+use std::marker::PhantomData;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use anyhow::{anyhow, Result};
+use fg_plugin::{GalaxyFormatPluginV1, Options, StoreError, StyledText, Window};
+use format_galaxy_core::GalaxyFormat;
+
+pub struct NativeGalaxyFormatPlugin<T: GalaxyFormat> {
+    // `fn() -> T` keeps the plugin `Send` and `Sync` independent of `T`
+    _format: PhantomData<fn() -> T>,
+}
+
+impl<T: GalaxyFormat> NativeGalaxyFormatPlugin<T> {
+    pub fn new() -> Self {
+        // messages of native converters go to stderr directly, filtered like the ones of wasm converters
+        format_galaxy_core::log::set_max_level(crate::guest::max_log_level());
+        NativeGalaxyFormatPlugin {
+            _format: PhantomData,
+        }
+    }
+}
+
+impl<T: GalaxyFormat> Default for NativeGalaxyFormatPlugin<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Runs `f`, turning a panic into an error just like a trap would be for wasm plugins.
+fn call_guarded<R>(f: impl FnOnce() -> R) -> Result<R> {
+    catch_unwind(AssertUnwindSafe(f)).map_err(|payload| {
+        let msg = payload.downcast_ref::<&str>().map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string());
+        anyhow!("Converter panicked: {}", msg)
+    })
+}
+
+impl<T: GalaxyFormat> GalaxyFormatPluginV1 for NativeGalaxyFormatPlugin<T> {
+    fn present(&mut self, bytes: &[u8]) -> Result<Result<String, String>> {
+        call_guarded(|| T::present(bytes))
+    }
+
+    fn store(&mut self, s: &str) -> Result<Result<Vec<u8>, String>> {
+        call_guarded(|| T::store(s))
+    }
+
+    fn present_styled(&mut self, bytes: &[u8]) -> Result<Result<StyledText, String>> {
+        call_guarded(|| T::present_styled(bytes))
+    }
+
+    fn sniff(&mut self, bytes: &[u8]) -> Result<Option<u8>> {
+        call_guarded(|| Some(T::sniff(bytes).min(100)))
+    }
+
+    fn present_with_options(&mut self, bytes: &[u8], options: &Options) -> Result<Result<String, String>> {
+        call_guarded(|| T::present_with_options(bytes, options))
+    }
+
+    fn present_styled_with_options(&mut self, bytes: &[u8], options: &Options) -> Result<Result<StyledText, String>> {
+        call_guarded(|| T::present_styled_with_options(bytes, options))
+    }
+
+    fn store_with_options(&mut self, s: &str, options: &Options) -> Result<Result<Vec<u8>, String>> {
+        call_guarded(|| T::store_with_options(s, options))
+    }
+
+    fn store_located(&mut self, s: &str, options: &Options) -> Result<Result<Vec<u8>, StoreError>> {
+        call_guarded(|| T::store_located(s, options))
+    }
+
+    fn present_window(&mut self, chunk: &[u8], offset: u64, max_records: u32) -> Result<Result<Window, String>> {
+        call_guarded(|| T::present_window(chunk, offset, max_records))
+    }
+
+    fn present_view(&mut self, view: &str, bytes: &[u8]) -> Result<Result<String, String>> {
+        call_guarded(|| T::present_view(view, bytes))
+    }
+
+    fn store_view(&mut self, view: &str, s: &str) -> Result<Result<Vec<u8>, String>> {
+        call_guarded(|| T::store_view(view, s))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Panicking {}
+
+    impl GalaxyFormat for Panicking {
+        fn present(_bytes: &[u8]) -> Result<String, String> {
+            panic!("present is broken")
+        }
+
+        fn store(_s: &str) -> Result<Vec<u8>, String> {
+            Err("store is broken".to_string())
+        }
+    }
+
+    #[test]
+    fn test_native_plugin() {
+        let mut plugin: Box<dyn GalaxyFormatPluginV1> = Box::new(NativeGalaxyFormatPlugin::<byte_sequence::Impl>::new());
+        assert_eq!(plugin.present(&[1, 2, 3]).unwrap(), Ok("1,2,3".to_string()));
+        assert_eq!(plugin.store("4,5").unwrap(), Ok(vec!(4, 5)));
+        assert!(plugin.store("foo").unwrap().is_err());
+    }
+
+    #[test]
+    fn test_native_plugin_panic() {
+        let mut plugin = NativeGalaxyFormatPlugin::<Panicking>::new();
+        let err = plugin.present(&[]).unwrap_err();
+        assert!(err.to_string().contains("present is broken"));
+        assert_eq!(plugin.store("").unwrap(), Err("store is broken".to_string()));
+    }
+}
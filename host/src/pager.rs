@@ -0,0 +1,132 @@
+/* Showing long output in a pager
+
+Output for a terminal is piped through `$PAGER`, or `less -R` if it isn't set so that colors
+survive. Like git, `LESS=FRX` is set unless the user configured less, so output that fits on
+the screen is printed without having to quit the pager. Output written after the user quit the
+pager is dropped, writers can check `is_closed` to stop producing more.
+*/
+
+use std::io::{self, Write};
+use std::process::{Child, Command, Stdio};
+
+pub const DEFAULT_PAGER: &str = "less -R";
+
+/// Splits the value of `$PAGER` into program and arguments.
+///
+/// Returns `None` if paging is disabled by an empty value or `cat`.
+pub fn parse_pager(var: Option<&str>) -> Option<Vec<String>> {
+    let command: Vec<String> = var.unwrap_or(DEFAULT_PAGER).split_whitespace().map(String::from).collect();
+    match command.first().map(String::as_str) {
+        None | Some("cat") => None,
+        Some(_) => Some(command),
+    }
+}
+
+pub struct Output {
+    pager: Option<Child>,
+    closed: bool,
+}
+
+impl Output {
+    /// Output to stdout.
+    pub fn stdout() -> Self {
+        Output { pager: None, closed: false }
+    }
+
+    /// Output through the pager, or to stdout if there is none or it can't be started.
+    pub fn pager() -> Self {
+        let command = match parse_pager(std::env::var("PAGER").ok().as_deref()) {
+            Some(command) => command,
+            None => return Self::stdout(),
+        };
+        let mut cmd = Command::new(&command[0]);
+        cmd.args(&command[1..]).stdin(Stdio::piped());
+        if std::env::var_os("LESS").is_none() {
+            cmd.env("LESS", "FRX");
+        }
+        match cmd.spawn() {
+            Ok(child) => Output { pager: Some(child), closed: false },
+            Err(e) => {
+                eprintln!("WARNING: Could not start the pager '{}': {}", command.join(" "), e);
+                Self::stdout()
+            }
+        }
+    }
+
+    /// Whether the user quit the pager, nothing more is shown then.
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.closed {
+            return Ok(buf.len());
+        }
+        let res = match self.pager.as_mut().and_then(|child| child.stdin.as_mut()) {
+            Some(stdin) => stdin.write(buf),
+            None => io::stdout().write(buf),
+        };
+        match res {
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {
+                self.closed = true;
+                Ok(buf.len())
+            }
+            res => res,
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let res = match self.pager.as_mut().and_then(|child| child.stdin.as_mut()) {
+            Some(stdin) => stdin.flush(),
+            None => io::stdout().flush(),
+        };
+        match res {
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {
+                self.closed = true;
+                Ok(())
+            }
+            res => res,
+        }
+    }
+}
+
+impl Drop for Output {
+    // waits until the user quit the pager, so it doesn't fight with the shell over the terminal
+    fn drop(&mut self) {
+        if let Some(mut child) = self.pager.take() {
+            drop(child.stdin.take());
+            let _ = child.wait();
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_pager() {
+        assert_eq!(parse_pager(None), Some(vec!("less".to_string(), "-R".to_string())));
+        assert_eq!(parse_pager(Some("most")), Some(vec!("most".to_string())));
+        assert_eq!(parse_pager(Some("")), None);
+        assert_eq!(parse_pager(Some("cat")), None);
+    }
+
+    #[test]
+    fn test_closed_pager() {
+        let child = Command::new("true").stdin(Stdio::piped()).spawn().unwrap();
+        let mut out = Output { pager: Some(child), closed: false };
+        // the pipe breaks once the pager exited
+        for _ in 0..100 {
+            out.write_all(&[b'x'; 4096]).unwrap();
+            if out.is_closed() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert!(out.is_closed());
+    }
+}
@@ -0,0 +1,200 @@
+/* Pool of reusable plugin instances for batch conversions
+
+Instantiating a module is much cheaper than compiling it, but still not free. The pool keeps
+idle instances around and hands them out again. Instances that trapped are discarded since
+their state (e.g. the guest allocator) can't be trusted anymore.
+
+Instances of converters using WASI are never reused: their clocks and random numbers would
+continue where the previous use left off, so the same bytes wouldn't always be presented the
+same way (see `wasi`).
+*/
+
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use fg_plugin::{GalaxyFormatPluginV1, Options, StoreError, StyledText, Window};
+
+use crate::{WasmtimeConverterModule, WasmtimeGalaxyFormatPlugin};
+
+// instances whose memory grew beyond this are dropped instead of being reused
+const MAX_RECYCLED_MEMORY: usize = 64 * 1024 * 1024;
+
+struct PoolInner {
+    module: WasmtimeConverterModule,
+    idle: Mutex<Vec<WasmtimeGalaxyFormatPlugin>>,
+    max_idle: usize,
+}
+
+#[derive(Clone)]
+pub struct WasmtimePluginPool {
+    inner: Arc<PoolInner>,
+}
+
+impl WasmtimePluginPool {
+    pub fn new(module: WasmtimeConverterModule, max_idle: usize) -> Self {
+        WasmtimePluginPool {
+            inner: Arc::new(PoolInner {
+                module,
+                idle: Mutex::new(vec!()),
+                max_idle,
+            }),
+        }
+    }
+
+    /// Takes an idle instance from the pool or creates a new one if there is none.
+    ///
+    /// The instance is returned to the pool when the `PooledPlugin` is dropped.
+    pub fn get(&self) -> Result<PooledPlugin> {
+        let idle = self.inner.idle.lock().unwrap().pop();
+        let plugin = match idle {
+            Some(plugin) => plugin,
+            None => self.inner.module.instantiate()?,
+        };
+        Ok(PooledPlugin {
+            plugin: Some(plugin),
+            pool: self.inner.clone(),
+            poisoned: false,
+        })
+    }
+
+    pub fn num_idle(&self) -> usize {
+        self.inner.idle.lock().unwrap().len()
+    }
+}
+
+pub struct PooledPlugin {
+    plugin: Option<WasmtimeGalaxyFormatPlugin>,
+    pool: Arc<PoolInner>,
+    poisoned: bool,
+}
+
+impl PooledPlugin {
+    fn track<T>(&mut self, res: Result<T>) -> Result<T> {
+        if res.is_err() {
+            self.poisoned = true;
+        }
+        res
+    }
+}
+
+impl Deref for PooledPlugin {
+    type Target = WasmtimeGalaxyFormatPlugin;
+
+    fn deref(&self) -> &Self::Target {
+        self.plugin.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for PooledPlugin {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.plugin.as_mut().unwrap()
+    }
+}
+
+impl GalaxyFormatPluginV1 for PooledPlugin {
+    fn present(&mut self, bytes: &[u8]) -> Result<Result<String, String>> {
+        let res = GalaxyFormatPluginV1::present(&mut **self, bytes);
+        self.track(res)
+    }
+
+    fn store(&mut self, s: &str) -> Result<Result<Vec<u8>, String>> {
+        let res = GalaxyFormatPluginV1::store(&mut **self, s);
+        self.track(res)
+    }
+
+    fn present_styled(&mut self, bytes: &[u8]) -> Result<Result<StyledText, String>> {
+        let res = GalaxyFormatPluginV1::present_styled(&mut **self, bytes);
+        self.track(res)
+    }
+
+    fn sniff(&mut self, bytes: &[u8]) -> Result<Option<u8>> {
+        let res = GalaxyFormatPluginV1::sniff(&mut **self, bytes);
+        self.track(res)
+    }
+
+    fn present_with_options(&mut self, bytes: &[u8], options: &Options) -> Result<Result<String, String>> {
+        let res = GalaxyFormatPluginV1::present_with_options(&mut **self, bytes, options);
+        self.track(res)
+    }
+
+    fn present_styled_with_options(&mut self, bytes: &[u8], options: &Options) -> Result<Result<StyledText, String>> {
+        let res = GalaxyFormatPluginV1::present_styled_with_options(&mut **self, bytes, options);
+        self.track(res)
+    }
+
+    fn store_with_options(&mut self, s: &str, options: &Options) -> Result<Result<Vec<u8>, String>> {
+        let res = GalaxyFormatPluginV1::store_with_options(&mut **self, s, options);
+        self.track(res)
+    }
+
+    fn store_located(&mut self, s: &str, options: &Options) -> Result<Result<Vec<u8>, StoreError>> {
+        let res = GalaxyFormatPluginV1::store_located(&mut **self, s, options);
+        self.track(res)
+    }
+
+    fn present_window(&mut self, chunk: &[u8], offset: u64, max_records: u32) -> Result<Result<Window, String>> {
+        let res = GalaxyFormatPluginV1::present_window(&mut **self, chunk, offset, max_records);
+        self.track(res)
+    }
+
+    fn present_view(&mut self, view: &str, bytes: &[u8]) -> Result<Result<String, String>> {
+        let res = GalaxyFormatPluginV1::present_view(&mut **self, view, bytes);
+        self.track(res)
+    }
+
+    fn store_view(&mut self, view: &str, s: &str) -> Result<Result<Vec<u8>, String>> {
+        let res = GalaxyFormatPluginV1::store_view(&mut **self, view, s);
+        self.track(res)
+    }
+}
+
+impl Drop for PooledPlugin {
+    fn drop(&mut self) {
+        let plugin = match self.plugin.take() {
+            Some(plugin) => plugin,
+            None => return,
+        };
+        if self.poisoned || self.pool.module.imports_wasi() || plugin.memory_size() > MAX_RECYCLED_MEMORY {
+            return;
+        }
+        let mut idle = self.pool.idle.lock().unwrap();
+        if idle.len() < self.pool.max_idle {
+            idle.push(plugin);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use wasmtime::Engine;
+
+    // byte sequence converter from the test index
+    const BYTES_PLUGIN: &str = "../fg-index/converters/d14ea3e27f3235e7488b0af9875d952e1b7c13c267049c89676fcbd4f13f7356.wasm";
+
+    #[test]
+    fn test_pool_reuse() {
+        let module = WasmtimeConverterModule::new(&Engine::default(), Path::new(BYTES_PLUGIN)).unwrap();
+        let pool = WasmtimePluginPool::new(module, 2);
+
+        {
+            let mut a = pool.get().unwrap();
+            let mut b = pool.get().unwrap();
+            let mut c = pool.get().unwrap();
+            assert_eq!(a.present(&[1, 2]).unwrap(), Ok("1,2".to_string()));
+            assert_eq!(b.store("3,4").unwrap(), Ok(vec!(3, 4)));
+            assert!(c.store("foo").unwrap().is_err());
+        }
+        // the pool keeps at most two idle instances
+        assert_eq!(pool.num_idle(), 2);
+
+        for i in 0..100u8 {
+            let mut plugin = pool.get().unwrap();
+            assert_eq!(plugin.present(&[i]).unwrap(), Ok(i.to_string()));
+        }
+        assert_eq!(pool.num_idle(), 2);
+    }
+}
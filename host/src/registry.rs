@@ -0,0 +1,509 @@
+/* Lookup and instantiation of converter plugins
+
+The registry hides where a converter comes from (wasm module, native built-in, ...)
+and reports missing formats, converters or versions as errors.
+*/
+
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::{anyhow, Result};
+use fg_index::{Converter, ConverterHash, ConverterId, FileFormat, FormatId, Galaxy, Version, WasiCapability};
+use fg_plugin::{is_component, GalaxyFormatPluginV1, ModuleAdapter, Options, WorldPlugin};
+use format_galaxy_core::GalaxyFormat;
+use wasmtime::Engine;
+
+use crate::{ConverterSelection, NativeGalaxyFormatPlugin, WasmtimeConverterComponent, WasmtimeConverterModule, WasmtimePluginPool};
+
+/// Describes a converter without necessarily naming all of its parts.
+///
+/// Missing parts are filled in by `ConverterRegistry::resolve`.
+#[derive(Clone, Debug)]
+pub struct ConverterQuery {
+    pub format_id: FormatId,
+    pub converter_id: Option<ConverterId>,
+    pub version: Option<String>,
+}
+
+impl ConverterQuery {
+    pub fn new(format_id: FormatId) -> Self {
+        ConverterQuery {
+            format_id,
+            converter_id: None,
+            version: None,
+        }
+    }
+}
+
+/// Converter id under which the built-in hex dump converter is available for every format id,
+/// including ones that aren't part of the index. Indexes can't use it (see `Galaxy::from_json`).
+pub const FALLBACK_CONVERTER_ID: ConverterId = fg_index::RESERVED_CONVERTER_ID;
+
+pub trait ConverterRegistry {
+    fn galaxy(&self) -> &Galaxy;
+
+    /// Instantiates the plugin for the converter with the given hash.
+    fn load_hash(&self, hash: &ConverterHash) -> Result<Box<dyn GalaxyFormatPluginV1>>;
+
+    /// Instantiates the converter that works for any format (see `FALLBACK_CONVERTER_ID`).
+    fn load_fallback(&self) -> Result<Box<dyn GalaxyFormatPluginV1>> {
+        Ok(Box::new(NativeGalaxyFormatPlugin::<hexdump::Impl>::new()))
+    }
+
+    fn format(&self, format_id: FormatId) -> Result<&FileFormat> {
+        self.galaxy().formats.get(&format_id)
+            .ok_or_else(|| anyhow!("Unknown format id {}", format_id.0))
+    }
+
+    fn converter(&self, format_id: FormatId, converter_id: ConverterId) -> Result<&Converter> {
+        let format = self.format(format_id)?;
+        format.converters.get(&converter_id)
+            .ok_or_else(|| anyhow!("Format '{}' has no converter with id {}", format.name, converter_id.0))
+    }
+
+    fn version(&self, selection: &ConverterSelection) -> Result<&Version> {
+        let converter = self.converter(selection.format_id, selection.converter_id)?;
+        converter.versions.get(selection.version_idx)
+            .ok_or_else(|| anyhow!("Converter '{}' has no version with index {}", converter.name, selection.version_idx))
+    }
+
+    fn converter_hash(&self, selection: &ConverterSelection) -> Result<&ConverterHash> {
+        Ok(&self.version(selection)?.hash)
+    }
+
+    /// Checks that the selected converter offers the named view.
+    fn check_view(&self, selection: &ConverterSelection, view: &str) -> Result<()> {
+        if selection.converter_id == FALLBACK_CONVERTER_ID {
+            return Err(anyhow!("The hex dump has no view '{}'", view));
+        }
+        let converter = self.converter(selection.format_id, selection.converter_id)?;
+        if converter.views.iter().any(|v| v == view) {
+            return Ok(());
+        }
+        if converter.views.is_empty() {
+            Err(anyhow!("Converter '{}' has no view '{}', it only has a default view", converter.name, view))
+        } else {
+            Err(anyhow!("Converter '{}' has no view '{}', available views: {}", converter.name, view, converter.views.join(", ")))
+        }
+    }
+
+    /// Whether the selected converter can present parts of a file.
+    fn is_windowed(&self, selection: &ConverterSelection) -> Result<bool> {
+        if selection.converter_id == FALLBACK_CONVERTER_ID {
+            return Ok(true);
+        }
+        Ok(self.converter(selection.format_id, selection.converter_id)?.windowed)
+    }
+
+    /// Checks option values (e.g. from the command line) against the options the selected
+    /// version of a converter declares.
+    fn check_options(&self, selection: &ConverterSelection, values: &[(String, String)]) -> Result<Options> {
+        let mut options = Options::new();
+        if values.is_empty() {
+            return Ok(options);
+        }
+        if selection.converter_id == FALLBACK_CONVERTER_ID {
+            return Err(anyhow!("The hex dump has no options"));
+        }
+        let converter = self.converter(selection.format_id, selection.converter_id)?;
+        let version = self.version(selection)?;
+        for (key, value) in values {
+            let option = version.options.iter().find(|o| &o.name == key).ok_or_else(|| {
+                let names: Vec<_> = version.options.iter().map(|o| o.name.as_str()).collect();
+                match names.is_empty() {
+                    true => anyhow!("Version {} of converter '{}' has no options", version.version, converter.name),
+                    false => anyhow!("Version {} of converter '{}' has no option '{}', available options: {}", version.version, converter.name, key, names.join(", ")),
+                }
+            })?;
+            option.check(value)?;
+            options.set(key, value).map_err(|e| anyhow!(e))?;
+        }
+        Ok(options)
+    }
+
+    /// Resolves a query to a concrete selection.
+    ///
+    /// Without a converter id, the converter with the lowest id that has at least one version is
+    /// used. Without a version, the newest version is used.
+    fn resolve(&self, query: &ConverterQuery) -> Result<ConverterSelection> {
+        if query.converter_id == Some(FALLBACK_CONVERTER_ID) {
+            // the fallback converter has no versions
+            return Ok(ConverterSelection {
+                format_id: query.format_id,
+                converter_id: FALLBACK_CONVERTER_ID,
+                version_idx: 0,
+            });
+        }
+        let format = self.format(query.format_id)?;
+        let converter_id = match query.converter_id {
+            Some(id) => id,
+            None => format.converters.iter()
+                .filter(|(_id, c)| !c.versions.is_empty())
+                .map(|(id, _c)| *id)
+                .min_by_key(|id| id.0)
+                .ok_or_else(|| anyhow!("Format '{}' has no converters", format.name))?,
+        };
+        let converter = self.converter(query.format_id, converter_id)?;
+        let version_idx = match &query.version {
+            Some(version) => converter.versions.iter()
+                .position(|v| &v.version == version)
+                .ok_or_else(|| anyhow!("Converter '{}' has no version {}", converter.name, version))?,
+            None => converter.versions.len().checked_sub(1)
+                .ok_or_else(|| anyhow!("Converter '{}' has no versions", converter.name))?,
+        };
+        Ok(ConverterSelection {
+            format_id: query.format_id,
+            converter_id,
+            version_idx,
+        })
+    }
+
+    fn load(&self, selection: &ConverterSelection) -> Result<Box<dyn GalaxyFormatPluginV1>> {
+        if selection.converter_id == FALLBACK_CONVERTER_ID {
+            return self.load_fallback();
+        }
+        let hash = self.converter_hash(selection)?;
+        self.load_hash(hash)
+    }
+
+    fn load_query(&self, query: &ConverterQuery) -> Result<Box<dyn GalaxyFormatPluginV1>> {
+        self.load(&self.resolve(query)?)
+    }
+}
+
+/// Splits a `key=value` command line argument.
+pub fn parse_option_arg(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("Expected key=value, found '{}'", s)),
+    }
+}
+
+type NativeConstructor = fn() -> Box<dyn GalaxyFormatPluginV1>;
+
+// number of idle instances kept per converter
+const MAX_IDLE_INSTANCES: usize = 16;
+
+/// Registry loading converters from a local directory of wasm modules named `<hash>.wasm`.
+///
+/// Modules are compiled once and their instances are pooled (unless they use WASI), so loading
+/// the same converter repeatedly is cheap. Components are compiled once as well. Converters that are registered as
+/// native are run in-process instead.
+pub struct LocalRegistry {
+    galaxy: Galaxy,
+    plugin_dir: PathBuf,
+    native: HashMap<String, NativeConstructor>,
+    engine: Engine,
+    pools: Mutex<HashMap<String, WasmtimePluginPool>>,
+    components: Mutex<HashMap<String, WasmtimeConverterComponent>>,
+}
+
+impl LocalRegistry {
+    pub fn new(galaxy: Galaxy, plugin_dir: impl AsRef<Path>) -> Self {
+        LocalRegistry {
+            galaxy,
+            plugin_dir: plugin_dir.as_ref().to_path_buf(),
+            native: HashMap::new(),
+            engine: Engine::default(),
+            pools: Mutex::new(HashMap::new()),
+            components: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Runs the converter with the given hash natively, using the `GalaxyFormat` impl `T`.
+    ///
+    /// Only do this for trusted converters, native code isn't sandboxed.
+    pub fn register_native<T: GalaxyFormat + 'static>(&mut self, hash: &ConverterHash) {
+        fn construct<T: GalaxyFormat + 'static>() -> Box<dyn GalaxyFormatPluginV1> {
+            Box::new(NativeGalaxyFormatPlugin::<T>::new())
+        }
+        self.native.insert(hash.0.clone(), construct::<T>);
+    }
+
+    pub fn plugin_path(&self, hash: &ConverterHash) -> PathBuf {
+        self.plugin_dir.join(format!("{}.wasm", hash.0))
+    }
+
+    /// Makes sure the index knows `format_id`, taking the format from the first of the
+    /// `fallback` indexes that knows it otherwise.
+    ///
+    /// Returns false if none of the indexes know the format.
+    pub fn ensure_format(&mut self, format_id: FormatId, fallback: &[PathBuf]) -> Result<bool> {
+        if self.galaxy.formats.contains_key(&format_id) {
+            return Ok(true);
+        }
+        for path in fallback {
+            let galaxy = Galaxy::from_json(path)
+                .map_err(|e| anyhow!("Couldn't read fallback index {}: {}", path.display(), e))?;
+            if let Some(format) = galaxy.formats.get(&format_id) {
+                self.galaxy.formats.insert(format_id, format.clone());
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// The WASI capabilities declared in the index for the converter with the given hash.
+    pub fn wasi_capabilities(&self, hash: &ConverterHash) -> Vec<WasiCapability> {
+        self.galaxy.formats.values()
+            .flat_map(|format| format.converters.values())
+            .find(|converter| converter.versions.iter().any(|v| v.hash == *hash))
+            .map(|converter| converter.wasi.clone())
+            .unwrap_or_default()
+    }
+
+    /// Whether the compiled code of the wasm converter with the given hash is cached.
+    pub fn is_compiled(&self, hash: &ConverterHash) -> bool {
+        std::fs::read(self.plugin_path(hash))
+            .map(|bytes| WasmtimeConverterModule::cache_path(&bytes).is_file())
+            .unwrap_or(false)
+    }
+
+    /// Returns the instance pool for the wasm converter with the given hash, compiling it if needed.
+    pub fn pool(&self, hash: &ConverterHash) -> Result<WasmtimePluginPool> {
+        if let Some(pool) = self.pools.lock().unwrap().get(&hash.0) {
+            return Ok(pool.clone());
+        }
+        let path = self.plugin_path(hash);
+        if !path.is_file() {
+            return Err(anyhow!("Converter module {} not found", path.display()));
+        }
+        // compile without holding the lock, other converters may be loaded in the meantime
+        let module = WasmtimeConverterModule::with_wasi(&self.engine, &path, &self.wasi_capabilities(hash))?;
+        let pool = self.pools.lock().unwrap()
+            .entry(hash.0.clone())
+            .or_insert_with(|| WasmtimePluginPool::new(module, MAX_IDLE_INSTANCES))
+            .clone();
+        Ok(pool)
+    }
+
+    /// Returns the wasm converter with the given hash if it is a component, compiling it if needed.
+    pub fn component(&self, hash: &ConverterHash) -> Result<Option<WasmtimeConverterComponent>> {
+        if let Some(component) = self.components.lock().unwrap().get(&hash.0) {
+            return Ok(Some(component.clone()));
+        }
+        if self.pools.lock().unwrap().contains_key(&hash.0) {
+            return Ok(None);
+        }
+        let path = self.plugin_path(hash);
+        let mut preamble = vec!();
+        match std::fs::File::open(&path) {
+            Ok(file) => file.take(8).read_to_end(&mut preamble)?,
+            // reported when loading the module
+            Err(_) => return Ok(None),
+        };
+        if !is_component(&preamble) {
+            return Ok(None);
+        }
+        let component = WasmtimeConverterComponent::with_wasi(&self.engine, &path, &self.wasi_capabilities(hash))?;
+        let component = self.components.lock().unwrap()
+            .entry(hash.0.clone())
+            .or_insert(component)
+            .clone();
+        Ok(Some(component))
+    }
+}
+
+impl ConverterRegistry for LocalRegistry {
+    fn galaxy(&self) -> &Galaxy {
+        &self.galaxy
+    }
+
+    fn load_hash(&self, hash: &ConverterHash) -> Result<Box<dyn GalaxyFormatPluginV1>> {
+        if let Some(construct) = self.native.get(&hash.0) {
+            return Ok(construct());
+        }
+        // both kinds of wasm converters are called through the converter world
+        if let Some(component) = self.component(hash)? {
+            return Ok(Box::new(WorldPlugin(component.instantiate()?)));
+        }
+        Ok(Box::new(WorldPlugin(ModuleAdapter::new(self.pool(hash)?.get()?))))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry() -> LocalRegistry {
+        let galaxy = Galaxy::from_json(Path::new("../fg-index/test_index.json")).unwrap();
+        LocalRegistry::new(galaxy, "../fg-index/converters/")
+    }
+
+    #[test]
+    fn test_resolve() {
+        let registry = registry();
+
+        let selection = registry.resolve(&ConverterQuery::new(FormatId(100))).unwrap();
+        assert_eq!(selection.converter_id, ConverterId(1100110011));
+        assert_eq!(selection.version_idx, 5);
+
+        let query = ConverterQuery {
+            format_id: FormatId(2),
+            converter_id: Some(ConverterId(1100110012)),
+            version: Some("0.1.0".to_string()),
+        };
+        let selection = registry.resolve(&query).unwrap();
+        assert_eq!(selection.version_idx, 0);
+    }
+
+    #[test]
+    fn test_missing_ids() {
+        let registry = registry();
+
+        let err = registry.resolve(&ConverterQuery::new(FormatId(12345))).unwrap_err();
+        assert_eq!(err.to_string(), "Unknown format id 12345");
+
+        let query = ConverterQuery {
+            format_id: FormatId(2),
+            converter_id: Some(ConverterId(42)),
+            version: None,
+        };
+        assert!(registry.resolve(&query).is_err());
+
+        let query = ConverterQuery {
+            format_id: FormatId(2),
+            converter_id: None,
+            version: Some("9.9.9".to_string()),
+        };
+        assert!(registry.resolve(&query).is_err());
+
+        // converter without any versions
+        let query = ConverterQuery {
+            format_id: FormatId(201),
+            converter_id: None,
+            version: None,
+        };
+        assert!(registry.resolve(&query).is_err());
+
+        let selection = ConverterSelection {
+            format_id: FormatId(2),
+            converter_id: ConverterId(1100110011),
+            version_idx: 7,
+        };
+        assert!(registry.load(&selection).is_err());
+    }
+
+    #[test]
+    fn test_load_native() {
+        let mut registry = registry();
+        let selection = registry.resolve(&ConverterQuery::new(FormatId(2))).unwrap();
+        let hash = registry.converter_hash(&selection).unwrap().clone();
+        registry.register_native::<byte_sequence::Impl>(&hash);
+
+        let mut plugin = registry.load(&selection).unwrap();
+        assert_eq!(plugin.present(&[1, 2, 3]).unwrap(), Ok("1,2,3".to_string()));
+    }
+
+    #[test]
+    fn test_fallback() {
+        let registry = registry();
+        for format_id in [FormatId(2), FormatId(201), FormatId(12345)] {
+            let query = ConverterQuery {
+                format_id,
+                converter_id: Some(FALLBACK_CONVERTER_ID),
+                version: None,
+            };
+            let mut plugin = registry.load_query(&query).unwrap();
+            let s = plugin.present(b"fg").unwrap().unwrap();
+            assert!(s.starts_with("00000000  66 67 "));
+            assert_eq!(plugin.store(&s).unwrap(), Ok(b"fg".to_vec()));
+        }
+    }
+
+    #[test]
+    fn test_check_view() {
+        let registry = registry();
+        let selection = registry.resolve(&ConverterQuery::new(FormatId(300))).unwrap();
+        registry.check_view(&selection, "summary").unwrap();
+        let err = registry.check_view(&selection, "wat").unwrap_err();
+        assert!(err.to_string().contains("available views: summary"));
+
+        let mut plugin = registry.load(&selection).unwrap();
+        let summary = plugin.present_view("summary", b"\0asm\x01\0\0\0").unwrap();
+        assert_eq!(summary, Ok("imports (0):\nexports (0):\n".to_string()));
+
+        let selection = registry.resolve(&ConverterQuery::new(FormatId(2))).unwrap();
+        assert!(registry.check_view(&selection, "summary").is_err());
+    }
+
+    #[test]
+    fn test_check_options() {
+        let registry = registry();
+        let selection = registry.resolve(&ConverterQuery::new(FormatId(100))).unwrap();
+        let values = vec!(parse_option_arg("indent=4").unwrap(), parse_option_arg("sort_keys=true").unwrap());
+        let options = registry.check_options(&selection, &values).unwrap();
+        assert_eq!(options.int("indent", 2), Ok(4));
+        assert_eq!(options.bool("sort_keys", false), Ok(true));
+
+        let err = registry.check_options(&selection, &[parse_option_arg("width=80").unwrap()]).unwrap_err();
+        assert!(err.to_string().contains("available options: indent, sort_keys, trailing_commas"));
+        assert!(registry.check_options(&selection, &[parse_option_arg("indent=100").unwrap()]).is_err());
+        assert!(parse_option_arg("indent").is_err());
+        assert!(parse_option_arg("=4").is_err());
+
+        // the published build takes the options it declares
+        let mut plugin = registry.load(&selection).unwrap();
+        let bytes = plugin.store("[1, 2]").unwrap().unwrap();
+        let text = plugin.present_with_options(&bytes, &options).unwrap().unwrap();
+        assert_eq!(text, "[\n    1,\n    2,\n]");
+
+        // builds from before the converter had options don't declare them
+        let query = ConverterQuery { version: Some("0.1.4".to_string()), ..ConverterQuery::new(FormatId(100)) };
+        let err = registry.check_options(&registry.resolve(&query).unwrap(), &values).unwrap_err();
+        assert_eq!(err.to_string(), "Version 0.1.4 of converter 'Json-like' has no options");
+
+        // converters without options accept no values at all
+        let selection = registry.resolve(&ConverterQuery::new(FormatId(2))).unwrap();
+        assert!(registry.check_options(&selection, &[]).unwrap().is_empty());
+        assert!(registry.check_options(&selection, &values).is_err());
+    }
+
+    #[test]
+    fn test_wasi_not_reused() {
+        // presents the number of microseconds on the converter's clock as a digit
+        let wat = crate::testing::stub_wat(
+            r#"(import "wasi_snapshot_preview1" "clock_time_get" (func $clock_time_get (param i32 i64 i32) (result i32)))"#,
+            &[
+                ("present", "(drop (call $clock_time_get (i32.const 0) (i64.const 1) (i32.const 64)))
+                    (i32.store8 (i32.const 128) (i32.add (i32.const 48)
+                        (i32.wrap_i64 (i64.div_u (i64.load (i32.const 64)) (i64.const 1000)))))
+                    (i32.const 0)"),
+                ("result_get_ptr", "(i32.const 128)"),
+                ("result_get_len", "(i32.const 1)"),
+                ("result_get_success", "(i32.const 1)"),
+            ],
+        );
+        let dir = std::env::temp_dir().join(format!("fg-registry-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("clock.wasm"), wat).unwrap();
+        let galaxy = Galaxy::from_json_str(r#"{"formats": {"1": {"name": "Clock", "desc": "", "extensions": [],
+            "converters": {"1": {"name": "clock", "desc": "", "versions": [["1.0.0", "clock"]], "wasi": ["clock"]}}}}}"#).unwrap();
+        let registry = LocalRegistry::new(galaxy, &dir);
+
+        // every load starts with a fresh clock
+        for _ in 0..2 {
+            let mut plugin = registry.load_query(&ConverterQuery::new(FormatId(1))).unwrap();
+            assert_eq!(plugin.present(b"").unwrap(), Ok("0".to_string()));
+            assert_eq!(plugin.present(b"").unwrap(), Ok("1".to_string()));
+        }
+        assert_eq!(registry.pool(&ConverterHash("clock".to_string())).unwrap().num_idle(), 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_ensure_format() {
+        let mut registry = LocalRegistry::new(Galaxy { formats: HashMap::new() }, "../fg-index/converters/");
+        assert!(!registry.ensure_format(FormatId(200), &[]).unwrap());
+        assert!(registry.ensure_format(FormatId(200), &[PathBuf::from("does-not-exist.json")]).is_err());
+
+        let fallback = [PathBuf::from("../fg-index/test_index.json")];
+        assert!(registry.ensure_format(FormatId(200), &fallback).unwrap());
+        assert_eq!(registry.format(FormatId(200)).unwrap().name, "Bson");
+        assert!(registry.format(FormatId(100)).is_err());
+        assert!(!registry.ensure_format(FormatId(12345), &fallback).unwrap());
+    }
+}
@@ -1,6 +1,7 @@
 /* Interactive selection of converters on the command line
 */
 
+use anyhow::{anyhow, Result};
 use fg_index::Converter;
 use fg_index::ConverterId;
 use fg_index::FileFormat;
@@ -8,7 +9,7 @@ use fg_index::FormatId;
 use fg_index::Galaxy;
 use terminal_menu::{menu, label, button, run, mut_menu};
 
-use super::FileType;
+use super::{FileType, FormatCandidate, FALLBACK_CONVERTER_ID};
 
 enum Answer<T> {
     Selected(T),
@@ -16,13 +17,14 @@ enum Answer<T> {
     Exit,
 }
 
+#[derive(Clone, Debug)]
 pub struct ConverterSelection {
     pub format_id: FormatId,
     pub converter_id: ConverterId,
     pub version_idx: usize,
 }
 
-fn ask(formats: &[(FormatId, FileFormat)], allow_format_selection: bool) -> Option<ConverterSelection> {
+fn ask(formats: &[(FormatId, FileFormat)], candidates: &[FormatCandidate], allow_format_selection: bool) -> Option<ConverterSelection> {
     let mut format_state = None;
     let mut converter_state = None;
 
@@ -35,7 +37,7 @@ fn ask(formats: &[(FormatId, FileFormat)], allow_format_selection: bool) -> Opti
         match (&format_state, &converter_state) {
             (None, None) => {
                 // ask for format
-                match ask_format(formats) {
+                match ask_format(formats, candidates) {
                     Answer::Selected(format) => {
                         format_state = Some(format);
                     }
@@ -43,11 +45,20 @@ fn ask(formats: &[(FormatId, FileFormat)], allow_format_selection: bool) -> Opti
                     Answer::Back => unreachable!()
                 }
             }
-            (Some((_format_id, format)), None) => {
+            (Some((format_id, format)), None) => {
                 // ask for converter
-                let mut converters: Vec<_> = format.converters.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+                let mut converters: Vec<_> = format.converters.iter().map(|(k, v)| (*k, v.clone())).collect();
                 converters.sort_by_key(|c| c.1.name.to_string());
+                converters.push(fallback_converter());
                 match ask_converter(converters.as_slice(), allow_format_selection, &format.name) {
+                    Answer::Selected((FALLBACK_CONVERTER_ID, _)) => {
+                        // the built-in converter has no versions to choose from
+                        return Some(ConverterSelection {
+                            format_id: *format_id,
+                            converter_id: FALLBACK_CONVERTER_ID,
+                            version_idx: 0,
+                        });
+                    }
                     Answer::Selected(converter) => {
                         converter_state = Some(converter.clone());
                     }
@@ -59,12 +70,12 @@ fn ask(formats: &[(FormatId, FileFormat)], allow_format_selection: bool) -> Opti
             }
             (Some(format), Some(converter)) => {
                 // ask for version
-                let versions: Vec<&String> = converter.1.versions.iter().map(|x| &x.0).collect();
+                let versions: Vec<&String> = converter.1.versions.iter().map(|v| &v.version).collect();
                 match ask_version(versions.as_slice()) {
                     Answer::Selected(version_idx) => {
                         return Some(ConverterSelection {
-                            format_id: format.0.clone(), 
-                            converter_id: converter.0.clone(), 
+                            format_id: format.0, 
+                            converter_id: converter.0, 
                             version_idx
                         });
                     }
@@ -80,13 +91,30 @@ fn ask(formats: &[(FormatId, FileFormat)], allow_format_selection: bool) -> Opti
 
 }
 
-fn ask_format(formats: &[(FormatId, FileFormat)]) -> Answer<&(FormatId, FileFormat)> {
+// offered for every format, in addition to the converters of the index
+fn fallback_converter() -> (ConverterId, Converter) {
+    (FALLBACK_CONVERTER_ID, Converter {
+        name: "Hex dump (raw bytes)".to_string(),
+        desc: "Built-in hex dump that works for any format".to_string(),
+        versions: vec!(),
+        views: vec!(),
+        windowed: true,
+        wasi: vec!(),
+    })
+}
+
+fn ask_format<'a>(formats: &'a [(FormatId, FileFormat)], candidates: &[FormatCandidate]) -> Answer<&'a (FormatId, FileFormat)> {
     let mut items = vec!(
         label("Please select a format:"),
     );
     let num_labels = items.len();
 
-    items.extend(formats.iter().map(|x| button(&x.1.name)));
+    items.extend(formats.iter().map(|x| {
+        match candidates.iter().find(|c| c.format_id == x.0) {
+            Some(c) => button(format!("{} ({}% match)", x.1.name, c.confidence)),
+            None => button(&x.1.name),
+        }
+    }));
     
     items.push(button("(exit)"));
     
@@ -98,7 +126,7 @@ fn ask_format(formats: &[(FormatId, FileFormat)]) -> Answer<&(FormatId, FileForm
     let idx = mut_menu(&menu).selected_item_index() - num_labels;
     
     if idx < formats.len() {
-        let format_id = formats.iter().nth(idx).unwrap();
+        let format_id = &formats[idx];
         return Answer::Selected(format_id);
     }
 
@@ -111,7 +139,7 @@ fn ask_format(formats: &[(FormatId, FileFormat)]) -> Answer<&(FormatId, FileForm
 
 fn ask_converter<'a>(converters: &'a[(ConverterId, Converter)], offer_back: bool, format_name: &str) -> Answer<&'a (ConverterId, Converter)> {
     let mut items = vec!(
-        label(&format!("File format: {}", format_name)),
+        label(format!("File format: {}", format_name)),
         label("Please select a converter:"),
     );
     let num_labels = items.len();
@@ -133,7 +161,7 @@ fn ask_converter<'a>(converters: &'a[(ConverterId, Converter)], offer_back: bool
     let idx = mut_menu(&menu).selected_item_index() - num_labels;
     
     if idx < converters.len() {
-        let converter_id = converters.iter().nth(idx).unwrap();
+        let converter_id = &converters[idx];
         return Answer::Selected(converter_id);
     }
 
@@ -184,34 +212,54 @@ fn ask_version(versions: &[&String]) -> Answer<usize> {
 }
 
 
-pub fn select_plugin(galaxy: &Galaxy, file_type: &FileType) -> Option<ConverterSelection> {
+/// Asks the user to select a converter for a file of the given type.
+///
+/// For files without a known format id, `candidates` are the formats detected for the file
+/// (see `detect_formats`). They're offered in order, so the most likely format is preselected.
+///
+/// Returns `Ok(None)` if the selection was cancelled and an error if the format id of the file
+/// isn't part of the index.
+pub fn select_plugin(galaxy: &Galaxy, file_type: &FileType, candidates: &[FormatCandidate]) -> Result<Option<ConverterSelection>> {
     // ask user to select a converter plugin
-    match file_type {
+    Ok(match file_type {
         FileType::FormatId(fid) => {
-            let formats = vec!((fid.clone(), galaxy.formats[&fid].clone()));
-            ask(formats.as_slice(), false)
+            let format = galaxy.formats.get(fid)
+                .ok_or_else(|| anyhow!("Unknown format id {}", fid.0))?;
+            let formats = vec!((*fid, format.clone()));
+            ask(formats.as_slice(), &[], false)
         }
         FileType::Ext(opt_ext) => {
-            // only list plugins that are compatible with the given file extension
-            let mut formats: Vec<(FormatId, FileFormat)> = if let Some(ext) = opt_ext {
-                let filtered_formats: Vec<_> = galaxy.formats.iter()
-                    .filter(|(_id, ff)| ff.extensions.iter().any(|s| s==ext))
-                    .map(|(id, format)| (id.clone(), format.clone())).collect();
-                
-                if filtered_formats.is_empty() {
-                    println!("No matching format found for file with extension \'{}\'", ext);
-                    galaxy.formats.iter().map(|(id, format)| (id.clone(), format.clone())).collect()
-                } else {
-                    filtered_formats
+            // only list formats that were detected
+            let formats: Vec<(FormatId, FileFormat)> = candidates.iter()
+                .filter_map(|c| galaxy.formats.get(&c.format_id).map(|format| (c.format_id, format.clone())))
+                .collect();
+
+            let formats = if formats.is_empty() {
+                match opt_ext {
+                    Some(ext) => println!("No matching format found for file with extension \'{}\'", ext),
+                    None => println!("No matching format found for file"),
                 }
+                let mut formats: Vec<_> = galaxy.formats.iter().map(|(id, format)| (*id, format.clone())).collect();
+                formats.sort_by_key(|x| x.1.name.to_string());
+                formats
             } else {
-                galaxy.formats.iter().map(|(id, format)| (id.clone(), format.clone())).collect()
+                formats
             };
         
-            formats.sort_by_key(|x| x.1.name.to_string());
-        
             // ask user
-            ask(formats.as_slice(), true)
+            ask(formats.as_slice(), candidates, true)
         }
-    }
-}
\ No newline at end of file
+    })
+}
+
+/// Asks the user whether to use the hex dump converter for a file whose format is unknown.
+pub fn offer_raw_view(format_id: FormatId) -> bool {
+    let menu = menu(vec!(
+        label(format!("The file uses the unknown format id {}.", format_id.0)),
+        button("Use hex dump (raw bytes)"),
+        button("(exit)"),
+    ));
+    run(&menu);
+    let menu = mut_menu(&menu);
+    !menu.canceled() && menu.selected_item_index() == 1
+}
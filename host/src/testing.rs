@@ -0,0 +1,34 @@
+/* A stub V1 converter for tests
+
+The module has all required exports. `present` and `store` trap and the other functions return
+zero unless a test overrides their bodies, e.g. to return out of bounds pointers.
+*/
+
+use crate::WasmtimeGalaxyFormatPlugin;
+
+const EXPORTS: [(&str, &str, &str); 7] = [
+    ("present", "(param i32 i32) (result i32)", "unreachable"),
+    ("store", "(param i32 i32) (result i32)", "unreachable"),
+    ("alloc", "(param i32) (result i32)", "(i32.const 0)"),
+    ("free", "(param i32)", ""),
+    ("result_get_ptr", "(param i32) (result i32)", "(i32.const 0)"),
+    ("result_get_len", "(param i32) (result i32)", "(i32.const 0)"),
+    ("result_get_success", "(param i32) (result i32)", "(i32.const 0)"),
+];
+
+/// The stub module in text format. `items` (imports, data, further functions) go in front of
+/// the exports and `bodies` replace the bodies of the exported functions they name.
+pub fn stub_wat(items: &str, bodies: &[(&str, &str)]) -> String {
+    let exports: String = EXPORTS.iter()
+        .map(|(name, ty, body)| {
+            let body = bodies.iter().find(|(n, _body)| n == name).map_or(*body, |(_name, body)| body);
+            format!("    (func (export \"{}\") {} {})\n", name, ty, body)
+        })
+        .collect();
+    // imports have to come before the memory
+    format!("(module\n{}\n    (memory (export \"memory\") 1)\n{})", items, exports)
+}
+
+pub fn stub_plugin(items: &str, bodies: &[(&str, &str)]) -> WasmtimeGalaxyFormatPlugin {
+    WasmtimeGalaxyFormatPlugin::from_bytes(stub_wat(items, bodies).as_bytes()).unwrap()
+}
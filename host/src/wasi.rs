@@ -0,0 +1,151 @@
+/* Sandboxed WASI environment for converters that need parts of it
+
+Converters get no WASI imports unless the index declares capabilities for them (see
+`WasiCapability`). Every WASI function belongs to a capability and converters importing
+functions of undeclared capabilities are refused before they are instantiated (see
+`inspect`). No files are preopened and there is never a network, and clocks and random
+numbers are deterministic, so that a converter presents the same bytes the same way every
+time. For that, every instance gets a fresh environment and instances using WASI are never
+reused (see `pool`).
+*/
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use fg_index::WasiCapability;
+use wasmtime_wasi::{Deterministic, HostMonotonicClock, HostWallClock, WasiCtxBuilder};
+
+pub const PREVIEW1_MODULE: &str = "wasi_snapshot_preview1";
+
+/// The capability a WASI preview 1 function belongs to, `None` for functions converters never get.
+pub fn preview1_capability(name: &str) -> Option<WasiCapability> {
+    use WasiCapability::*;
+    match name {
+        "clock_time_get" | "clock_res_get" | "poll_oneoff" => Some(Clock),
+        "random_get" => Some(Random),
+        "environ_get" | "environ_sizes_get" | "args_get" | "args_sizes_get" => Some(Env),
+        "proc_exit" | "proc_raise" | "sched_yield" => Some(Stdio),
+        // nothing is preopened, so the only file descriptors are the ones of stdio
+        _ if name.starts_with("fd_") => Some(Stdio),
+        _ => None,
+    }
+}
+
+/// The capability of a WASI preview 2 interface like `wasi:clocks/wall-clock@0.2.0`, `None`
+/// for interfaces converters never get.
+pub fn preview2_capability(name: &str) -> Option<WasiCapability> {
+    use WasiCapability::*;
+    let interface = name.split('@').next().unwrap_or(name);
+    match interface {
+        "wasi:cli/environment" => Some(Env),
+        _ if interface.starts_with("wasi:clocks/") => Some(Clock),
+        _ if interface.starts_with("wasi:random/") => Some(Random),
+        _ if interface.starts_with("wasi:cli/") || interface.starts_with("wasi:io/") => Some(Stdio),
+        // the standard library imports it regardless, nothing is preopened (see `Stdio`)
+        _ if interface.starts_with("wasi:filesystem/") => Some(Stdio),
+        _ => None,
+    }
+}
+
+// starts at zero and advances by a microsecond with every reading, so that waiting terminates
+#[derive(Default)]
+struct StepClock {
+    micros: AtomicU64,
+}
+
+impl HostWallClock for StepClock {
+    fn resolution(&self) -> Duration {
+        Duration::from_micros(1)
+    }
+
+    fn now(&self) -> Duration {
+        Duration::from_micros(self.micros.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl HostMonotonicClock for StepClock {
+    fn resolution(&self) -> u64 {
+        1000
+    }
+
+    fn now(&self) -> u64 {
+        self.micros.fetch_add(1, Ordering::Relaxed) * 1000
+    }
+}
+
+fn random_bytes() -> Vec<u8> {
+    // xorshift with a fixed seed
+    let mut x: u32 = 0x9e37_79b9;
+    (0..4096).map(|_| {
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        x as u8
+    }).collect()
+}
+
+/// The environment of converters: closed stdio, no environment variables, arguments, files or
+/// network, deterministic clocks and random numbers.
+pub fn sandbox() -> WasiCtxBuilder {
+    let mut builder = WasiCtxBuilder::new();
+    builder
+        .wall_clock(StepClock::default())
+        .monotonic_clock(StepClock::default())
+        .secure_random(Deterministic::new(random_bytes()))
+        .insecure_random(Deterministic::new(random_bytes()))
+        .insecure_random_seed(0)
+        .allow_tcp(false)
+        .allow_udp(false)
+        .allow_ip_name_lookup(false);
+    builder
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{testing, WasmtimeConverterModule};
+    use wasmtime::Engine;
+    use fg_plugin::GalaxyFormatPluginV1_;
+
+    // `present` returns the low word of the realtime clock in nanoseconds
+    fn clock_wat(import: &str) -> String {
+        let items = format!(r#"(import "wasi_snapshot_preview1" "{}" (func $clock_time_get (param i32 i64 i32) (result i32)))"#, import);
+        testing::stub_wat(&items, &[
+            ("present", "(drop (call $clock_time_get (i32.const 0) (i64.const 1) (i32.const 64))) (i32.load (i32.const 64))"),
+        ])
+    }
+
+    #[test]
+    fn test_capabilities() {
+        assert_eq!(preview1_capability("random_get"), Some(WasiCapability::Random));
+        assert_eq!(preview1_capability("fd_write"), Some(WasiCapability::Stdio));
+        assert_eq!(preview1_capability("path_open"), None);
+        assert_eq!(preview2_capability("wasi:clocks/wall-clock@0.2.0"), Some(WasiCapability::Clock));
+        assert_eq!(preview2_capability("wasi:cli/environment@0.2.0"), Some(WasiCapability::Env));
+        assert_eq!(preview2_capability("wasi:cli/stderr@0.2.0"), Some(WasiCapability::Stdio));
+        assert_eq!(preview2_capability("wasi:sockets/tcp@0.2.0"), None);
+    }
+
+    #[test]
+    fn test_sandbox() {
+        let engine = Engine::default();
+        let wat = clock_wat("clock_time_get");
+        // undeclared capabilities are refused
+        let err = WasmtimeConverterModule::from_bytes(&engine, wat.as_bytes(), &[]).err().unwrap();
+        assert!(err.to_string().contains("clock"), "{}", err);
+
+        let module = WasmtimeConverterModule::from_bytes(&engine, wat.as_bytes(), &[WasiCapability::Clock]).unwrap();
+        // the clock is the same for every instance
+        for _ in 0..2 {
+            let mut plugin = module.instantiate().unwrap();
+            assert_eq!(plugin.present(0, 0).unwrap(), 0);
+            assert_eq!(plugin.present(0, 0).unwrap(), 1000);
+        }
+
+        // never available, even if everything is declared
+        let wat = clock_wat("path_open");
+        let all = [WasiCapability::Stdio, WasiCapability::Clock, WasiCapability::Random, WasiCapability::Env];
+        assert!(WasmtimeConverterModule::from_bytes(&engine, wat.as_bytes(), &all).is_err());
+    }
+}
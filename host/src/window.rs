@@ -0,0 +1,196 @@
+/* Presenting parts of large files, window by window
+
+Only the chunk of the file needed for the next window is read, see `fg_plugin::next_window`.
+*/
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use fg_plugin::{next_window, GalaxyFormatPluginV1, Window};
+
+use crate::{is_fg_file, HEADER_LEN};
+
+const CHUNK_SIZE: u64 = 64 * 1024;
+
+pub struct WindowedPresenter<'a> {
+    plugin: &'a mut dyn GalaxyFormatPluginV1,
+    file: File,
+    // offset of the payload in the file
+    payload_start: u64,
+    // offsets below are relative to the payload
+    pos: u64,
+    end: u64,
+}
+
+impl<'a> WindowedPresenter<'a> {
+    /// Presents the payload of the file at `path` (the whole file if it isn't a container).
+    ///
+    /// Only the records from `start` to `end` (the end of the payload if not set) are presented.
+    /// `start` must be at a record boundary.
+    pub fn open(plugin: &'a mut dyn GalaxyFormatPluginV1, path: &Path, start: u64, end: Option<u64>) -> Result<Self> {
+        let file = File::open(path)?;
+        let payload_start = if is_fg_file(path) { HEADER_LEN } else { 0 };
+        let payload_len = file.metadata()?.len().saturating_sub(payload_start);
+        let range = start..end.unwrap_or(payload_len);
+        if range.start > range.end || range.end > payload_len {
+            return Err(anyhow!("The range {}..{} is outside of the content (0..{})", range.start, range.end, payload_len));
+        }
+        Ok(WindowedPresenter {
+            plugin,
+            file,
+            payload_start,
+            pos: range.start,
+            end: range.end,
+        })
+    }
+
+    /// Continuation token: the offset in the payload where the next window starts.
+    pub fn position(&self) -> u64 {
+        self.pos
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.pos >= self.end
+    }
+
+    /// Presents up to `max_records` further records, `None` at the end of the range.
+    ///
+    /// Windows may hold fewer records than requested, e.g. when they end at a chunk boundary.
+    pub fn next_window(&mut self, max_records: u32) -> Result<Option<Window>> {
+        if self.is_done() {
+            return Ok(None);
+        }
+        let (file, payload_start) = (&mut self.file, self.payload_start);
+        let read = |pos, len| read(file, payload_start + pos, len);
+        let window = next_window(self.plugin, read, self.pos, self.end, CHUNK_SIZE, max_records)?
+            .map_err(|e| anyhow!(e))?;
+        self.pos = window.next;
+        Ok(Some(window))
+    }
+}
+
+fn read(file: &mut File, pos: u64, len: u64) -> Result<Vec<u8>> {
+    file.seek(SeekFrom::Start(pos))?;
+    let mut bytes = vec!();
+    file.take(len).read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Parses a byte range like `100..200` or `0x100..` (open ranges end at the end of the file).
+pub fn parse_range(s: &str) -> Result<(u64, Option<u64>), String> {
+    fn parse_offset(s: &str) -> Result<u64, String> {
+        let res = match s.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16),
+            None => s.parse(),
+        };
+        res.map_err(|_| format!("Invalid offset '{}'", s))
+    }
+
+    let (start, end) = s.split_once("..").ok_or_else(|| format!("Expected a range like 100..200, found '{}'", s))?;
+    let start = if start.is_empty() { 0 } else { parse_offset(start)? };
+    let end = if end.is_empty() { None } else { Some(parse_offset(end)?) };
+    Ok((start, end))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{write_file, NativeGalaxyFormatPlugin};
+    use fg_index::FormatId;
+    use format_galaxy_core::GalaxyFormat;
+
+    // records are a length byte followed by that many bytes, presented as their length
+    struct Records {}
+
+    impl GalaxyFormat for Records {
+        fn present(_bytes: &[u8]) -> Result<String, String> {
+            Err("Records are only presented in windows".to_string())
+        }
+
+        fn store(_s: &str) -> Result<Vec<u8>, String> {
+            Err("Records can't be stored".to_string())
+        }
+
+        fn present_window(chunk: &[u8], offset: u64, max_records: u32) -> Result<Window, String> {
+            let mut text = String::new();
+            let mut pos = 0;
+            let mut records = 0;
+            while records < max_records {
+                match chunk.get(pos) {
+                    Some(len) if pos + 1 + (*len as usize) <= chunk.len() => {
+                        text.push_str(&format!("{};", len));
+                        pos += 1 + *len as usize;
+                        records += 1;
+                    }
+                    _ => break,
+                }
+            }
+            Ok(Window { text, records, next: offset + pos as u64 })
+        }
+    }
+
+    // reports one record more than it was asked for
+    struct TooMany {}
+
+    impl GalaxyFormat for TooMany {
+        fn present(bytes: &[u8]) -> Result<String, String> {
+            Records::present(bytes)
+        }
+
+        fn store(s: &str) -> Result<Vec<u8>, String> {
+            Records::store(s)
+        }
+
+        fn present_window(chunk: &[u8], offset: u64, max_records: u32) -> Result<Window, String> {
+            let window = Records::present_window(chunk, offset, max_records)?;
+            Ok(Window { records: window.records + 1, ..window })
+        }
+    }
+
+    #[test]
+    fn test_windows() {
+        let path = std::env::temp_dir().join(format!("fg-window-test-{}.fg", std::process::id()));
+        let mut payload = vec!(2, 0, 0, 0, 1, 0);
+        payload.push(200);
+        payload.extend(std::iter::repeat_n(0, 200));
+        write_file(&path, FormatId(1), &payload).unwrap();
+
+        let mut plugin = NativeGalaxyFormatPlugin::<Records>::new();
+        let mut presenter = WindowedPresenter::open(&mut plugin, &path, 0, None).unwrap();
+        assert_eq!(presenter.next_window(2).unwrap(), Some(Window { text: "2;0;".to_string(), records: 2, next: 4 }));
+        assert_eq!(presenter.position(), 4);
+        assert_eq!(presenter.next_window(5).unwrap().unwrap().text, "1;200;");
+        assert!(presenter.is_done());
+        assert_eq!(presenter.next_window(5).unwrap(), None);
+
+        // the record at offset 4 is cut off by the range
+        let mut presenter = WindowedPresenter::open(&mut plugin, &path, 3, Some(5)).unwrap();
+        assert_eq!(presenter.next_window(5).unwrap().unwrap().text, "0;");
+        assert!(presenter.next_window(5).is_err());
+        assert!(WindowedPresenter::open(&mut plugin, &path, 0, Some(1000)).is_err());
+
+        // converters without windows
+        let mut plugin = NativeGalaxyFormatPlugin::<byte_sequence::Impl>::new();
+        let mut presenter = WindowedPresenter::open(&mut plugin, &path, 0, None).unwrap();
+        assert!(presenter.next_window(1).is_err());
+
+        let mut plugin = NativeGalaxyFormatPlugin::<TooMany>::new();
+        let mut presenter = WindowedPresenter::open(&mut plugin, &path, 0, None).unwrap();
+        let err = presenter.next_window(2).unwrap_err();
+        assert!(err.to_string().contains("3 records"), "{}", err);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("16..32"), Ok((16, Some(32))));
+        assert_eq!(parse_range("0x10.."), Ok((16, None)));
+        assert_eq!(parse_range("..0x20"), Ok((0, Some(32))));
+        assert!(parse_range("16").is_err());
+        assert!(parse_range("a..b").is_err());
+    }
+}
@@ -0,0 +1,180 @@
+/* Moving raw files into the container format and back
+
+`wrap` prepends the container header to a file of a known format, `unwrap` strips it again and
+names the file after the first extension of its format.
+*/
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use fg_index::FormatId;
+
+use crate::{detect_formats, file_extension, is_fg_file, read_file, read_format_id, write_file, ConverterRegistry};
+
+/// Glob pattern matching the input files of `unwrap` in directories.
+pub const UNWRAP_PATTERN: &str = "*.fg";
+
+/// A single wrap or unwrap operation, planned but not yet carried out.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WrapPlan {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    pub format_id: FormatId,
+}
+
+/// Plans wrapping the raw file `input` into a container next to it (`data.bson` -> `data.fg`).
+///
+/// Without `format_id`, the format is detected from the file's content and extension. This
+/// fails if no format or several equally likely formats are found.
+pub fn plan_wrap<R: ConverterRegistry + ?Sized>(registry: &R, input: &Path, format_id: Option<FormatId>) -> Result<WrapPlan> {
+    if is_fg_file(input) {
+        return Err(anyhow!("{} already uses the container format", input.display()));
+    }
+    let format_id = match format_id {
+        Some(format_id) => {
+            registry.format(format_id)?;
+            format_id
+        }
+        None => detect_format(registry, input)?,
+    };
+    Ok(WrapPlan {
+        input: input.to_path_buf(),
+        output: input.with_extension("fg"),
+        format_id,
+    })
+}
+
+fn detect_format<R: ConverterRegistry + ?Sized>(registry: &R, input: &Path) -> Result<FormatId> {
+    let bytes = std::fs::read(input)?;
+    let candidates = detect_formats(registry, &bytes, file_extension(input));
+    let best = candidates.first()
+        .ok_or_else(|| anyhow!("Couldn't detect the format of {} (use --format)", input.display()))?;
+    let equally_likely: Vec<_> = candidates.iter()
+        .filter(|c| c.confidence == best.confidence)
+        .map(|c| format!("{} ({})", registry.galaxy().formats[&c.format_id].name, c.format_id.0))
+        .collect();
+    if equally_likely.len() > 1 {
+        return Err(anyhow!("The format of {} is ambiguous, it could be {} (use --format)", input.display(), equally_likely.join(", ")));
+    }
+    Ok(best.format_id)
+}
+
+/// Plans unwrapping the container `input` into a raw file.
+///
+/// Without `output`, the file is named after the first extension of its format (`data.fg` ->
+/// `data.bson`).
+pub fn plan_unwrap<R: ConverterRegistry + ?Sized>(registry: &R, input: &Path, output: Option<&Path>) -> Result<WrapPlan> {
+    let format_id = read_format_id(input)?;
+    let output = match output {
+        Some(output) => output.to_path_buf(),
+        None => {
+            let format = registry.format(format_id)?;
+            let ext = format.extensions.first()
+                .ok_or_else(|| anyhow!("Format '{}' has no file extension, the output needs to be named explicitly", format.name))?;
+            unwrapped_path(input, ext)
+        }
+    };
+    Ok(WrapPlan {
+        input: input.to_path_buf(),
+        output,
+        format_id,
+    })
+}
+
+fn unwrapped_path(input: &Path, ext: &str) -> PathBuf {
+    let stem = if is_fg_file(input) { input.with_extension("") } else { input.to_path_buf() };
+    if file_extension(&stem) == Some(ext) {
+        return stem;
+    }
+    let mut name = OsString::from(stem);
+    name.push(".");
+    name.push(ext);
+    PathBuf::from(name)
+}
+
+/// Writes the container planned by `plan_wrap`. Existing files are only replaced if `force` is set.
+pub fn wrap(plan: &WrapPlan, force: bool) -> Result<()> {
+    check_output(plan, force)?;
+    let bytes = std::fs::read(&plan.input)?;
+    write_file(&plan.output, plan.format_id, &bytes)
+}
+
+/// Writes the raw file planned by `plan_unwrap`. Existing files are only replaced if `force` is set.
+pub fn unwrap(plan: &WrapPlan, force: bool) -> Result<()> {
+    check_output(plan, force)?;
+    let (_format_id, bytes) = read_file(&plan.input)?;
+    std::fs::write(&plan.output, bytes)?;
+    Ok(())
+}
+
+fn check_output(plan: &WrapPlan, force: bool) -> Result<()> {
+    if plan.output == plan.input {
+        return Err(anyhow!("{} can't be replaced by itself", plan.input.display()));
+    }
+    if !force && plan.output.exists() {
+        return Err(anyhow!("{} already exists (use --force to overwrite it)", plan.output.display()));
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LocalRegistry;
+    use fg_index::Galaxy;
+
+    #[test]
+    fn test_unwrapped_path() {
+        assert_eq!(unwrapped_path(Path::new("a/data.fg"), "bson"), PathBuf::from("a/data.bson"));
+        assert_eq!(unwrapped_path(Path::new("data.bson.fg"), "bson"), PathBuf::from("data.bson"));
+        assert_eq!(unwrapped_path(Path::new("data.v1.fg"), "bson"), PathBuf::from("data.v1.bson"));
+        assert_eq!(unwrapped_path(Path::new("data"), "wasm"), PathBuf::from("data.wasm"));
+    }
+
+    #[test]
+    fn test_wrap_unwrap() {
+        let dir = std::env::temp_dir().join(format!("fg-wrap-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let galaxy = Galaxy::from_json(Path::new("../fg-index/test_index.json")).unwrap();
+        let registry = LocalRegistry::new(galaxy, "../fg-index/converters/");
+
+        // detected by its magic bytes, despite the extension
+        let module = b"\0asm\x01\0\0\0".to_vec();
+        std::fs::write(dir.join("module.bin"), &module).unwrap();
+        let plan = plan_wrap(&registry, &dir.join("module.bin"), None).unwrap();
+        assert_eq!(plan.output, dir.join("module.fg"));
+        assert_eq!(plan.format_id, FormatId(300));
+        wrap(&plan, false).unwrap();
+        assert_eq!(read_file(&dir.join("module.fg")).unwrap(), (FormatId(300), module.clone()));
+        assert!(wrap(&plan, false).is_err());
+        wrap(&plan, true).unwrap();
+
+        let plan = plan_unwrap(&registry, &dir.join("module.fg"), None).unwrap();
+        assert_eq!(plan.output, dir.join("module.wasm"));
+        unwrap(&plan, false).unwrap();
+        assert_eq!(std::fs::read(dir.join("module.wasm")).unwrap(), module);
+
+        // two formats use the `bson` extension and the bson converter doesn't recognize the bytes
+        std::fs::write(dir.join("data.bson"), [1, 2, 3]).unwrap();
+        let err = plan_wrap(&registry, &dir.join("data.bson"), None).unwrap_err();
+        assert!(err.to_string().contains("ambiguous"));
+        let plan = plan_wrap(&registry, &dir.join("data.bson"), Some(FormatId(200))).unwrap();
+        assert_eq!(plan.output, dir.join("data.fg"));
+        assert!(plan_wrap(&registry, &dir.join("data.bson"), Some(FormatId(12345))).is_err());
+        assert!(plan_wrap(&registry, &dir.join("module.fg"), None).is_err());
+        // unless the converter sniffs a document
+        std::fs::write(dir.join("doc.bson"), [5, 0, 0, 0, 0]).unwrap();
+        assert_eq!(plan_wrap(&registry, &dir.join("doc.bson"), None).unwrap().format_id, FormatId(200));
+
+        // the byte sequence format has no extension
+        write_file(&dir.join("bytes.fg"), FormatId(2), &[1, 2]).unwrap();
+        assert!(plan_unwrap(&registry, &dir.join("bytes.fg"), None).is_err());
+        let plan = plan_unwrap(&registry, &dir.join("bytes.fg"), Some(&dir.join("bytes.raw"))).unwrap();
+        unwrap(&plan, false).unwrap();
+        assert_eq!(std::fs::read(dir.join("bytes.raw")).unwrap(), vec!(1, 2));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,8 +1,7 @@
 use yew::prelude::*;
-use yew::services::storage::{Area, StorageService};
 use yew::services::fetch::{FetchService, FetchTask, Request, Response};
 
-use yew::services::reader::{File, FileChunk, FileData, ReaderService, ReaderTask};
+use yew::services::reader::{File, FileData, ReaderService, ReaderTask};
 use yew::{html, ChangeData, Component, ComponentLink, Html, ShouldRender};
 
 
@@ -11,14 +10,19 @@ use yewtil::future::LinkFuture;
 use fg_index::Galaxy;
 use fg_index::FormatId;
 use fg_index::ConverterId;
+use fg_index::{Converter, ConverterOption, FileFormat, OptionKind, Version};
 
+use crate::cache;
+use crate::editor::{error_span, view_hex};
 use crate::plugin::GalaxyFormatPluginV1;
-use crate::plugin::WebGalaxyFormatPlugin;
+use crate::plugin::{WebGalaxyFormatPlugin, WINDOW_CHUNK_SIZE};
+use crate::settings::Sources;
+use fg_plugin::{Options, StyledText};
 
 use yew::format::Nothing;
+use yew::NodeRef;
 
-const INDEX_URL: &str = "https://raw.githubusercontent.com/fkohlgrueber/format-galaxy/main/fg-index/test_index.json";
-const PLUGIN_URL: &str = "https://raw.githubusercontent.com/fkohlgrueber/format-galaxy/main/fg-index/converters/";
+use wasm_bindgen::{JsCast, JsValue};
 
 enum Selection {
     None,
@@ -61,6 +65,10 @@ impl Selection {
 
 pub struct App {
     link: ComponentLink<Self>,
+    sources: Sources,
+    // contents of the settings panel, applied to `sources` with its button
+    index_input: String,
+    plugin_input: String,
     galaxy: Option<Galaxy>,
     formats: Vec<(FormatId, String)>,
     ft: Option<FetchTask>,
@@ -68,7 +76,21 @@ pub struct App {
     plugin: Option<WebGalaxyFormatPlugin>,
     status: String,
     input_text: String,
+    // highlighted presentation of `bytes`
+    preview: Option<StyledText>,
+    // converter options changed in the form, the converter's defaults apply to all others
+    option_values: Vec<(String, String)>,
     bytes: Option<Vec<u8>>,
+    // the last bytes the text stored to while it doesn't store, shown as stale
+    stale_bytes: Option<Vec<u8>>,
+    // the bytes of the opened file, changes are marked against them
+    original: Option<Vec<u8>>,
+    // part of `input_text` the last store error is about
+    error_span: Option<std::ops::Range<usize>>,
+    editor_ref: NodeRef,
+    backdrop_ref: NodeRef,
+    // name of the opened file, downloads are named after it
+    file_name: Option<String>,
     reader_task: Option<ReaderTask>,
     reader_service: ReaderService,
 }
@@ -78,13 +100,25 @@ pub enum Msg {
     ConverterChange(ChangeData),
     VersionChange(ChangeData),
     FetchReady(String),
+    IndexFetchFailed(String),
+    CachedIndexReady(String),
+    PluginFetched(String, Vec<u8>),
+    PluginFetchFailed(String),
+    FetchPlugin(String, String),
     PluginFetchReady(Vec<u8>),
     PluginReady(WebGalaxyFormatPlugin),
     InputChanged(String),
+    EditorScrolled,
+    OptionChanged(String, String),
+    IndexInputChanged(String),
+    PluginInputChanged(String),
+    ApplySources,
+    Status(String),
     Nothing,
     OpenFile,
     FormatSource,
     Download,
+    DownloadRaw,
     OpenFileObj(File),
     FileLoaded(FileData)
 }
@@ -94,53 +128,84 @@ impl Component for App {
     type Properties = ();
 
     fn create(_: Self::Properties, link: ComponentLink<Self>) -> Self {
-        
-        let request = Request::get(INDEX_URL)
-            .body(Nothing)
-            .expect("Could not build that request");
-        let callback = link.callback(
-            move |response: Response<yew::format::Text>| {
-                let (meta, data) = response.into_parts();
-                if meta.status.is_success() {
-                    Msg::FetchReady(data.unwrap())
-                } else {
-                    Msg::Nothing
-                }
-            },
-        );
-        let ft = FetchService::fetch(request, callback).unwrap();
-
-        let app = App {
+        let sources = Sources::load();
+        let mut app = App {
             link,
+            index_input: sources.index_url.clone(),
+            plugin_input: sources.explicit_plugin_url().unwrap_or_default().to_string(),
+            sources,
             galaxy: None,
-            ft: Some(ft),
+            ft: None,
             formats: vec!(),
             selection: Selection::None,
             plugin: None,
             status: String::new(),
             input_text: "type here".to_string(),
+            preview: None,
+            option_values: vec!(),
             bytes: None,
+            stale_bytes: None,
+            original: None,
+            error_span: None,
+            editor_ref: NodeRef::default(),
+            backdrop_ref: NodeRef::default(),
+            file_name: None,
             reader_task: None,
             reader_service: ReaderService::new(),
         };
-
+        app.fetch_index();
         app
     }
 
     fn update(&mut self, msg: Self::Message) -> ShouldRender {
         match msg {
             Msg::FetchReady(s) => {
-                match Galaxy::from_json_str(&s) {
-                    Ok(g) => {
-                        self.formats = g.formats.iter().map(|(k, v)| (k.clone(), v.name.clone())).collect::<Vec<_>>();
-                        self.galaxy = Some(g);
-                        
-                    }
-                    Err(_e) => {
-                        yew::services::ConsoleService::log("Couldn't fetch galaxy index.");
+                self.ft = None;
+                if self.load_index(&s) {
+                    // kept for when the index can't be fetched
+                    let key = cache::index_key(&self.sources.index_url);
+                    wasm_bindgen_futures::spawn_local(async move {
+                        if let Err(e) = cache::store(&key, s.as_bytes()).await {
+                            yew::services::ConsoleService::log(&format!("Couldn't cache the index: {:?}", e));
+                        }
+                    });
+                }
+            }
+            Msg::IndexFetchFailed(reason) => {
+                self.ft = None;
+                let key = cache::index_key(&self.sources.index_url);
+                self.link.send_future(async move {
+                    match cache::load(&key).await {
+                        Ok(Some(bytes)) => Msg::CachedIndexReady(String::from_utf8_lossy(&bytes).into_owned()),
+                        _ => Msg::Status(format!("Couldn't fetch the index: {}", reason)),
                     }
+                })
+            }
+            Msg::CachedIndexReady(s) => {
+                if self.load_index(&s) {
+                    self.status = "Offline, using the cached index".to_string();
+                }
+            }
+            Msg::PluginFetched(hash, bytes) => {
+                self.ft = None;
+                if !cache::verify(&hash, &bytes) {
+                    self.status = format!("The converter {}.wasm doesn't match its hash, refusing to load it", hash);
+                } else {
+                    let key = cache::plugin_key(&hash);
+                    let cached = bytes.clone();
+                    wasm_bindgen_futures::spawn_local(async move {
+                        if let Err(e) = cache::store(&key, &cached).await {
+                            yew::services::ConsoleService::log(&format!("Couldn't cache the converter: {:?}", e));
+                        }
+                    });
+                    self.update(Msg::PluginFetchReady(bytes));
                 }
             }
+            Msg::FetchPlugin(hash, url) => self.fetch_plugin(hash, url),
+            Msg::PluginFetchFailed(reason) => {
+                self.ft = None;
+                self.status = format!("Couldn't fetch the converter: {}", reason);
+            }
             Msg::PluginFetchReady(bytes) => {
                 self.link.send_future(async move {
                     match WebGalaxyFormatPlugin::from_slice(&bytes).await {
@@ -150,9 +215,13 @@ impl Component for App {
                 })
             }
             Msg::PluginReady(mut plugin) => {
-                if let Some(bytes) = &mut self.bytes {
-                    if let Ok(Ok(s)) = plugin.present(&bytes) {
-                        self.input_text = s;
+                let options = self.options();
+                let windowed = self.is_windowed();
+                if let Some(bytes) = &self.bytes {
+                    if let Ok(Ok(styled)) = present(&mut plugin, windowed, bytes, &options) {
+                        self.input_text = styled.text.clone();
+                        self.preview = Some(styled);
+                        self.error_span = None;
                     }
                 }
                 self.plugin = Some(plugin);
@@ -161,24 +230,69 @@ impl Component for App {
                 
             }
             Msg::InputChanged(s) => {
+                let options = self.options();
+                let windowed = self.is_windowed();
                 if let Some(plugin) = &mut self.plugin {
-                    match plugin.store(&s) {
+                    match plugin.store_located(&s, &options) {
                         Err(e) => {
                             self.status = format!("Fatal error: {}", e);
-                            self.bytes = None;
+                            self.error_span = None;
+                            self.set_stale();
                         },
                         Ok(Err(e)) => {
-                            self.status = format!("Err: {}", e);
-                            self.bytes = None;
+                            self.status = format!("Err: {}", e.message);
+                            self.error_span = error_span(&s, &e);
+                            self.set_stale();
                         },
                         Ok(Ok(bytes)) => {
-                            self.status = format!("Ok");
+                            self.status = "Ok".to_string();
+                            self.preview = present(plugin, windowed, &bytes, &options).ok().and_then(|res| res.ok());
                             self.bytes = Some(bytes);
+                            self.stale_bytes = None;
+                            self.error_span = None;
                         },
                     }
                 }
                 self.input_text = s;
             }
+            Msg::EditorScrolled => {
+                // the backdrop with the error marks follows the text
+                if let (Some(editor), Some(backdrop)) = (self.editor_ref.cast::<web_sys::Element>(), self.backdrop_ref.cast::<web_sys::Element>()) {
+                    backdrop.set_scroll_top(editor.scroll_top());
+                    backdrop.set_scroll_left(editor.scroll_left());
+                }
+                return false;
+            }
+            Msg::OptionChanged(name, value) => {
+                let valid = self.selected_version()
+                    .and_then(|v| v.options.iter().find(|o| o.name == name))
+                    .map(|o| o.check(&value));
+                match valid {
+                    Some(Ok(())) => {
+                        self.option_values.retain(|(n, _v)| *n != name);
+                        self.option_values.push((name, value));
+                        // present again with the new layout
+                        self.update(Msg::FormatSource);
+                    }
+                    Some(Err(e)) => self.status = format!("Err: {}", e),
+                    None => {}
+                }
+            }
+            Msg::IndexInputChanged(s) => self.index_input = s,
+            Msg::PluginInputChanged(s) => self.plugin_input = s,
+            Msg::ApplySources => {
+                self.sources = Sources::new(Some(&self.index_input), Some(&self.plugin_input));
+                self.sources.save();
+                self.index_input = self.sources.index_url.clone();
+                self.plugin_input = self.sources.explicit_plugin_url().unwrap_or_default().to_string();
+                self.galaxy = None;
+                self.formats.clear();
+                self.selection = Selection::None;
+                self.plugin = None;
+                self.option_values.clear();
+                self.fetch_index();
+            }
+            Msg::Status(s) => self.status = s,
             Msg::OpenFile => {
                 
             }
@@ -193,22 +307,26 @@ impl Component for App {
 
             }
             Msg::FileLoaded(data) => {
-                let bytes = data.content;
-                // TODO: check that file conforms to fmtgal format, select a converter and present the content
+                self.reader_task = None;
+                self.open_file(data.name, data.content);
             }
             Msg::FormatSource => {
+                let options = self.options();
+                let windowed = self.is_windowed();
                 if let Some(bytes) = &self.bytes {
                     if let Some(plugin) = &mut self.plugin {
-                        match plugin.present(bytes) {
+                        match present(plugin, windowed, bytes, &options) {
                             Err(e) => {
                                 self.status = format!("Fatal error: {}", e);
                             },
                             Ok(Err(e)) => {
                                 self.status = format!("Err: {}", e);
                             },
-                            Ok(Ok(s)) => {
+                            Ok(Ok(styled)) => {
                                 //self.status = format!("Ok");
-                                self.input_text = s;
+                                self.input_text = styled.text.clone();
+                                self.preview = Some(styled);
+                                self.error_span = None;
                             },
                         }
                     }
@@ -216,7 +334,24 @@ impl Component for App {
                 }
             }
             Msg::Download => {
-                // TODO: wrap self.bytes in fmtgal format and trigger download
+                if let (Some(bytes), Some(fid)) = (&self.bytes, self.selection.get_format()) {
+                    let container = fg_container::encode(fid.0, bytes);
+                    let name = format!("{}.fg", self.file_stem());
+                    if let Err(e) = download(&name, &container) {
+                        self.status = format!("Download failed: {:?}", e);
+                    }
+                }
+            }
+            Msg::DownloadRaw => {
+                if let Some(bytes) = &self.bytes {
+                    let name = match self.selected_format().and_then(|f| f.extensions.first()) {
+                        Some(ext) => format!("{}.{}", self.file_stem(), ext),
+                        None => self.file_stem().to_string(),
+                    };
+                    if let Err(e) = download(&name, bytes) {
+                        self.status = format!("Download failed: {:?}", e);
+                    }
+                }
             }
             Msg::FormatChange(cd) => {
                 if let ChangeData::Select(elmt) = cd {
@@ -232,6 +367,7 @@ impl Component for App {
                         let cid = ConverterId(id);
                         if let Some(fid) = self.selection.get_format().cloned() {
                             self.selection = Selection::Converter(fid, cid);
+                            self.option_values.clear();
                         }
                     }
                 }
@@ -242,6 +378,11 @@ impl Component for App {
                     if let Some(fid) = self.selection.get_format().cloned() {
                         if let Some(cid) = self.selection.get_converter().cloned() {
                             self.selection = Selection::Version(fid, cid, version);
+                            // values of options the version doesn't declare would be refused
+                            let declared: Vec<String> = self.selected_version()
+                                .map(|v| v.options.iter().map(|o| o.name.clone()).collect())
+                                .unwrap_or_default();
+                            self.option_values.retain(|(name, _v)| declared.contains(name));
 
                             self.update_plugin();
                         }
@@ -274,7 +415,7 @@ impl Component for App {
         let versions = match (&self.galaxy, self.selection.get_format(), self.selection.get_converter()) {
             (Some(g), Some(fid), Some(cid)) => {
                 g.formats.get(fid).unwrap().converters.get(cid).unwrap().versions.iter().map(
-                    |(version, _)| html!(<option value=version selected=self.selection.get_version()==Some(version)>{&version}</option>)
+                    |v| html!(<option value=&v.version selected=self.selection.get_version()==Some(&v.version)>{&v.version}</option>)
                 ).collect()
             }
             _ => vec!()
@@ -283,6 +424,7 @@ impl Component for App {
 
         html! {
             <>
+            {self.view_settings()}
             <div style="padding: 10px;">
                 
                 <span>{"Format: "}</span><select disabled=self.galaxy.is_none() onchange=self.link.callback(|e| Msg::FormatChange(e))>
@@ -315,7 +457,8 @@ impl Component for App {
                 <br />
                 <button onclick=self.link.callback(|_| Msg::OpenFile)>{"Open File"}</button>
                 <button disabled=self.bytes.is_none() onclick=self.link.callback(|_| Msg::FormatSource)>{"Format source"}</button>
-                <button disabled=self.bytes.is_none() onclick=self.link.callback(|_| Msg::Download)>{"Download"}</button>
+                <button disabled=self.bytes.is_none() || self.selection.get_format().is_none() onclick=self.link.callback(|_| Msg::Download)>{"Download"}</button>
+                <button disabled=self.bytes.is_none() onclick=self.link.callback(|_| Msg::DownloadRaw)>{"Download raw"}</button>
                 <input type="file" multiple=false onchange=self.link.callback(move |value| {
                         let file = if let ChangeData::Files(files) = value {
                             files.get(0)
@@ -328,8 +471,17 @@ impl Component for App {
                     })
                 />
                 <br />
-                <textarea disabled=self.plugin.is_none() oninput=self.link.callback(|s: InputData| Msg::InputChanged(s.value)) value={&self.input_text}>{&self.input_text}</textarea>
+                {self.view_options()}
+                <div class="fg-split">
+                    {self.view_editor()}
+                    {match (&self.bytes, &self.stale_bytes) {
+                        (Some(bytes), _) => view_hex(bytes, self.original.as_deref(), false),
+                        (None, Some(bytes)) => view_hex(bytes, self.original.as_deref(), true),
+                        (None, None) => html!(<pre class="fg-hex"></pre>),
+                    }}
+                </div>
                 <textarea disabled=true value={&self.status}>{&self.status}</textarea>
+                {self.preview.as_ref().map(view_styled).unwrap_or_else(|| html!())}
             </div>
             </>
         }
@@ -341,34 +493,301 @@ impl Component for App {
 }
 
 impl App {
+    fn selected_format(&self) -> Option<&FileFormat> {
+        self.galaxy.as_ref()?.formats.get(self.selection.get_format()?)
+    }
+
+    fn selected_converter(&self) -> Option<&Converter> {
+        let fid = self.selection.get_format()?;
+        let cid = self.selection.get_converter()?;
+        self.galaxy.as_ref()?.formats.get(fid)?.converters.get(cid)
+    }
+
+    fn selected_version(&self) -> Option<&Version> {
+        let version = self.selection.get_version()?;
+        self.selected_converter()?.versions.iter().find(|v| v.version == version)
+    }
+
+    // containers select their format, other files the format with their extension
+    fn open_file(&mut self, name: String, content: Vec<u8>) {
+        let (format_id, bytes) = if fg_container::is_container(&content) {
+            match fg_container::decode(&content) {
+                Ok((format_id, payload)) => (Some(FormatId(format_id)), payload.to_vec()),
+                Err(e) => {
+                    self.status = format!("Err: {}", e);
+                    return;
+                }
+            }
+        } else {
+            let ext = name.rsplit_once('.').map(|(_, ext)| ext.to_ascii_lowercase());
+            let format_id = self.galaxy.as_ref().zip(ext).and_then(|(g, ext)| {
+                g.formats.iter().find(|(_, f)| f.extensions.contains(&ext)).map(|(fid, _)| *fid)
+            });
+            (format_id, content)
+        };
+        self.file_name = Some(name);
+        self.original = Some(bytes.clone());
+        self.bytes = Some(bytes);
+        self.stale_bytes = None;
+        self.error_span = None;
+        self.preview = None;
+
+        let format_name = format_id.and_then(|fid| Some(self.galaxy.as_ref()?.formats.get(&fid)?.name.clone()));
+        match (format_id, format_name) {
+            (Some(fid), Some(_)) if self.selection.get_format() == Some(&fid) && self.plugin.is_some() => {
+                // the converter is already loaded
+                self.status = "Ok".to_string();
+                self.update(Msg::FormatSource);
+            }
+            (Some(fid), Some(name)) => {
+                self.selection = Selection::Format(fid);
+                self.plugin = None;
+                self.option_values.clear();
+                self.status = format!("Format: {}, select a converter", name);
+            }
+            (Some(fid), None) => self.status = format!("Unknown format ({}), select one", fid.0),
+            (None, _) => self.status = "Not a container, select the format of the file".to_string(),
+        }
+    }
+
+    // keeps showing the last bytes that stored, until the text stores again
+    fn set_stale(&mut self) {
+        if let Some(bytes) = self.bytes.take() {
+            self.stale_bytes = Some(bytes);
+        }
+        self.preview = None;
+    }
+
+    // the text area over a backdrop with the same text, which marks the span of store errors
+    fn view_editor(&self) -> Html {
+        let text = &self.input_text;
+        let marks = match &self.error_span {
+            Some(span) if text.get(span.clone()).is_some() => {
+                // empty spans (e.g. at the end of the text) are marked as a space
+                let error = if span.is_empty() { " " } else { &text[span.clone()] };
+                html! {
+                    <>
+                        {&text[..span.start]}
+                        <mark class="fg-error">{error}</mark>
+                        {&text[span.end..]}
+                    </>
+                }
+            }
+            _ => html!({text}),
+        };
+        html! {
+            <div class="fg-editor">
+                // the trailing newline keeps the heights equal when the text ends with one
+                <pre class="fg-backdrop" ref=self.backdrop_ref.clone()>{marks}{"\n"}</pre>
+                <textarea ref=self.editor_ref.clone()
+                    disabled=self.plugin.is_none()
+                    oninput=self.link.callback(|s: InputData| Msg::InputChanged(s.value))
+                    onscroll=self.link.callback(|_| Msg::EditorScrolled)
+                    value={text}>{text}</textarea>
+            </div>
+        }
+    }
+
+    // name of downloads without extension
+    fn file_stem(&self) -> &str {
+        match &self.file_name {
+            Some(name) => name.strip_suffix(".fg")
+                .or_else(|| name.rsplit_once('.').map(|(stem, _)| stem))
+                .unwrap_or(name),
+            None => "data",
+        }
+    }
+
+    fn is_windowed(&self) -> bool {
+        self.selected_converter().is_some_and(|c| c.windowed)
+    }
+
+    // option values are checked when they're changed, so they are all valid
+    fn options(&self) -> Options {
+        let mut options = Options::new();
+        for (name, value) in &self.option_values {
+            let _ = options.set(name, value);
+        }
+        options
+    }
+
+    fn option_value(&self, option: &ConverterOption) -> String {
+        self.option_values.iter()
+            .find(|(name, _v)| *name == option.name)
+            .map(|(_name, v)| v.clone())
+            .unwrap_or_else(|| option.default_value())
+    }
+
+    // one form control per option of the selected version
+    fn view_options(&self) -> Html {
+        let options = match self.selected_version() {
+            Some(v) if !v.options.is_empty() => &v.options,
+            _ => return html!(),
+        };
+        html! {
+            <div class="fg-options">
+                {for options.iter().map(|o| self.view_option(o))}
+            </div>
+        }
+    }
+
+    fn view_option(&self, option: &ConverterOption) -> Html {
+        let name = option.name.clone();
+        let value = self.option_value(option);
+        let control = match &option.kind {
+            OptionKind::Bool { .. } => {
+                let checked = value == "true";
+                let new_value = (!checked).to_string();
+                html!(<input type="checkbox" checked=checked onclick=self.link.callback(move |_| Msg::OptionChanged(name.clone(), new_value.clone()))/>)
+            }
+            OptionKind::Int { min, max, .. } => {
+                let min = min.map(|n| n.to_string()).unwrap_or_default();
+                let max = max.map(|n| n.to_string()).unwrap_or_default();
+                html!(<input type="number" min=min max=max value=value oninput=self.link.callback(move |e: InputData| Msg::OptionChanged(name.clone(), e.value))/>)
+            }
+            OptionKind::Choice { choices, .. } => html! {
+                <select onchange=self.link.callback(move |cd| match cd {
+                    ChangeData::Select(elmt) => Msg::OptionChanged(name.clone(), elmt.value()),
+                    _ => Msg::Nothing,
+                })>
+                    {for choices.iter().map(|c| html!(<option value=c selected=*c==value>{c}</option>))}
+                </select>
+            },
+        };
+        html! {
+            <label title=option.desc.clone()>{format!("{}: ", option.name)}{control}</label>
+        }
+    }
+
+    // the index is always fetched, the cached copy is only used if that fails
+    fn fetch_index(&mut self) {
+        let request = Request::get(&self.sources.index_url)
+            .body(Nothing)
+            .expect("Could not build that request");
+        let callback = self.link.callback(
+            move |response: Response<yew::format::Text>| {
+                let (meta, data) = response.into_parts();
+                match data {
+                    Ok(data) if meta.status.is_success() => Msg::FetchReady(data),
+                    Ok(_) => Msg::IndexFetchFailed(meta.status.to_string()),
+                    Err(e) => Msg::IndexFetchFailed(e.to_string()),
+                }
+            },
+        );
+        match FetchService::fetch(request, callback) {
+            Ok(ft) => self.ft = Some(ft),
+            Err(e) => {
+                self.update(Msg::IndexFetchFailed(e.to_string()));
+            }
+        }
+    }
+
+    fn load_index(&mut self, s: &str) -> bool {
+        match Galaxy::from_json_str(s) {
+            Ok(g) => {
+                self.formats = g.formats.iter().map(|(k, v)| (*k, v.name.clone())).collect::<Vec<_>>();
+                self.galaxy = Some(g);
+                true
+            }
+            Err(e) => {
+                self.status = format!("Invalid index: {}", e);
+                false
+            }
+        }
+    }
+
+    // cached converters are used without fetching them again, if they still match their hash
     fn update_plugin(&mut self) {
-        // fetch plugin from index
-        let hash_str = self.get_selected_plugin_hash().unwrap();
+        let hash = match self.get_selected_plugin_hash() {
+            Some(hash) => hash,
+            None => return,
+        };
+        let url = self.sources.plugin_url(&hash);
+        self.status = "Loading converter...".to_string();
+        self.link.send_future(async move {
+            match cache::load(&cache::plugin_key(&hash)).await {
+                Ok(Some(bytes)) if cache::verify(&hash, &bytes) => Msg::PluginFetchReady(bytes),
+                _ => Msg::FetchPlugin(hash, url),
+            }
+        });
+    }
 
-        let request = Request::get(format!("{}{}.wasm", PLUGIN_URL, hash_str))
+    fn fetch_plugin(&mut self, hash: String, url: String) {
+        let request = Request::get(url)
             .body(Nothing)
             .expect("Could not build that request");
         let callback = self.link.callback(
             move |response: Response<yew::format::Binary>| {
                 let (meta, data) = response.into_parts();
-                if meta.status.is_success() {
-                    Msg::PluginFetchReady(data.unwrap())
-                } else {
-                    Msg::Nothing
+                match data {
+                    Ok(data) if meta.status.is_success() => Msg::PluginFetched(hash.clone(), data),
+                    Ok(_) => Msg::PluginFetchFailed(meta.status.to_string()),
+                    Err(e) => Msg::PluginFetchFailed(e.to_string()),
                 }
             },
         );
-        self.ft = Some(FetchService::fetch_binary(request, callback).unwrap());
+        match FetchService::fetch_binary(request, callback) {
+            Ok(ft) => self.ft = Some(ft),
+            Err(e) => self.status = format!("Couldn't fetch the converter: {}", e),
+        }
+    }
 
+    // where the index and converters come from, see `settings`
+    fn view_settings(&self) -> Html {
+        html! {
+            <details class="fg-settings" style="padding: 10px;">
+                <summary>{"Sources"}</summary>
+                <label>{"Index: "}
+                    <input type="url" size=80 value=&self.index_input
+                        oninput=self.link.callback(|e: InputData| Msg::IndexInputChanged(e.value))/>
+                </label>
+                <br />
+                <label>{"Converters: "}
+                    <input type="url" size=80 value=&self.plugin_input placeholder="converters/ next to the index"
+                        oninput=self.link.callback(|e: InputData| Msg::PluginInputChanged(e.value))/>
+                </label>
+                <br />
+                <button onclick=self.link.callback(|_| Msg::ApplySources)>{"Apply"}</button>
+            </details>
+        }
     }
 
     fn get_selected_plugin_hash(&self) -> Option<String> {
         match &self.selection {
             Selection::Version(fid, cid, version) => {
-                return Some(self.galaxy.as_ref()?.formats.get(fid)?.converters.get(cid)?.versions.iter().find(|(v, _)| v == version)?.1.0.clone());
+                Some(self.galaxy.as_ref()?.formats.get(fid)?.converters.get(cid)?.versions.iter().find(|v| &v.version == version)?.hash.0.clone())
             }
-            _ => { return None; }
+            _ => None,
         }
-        
     }
-}
\ No newline at end of file
+}
+
+// saves `bytes` as a file named `name` through a temporary link
+fn download(name: &str, bytes: &[u8]) -> Result<(), JsValue> {
+    let parts = js_sys::Array::of1(&js_sys::Uint8Array::from(bytes));
+    let blob = web_sys::Blob::new_with_u8_array_sequence(&parts)?;
+    let url = web_sys::Url::create_object_url_with_blob(&blob)?;
+    let document = web_sys::window().and_then(|w| w.document()).ok_or("no document")?;
+    let link: web_sys::HtmlAnchorElement = document.create_element("a")?.dyn_into()?;
+    link.set_href(&url);
+    link.set_download(name);
+    link.click();
+    web_sys::Url::revoke_object_url(&url)
+}
+
+// large inputs are presented window by window if the converter supports it, without styles
+fn present(plugin: &mut WebGalaxyFormatPlugin, windowed: bool, bytes: &[u8], options: &Options) -> anyhow::Result<Result<StyledText, String>> {
+    if windowed && options.is_empty() && bytes.len() as u64 > WINDOW_CHUNK_SIZE {
+        return Ok(plugin.present_windowed(bytes)?.map(StyledText::plain));
+    }
+    plugin.present_styled_with_options(bytes, options)
+}
+
+// renders styled text with a css class (e.g. `fg-key`) per span
+fn view_styled(text: &StyledText) -> Html {
+    html! {
+        <pre class="fg-preview">
+            {for text.segments().into_iter().map(|(s, style)| html!(<span class=format!("fg-{}", style.name())>{s}</span>))}
+        </pre>
+    }
+}
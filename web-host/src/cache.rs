@@ -0,0 +1,72 @@
+/* Cached copies of the index and converters in the browser's Cache Storage
+
+Converters are kept by their hash and verified against it whenever they are loaded, from the
+network as well as from the cache, so a converter can't be swapped by whoever serves it. The
+index is refreshed on every start and the cached copy is only used when fetching it fails.
+*/
+
+use js_sys::{ArrayBuffer, Uint8Array};
+use sha2::{Digest, Sha256};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{Cache, Response};
+
+const CACHE_NAME: &str = "format-galaxy";
+
+/// Cache key of the converter with `hash`, independent of where it was fetched from.
+pub fn plugin_key(hash: &str) -> String {
+    format!("/fg/converters/{}.wasm", hash)
+}
+
+/// Cache key of the index at `url`.
+pub fn index_key(url: &str) -> String {
+    format!("/fg/index?url={}", String::from(js_sys::encode_uri_component(url)))
+}
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Whether `bytes` are the converter with `hash`.
+pub fn verify(hash: &str, bytes: &[u8]) -> bool {
+    sha256_hex(bytes).eq_ignore_ascii_case(hash)
+}
+
+async fn open() -> Result<Cache, JsValue> {
+    let caches = web_sys::window().ok_or("no window")?.caches()?;
+    JsFuture::from(caches.open(CACHE_NAME)).await?.dyn_into()
+}
+
+/// The bytes stored for `key`, `None` if there are none.
+pub async fn load(key: &str) -> Result<Option<Vec<u8>>, JsValue> {
+    let cache = open().await?;
+    let response = JsFuture::from(cache.match_with_str(key)).await?;
+    if response.is_undefined() {
+        return Ok(None);
+    }
+    let response: Response = response.dyn_into()?;
+    let buffer: ArrayBuffer = JsFuture::from(response.array_buffer()?).await?.dyn_into()?;
+    Ok(Some(Uint8Array::new(&buffer).to_vec()))
+}
+
+pub async fn store(key: &str, bytes: &[u8]) -> Result<(), JsValue> {
+    let cache = open().await?;
+    let response = Response::new_with_opt_u8_array(Some(&mut bytes.to_vec()))?;
+    JsFuture::from(cache.put_with_str(key, &response)).await?;
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify() {
+        let hash = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        assert_eq!(sha256_hex(b""), hash);
+        assert!(verify(hash, b""));
+        assert!(verify(&hash.to_uppercase(), b""));
+        assert!(!verify(hash, b"\0asm"));
+    }
+}
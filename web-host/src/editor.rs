@@ -0,0 +1,209 @@
+/* The split view: converter text on the left, the stored bytes as hex on the right
+
+Converters can report the range of the text a store error is about (see
+`format_galaxy_core::location`). Errors without one are located from the conventions their
+messages already follow:
+
+    ┌─ input:2:5          codespan diagnostics, carets below give the length (json-like)
+    --> <anon>:2:5        rustc style (wat)
+    at line 2 column 5    serde_json (bson)
+    Line 2: ...           the whole line (hexdump)
+
+The hex view marks the bytes that differ from the opened file. While the text doesn't store,
+it keeps showing the last bytes that did, marked as stale.
+*/
+
+use std::ops::Range;
+
+use fg_plugin::StoreError;
+use yew::{html, Html};
+
+/// Bytes per row of the hex view.
+pub const HEX_ROW_LEN: usize = 16;
+
+/// Rows shown in the hex view, the rest of larger files is left out.
+pub const MAX_HEX_ROWS: usize = 4096;
+
+// byte offset of the 1-based `line` and `column` (in chars), columns past the end of a line
+// point at its end
+fn offset(text: &str, line: usize, column: usize) -> Option<usize> {
+    let start = match line {
+        0 => return None,
+        1 => 0,
+        _ => text.match_indices('\n').nth(line - 2)?.0 + 1,
+    };
+    let line = text[start..].split('\n').next().unwrap_or("");
+    let column = line.char_indices().nth(column.saturating_sub(1)).map_or(line.len(), |(i, _c)| i);
+    Some(start + column)
+}
+
+// `len` chars from `start`, but not past the end of the line
+fn span_at(text: &str, start: usize, len: usize) -> Range<usize> {
+    let end = text[start..].char_indices()
+        .take_while(|(_i, c)| *c != '\n')
+        .take(len)
+        .last()
+        .map_or(start, |(i, c)| start + i + c.len_utf8());
+    start..end
+}
+
+fn number(s: &str) -> Option<usize> {
+    s.trim_end_matches(|c: char| !c.is_ascii_digit()).parse().ok()
+}
+
+// `┌─ name:line:column` or `--> name:line:column`, followed by carets under the span
+fn arrow_span(text: &str, message: &str) -> Option<Range<usize>> {
+    let mut lines = message.lines();
+    let location = lines.by_ref().find_map(|line| {
+        let line = line.trim_start();
+        line.strip_prefix("┌─").or_else(|| line.strip_prefix("-->"))
+    })?;
+    let mut parts = location.trim().rsplitn(3, ':');
+    let column = number(parts.next()?)?;
+    let line = number(parts.next()?)?;
+    let len = lines.find(|line| line.contains('^'))
+        .map_or(1, |line| line.chars().skip_while(|c| *c != '^').take_while(|c| *c == '^').count());
+    Some(span_at(text, offset(text, line, column)?, len))
+}
+
+// `line 2 column 5`
+fn line_column_span(text: &str, message: &str) -> Option<Range<usize>> {
+    let words: Vec<_> = message.split_whitespace().collect();
+    words.windows(4).find_map(|w| match w {
+        [l, line, c, column] if l.eq_ignore_ascii_case("line") && c.eq_ignore_ascii_case("column") =>
+            Some(span_at(text, offset(text, number(line)?, number(column)?)?, 1)),
+        _ => None,
+    })
+}
+
+// `Line 2: ...`
+fn line_span(text: &str, message: &str) -> Option<Range<usize>> {
+    let (line, _rest) = message.strip_prefix("Line ")?.split_once(':')?;
+    let start = offset(text, line.parse().ok()?, 1)?;
+    Some(span_at(text, start, usize::MAX))
+}
+
+/// The range of `text` a store error is about, the one the converter reported or else the one
+/// in its message.
+pub fn error_span(text: &str, error: &StoreError) -> Option<Range<usize>> {
+    match &error.location {
+        // ranges past the end or inside of a character are the converter's mistake
+        Some(location) if text.get(location.clone()).is_some() => Some(location.clone()),
+        _ => message_span(text, &error.message),
+    }
+}
+
+fn message_span(text: &str, message: &str) -> Option<Range<usize>> {
+    arrow_span(text, message)
+        .or_else(|| line_column_span(text, message))
+        .or_else(|| line_span(text, message))
+}
+
+/// The range of `bytes` that differs from `original`, the bytes before and after it are the
+/// same in both.
+pub fn changed_range(original: &[u8], bytes: &[u8]) -> Range<usize> {
+    let prefix = original.iter().zip(bytes).take_while(|(a, b)| a == b).count();
+    let suffix = original[prefix..].iter().rev()
+        .zip(bytes[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    prefix..bytes.len() - suffix
+}
+
+// splits `row` into the parts outside and inside of `changed`
+fn row_segments(row: Range<usize>, changed: &Range<usize>) -> Vec<(Range<usize>, bool)> {
+    let start = changed.start.clamp(row.start, row.end);
+    let end = changed.end.clamp(start, row.end);
+    vec!((row.start..start, false), (start..end, true), (end..row.end, false))
+        .into_iter()
+        .filter(|(range, _changed)| !range.is_empty())
+        .collect()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x} ", b)).collect()
+}
+
+/// `bytes` as hex, compared to `original` if there is one. Stale bytes are the last that
+/// stored while the text doesn't.
+pub fn view_hex(bytes: &[u8], original: Option<&[u8]>, stale: bool) -> Html {
+    let changed = original.map(|original| changed_range(original, bytes));
+    let rows = bytes.len().div_ceil(HEX_ROW_LEN);
+    let class = if stale { "fg-hex fg-hex-stale" } else { "fg-hex" };
+    let title = if stale { "The text doesn't store, these are the last bytes it stored to" } else { "" };
+    let view_row = |row: usize| {
+        let range = row * HEX_ROW_LEN..bytes.len().min((row + 1) * HEX_ROW_LEN);
+        let segments = match &changed {
+            Some(changed) => row_segments(range.clone(), changed).into_iter()
+                .map(|(range, changed)| {
+                    let class = if changed { "fg-hex-changed" } else { "fg-hex-unchanged" };
+                    html!(<span class=class>{hex(&bytes[range])}</span>)
+                })
+                .collect(),
+            None => vec!(html!({hex(&bytes[range.clone()])})),
+        };
+        html! {
+            <div><span class="fg-hex-offset">{format!("{:08x}  ", range.start)}</span>{segments}</div>
+        }
+    };
+    html! {
+        <pre class=class title=title>
+            {for (0..rows.min(MAX_HEX_ROWS)).map(view_row)}
+            {if rows > MAX_HEX_ROWS {
+                html!(<div>{format!("... {} more bytes", bytes.len() - MAX_HEX_ROWS * HEX_ROW_LEN)}</div>)
+            } else {
+                html!()
+            }}
+        </pre>
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_span() {
+        let text = "{\n{ tr\nä x\n";
+        let located = StoreError::at("Unexpected input at line 1 column 1".to_string(), 4..6);
+        assert_eq!(error_span(text, &located).map(|span| &text[span]), Some("tr"));
+        // the message is the fallback for locations that don't fit the text
+        let outside = StoreError::at("Unexpected input at line 1 column 1".to_string(), 4..99);
+        assert_eq!(error_span(text, &outside).map(|span| &text[span]), Some("{"));
+        let inside_char = StoreError::at("Unexpected input".to_string(), 8..9);
+        assert_eq!(error_span(text, &inside_char), None);
+    }
+
+    #[test]
+    fn test_message_span() {
+        let text = "{\n{ tr\nä x\n";
+        let codespan = "error: Unexpected input\n  ┌─ input:2:3\n  │\n2 │ { tr\n  │   ^^ Expected `true`\n";
+        assert_eq!(message_span(text, codespan).map(|span| &text[span]), Some("tr"));
+        let wat = "expected `(`\n     --> <anon>:3:3\n      |\n    3 | ä x\n      |   ^";
+        assert_eq!(message_span(text, wat).map(|span| &text[span]), Some("x"));
+        let serde = "expected value at line 3 column 1";
+        assert_eq!(message_span(text, serde).map(|span| &text[span]), Some("ä"));
+        let hexdump = "Line 2: Expected a byte (two hex digits), found 'tr'";
+        assert_eq!(message_span(text, hexdump).map(|span| &text[span]), Some("{ tr"));
+
+        // past the end of the line or text, the span is empty
+        assert_eq!(message_span(text, "at line 1 column 9"), Some(1..1));
+        assert_eq!(message_span(text, "at line 4 column 1"), Some(text.len()..text.len()));
+        assert_eq!(message_span(text, "at line 5 column 1"), None);
+        assert_eq!(message_span(text, "Could not convert text to byte sequence."), None);
+    }
+
+    #[test]
+    fn test_changed_range() {
+        assert_eq!(changed_range(b"abcdef", b"abcdef"), 6..6);
+        assert_eq!(changed_range(b"abcdef", b"abXYef"), 2..4);
+        assert_eq!(changed_range(b"abcdef", b"abef"), 2..2);
+        assert_eq!(changed_range(b"abab", b"ababab"), 4..6);
+        assert_eq!(changed_range(b"", b"ab"), 0..2);
+
+        assert_eq!(row_segments(0..16, &(2..4)), vec!((0..2, false), (2..4, true), (4..16, false)));
+        assert_eq!(row_segments(16..32, &(2..4)), vec!((16..32, false)));
+        assert_eq!(row_segments(16..32, &(2..20)), vec!((16..20, true), (20..32, false)));
+    }
+}
@@ -0,0 +1,103 @@
+/* Functions imported by converters, see `format_galaxy_core::log`
+
+Logged messages go to the browser console. Which levels are shown is set with the `log` query
+parameter of the page, e.g. `?log=debug` (`warn` by default). Panic messages are kept until the
+trap that follows them and replace its message.
+*/
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use format_galaxy_core::log::{format_message, Level};
+use js_sys::{Object, Reflect, Uint8Array};
+use js_sys::WebAssembly::Memory;
+use wasm_bindgen::prelude::*;
+
+use crate::settings::query_param;
+
+/// The most verbose level of messages logged by converters that is shown.
+pub fn max_log_level() -> Option<Level> {
+    match query_param("log") {
+        Some(value) => Level::parse_max(&value).unwrap_or_else(|e| {
+            web_sys::console::warn_1(&e.into());
+            Some(Level::Warn)
+        }),
+        None => Some(Level::Warn),
+    }
+}
+
+fn read_guest_str(memory: &Option<Memory>, ptr: u32, len: u32) -> String {
+    match memory {
+        Some(memory) => {
+            let bytes = Uint8Array::new(&memory.buffer()).subarray(ptr, ptr.saturating_add(len)).to_vec();
+            String::from_utf8_lossy(&bytes).into_owned()
+        }
+        None => "<message before instantiation>".to_string(),
+    }
+}
+
+pub struct GuestImports {
+    imports: Object,
+    memory: Rc<RefCell<Option<Memory>>>,
+    panic: Rc<RefCell<Option<String>>>,
+    // the imports call these, so they have to live as long as the instance
+    _log: Closure<dyn FnMut(u32, u32, u32)>,
+    _panic: Closure<dyn FnMut(u32, u32)>,
+}
+
+impl GuestImports {
+    pub fn new() -> Result<Self, JsValue> {
+        let memory: Rc<RefCell<Option<Memory>>> = Rc::new(RefCell::new(None));
+        let panic = Rc::new(RefCell::new(None));
+        let max_level = max_log_level();
+
+        let log_memory = memory.clone();
+        let log = Closure::wrap(Box::new(move |level: u32, ptr: u32, len: u32| {
+            let level = Level::from_u32(level).unwrap_or(Level::Error);
+            if Some(level) > max_level {
+                return;
+            }
+            let msg: JsValue = format_message(level, &read_guest_str(&log_memory.borrow(), ptr, len)).into();
+            match level {
+                Level::Error => web_sys::console::error_1(&msg),
+                Level::Warn => web_sys::console::warn_1(&msg),
+                Level::Info => web_sys::console::info_1(&msg),
+                Level::Debug => web_sys::console::debug_1(&msg),
+            }
+        }) as Box<dyn FnMut(u32, u32, u32)>);
+
+        let panic_memory = memory.clone();
+        let panic_msg = panic.clone();
+        let on_panic = Closure::wrap(Box::new(move |ptr: u32, len: u32| {
+            *panic_msg.borrow_mut() = Some(read_guest_str(&panic_memory.borrow(), ptr, len));
+        }) as Box<dyn FnMut(u32, u32)>);
+
+        let fg = Object::new();
+        Reflect::set(&fg, &"log".into(), log.as_ref())?;
+        Reflect::set(&fg, &"panic".into(), on_panic.as_ref())?;
+        let imports = Object::new();
+        Reflect::set(&imports, &"fg".into(), &fg)?;
+
+        Ok(GuestImports {
+            imports,
+            memory,
+            panic,
+            _log: log,
+            _panic: on_panic,
+        })
+    }
+
+    /// The import object passed to `WebAssembly.instantiate`.
+    pub fn imports(&self) -> &Object {
+        &self.imports
+    }
+
+    pub fn set_memory(&self, memory: Memory) {
+        *self.memory.borrow_mut() = Some(memory);
+    }
+
+    /// The message of the last panic, if it wasn't reported yet.
+    pub fn take_panic(&self) -> Option<String> {
+        self.panic.borrow_mut().take()
+    }
+}
@@ -1,8 +1,11 @@
 #![recursion_limit="2048"]
-use yew::prelude::*;
 
 mod app;
+mod cache;
+mod editor;
+mod guest;
 pub mod plugin;
+mod settings;
 
 
 fn main() {
@@ -1,14 +1,20 @@
 pub use fg_plugin::GalaxyFormatPluginV1;
-use fg_plugin::GalaxyFormatPluginV1_;
+use fg_plugin::{is_component, memory_range, next_window, GalaxyFormatPluginV1_, MemoryAccess, OPTIONAL_CALLS};
+use std::collections::HashMap;
 use anyhow::Result;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
-use js_sys::{Function, Object, Reflect, WebAssembly};
+use js_sys::{Function, Object, Reflect, Uint8Array, WebAssembly};
 use js_sys::WebAssembly::Memory;
 use wasm_bindgen_futures::JsFuture;
 
 use anyhow::anyhow;
 
+use crate::guest::GuestImports;
+
+/// Size of the chunks passed to converters presenting large inputs window by window.
+pub const WINDOW_CHUNK_SIZE: u64 = 1024 * 1024;
+
 
 pub struct WebGalaxyFormatPlugin {
     memory: Memory,
@@ -16,20 +22,32 @@ pub struct WebGalaxyFormatPlugin {
     free_fn: Function,
     present_fn: Function,
     store_fn: Function,
+    // the exports of `OPTIONAL_CALLS` the module has
+    optional_fns: HashMap<&'static str, Function>,
     get_result_ptr_fn: Function,
     get_result_len_fn: Function,
     get_result_success_fn: Function,
+    get_result_location_fn: Option<Function>,
+    guest: GuestImports,
 }
 
 fn get_fn(exports: &Object, name: &str) -> Result<Function, JsValue> {
     Ok(Reflect::get(exports, &name.into())?
         .dyn_into::<Function>()
-        .expect(&format!("{} export wasn't a function", name)))
+        .unwrap_or_else(|_| panic!("{} export wasn't a function", name)))
+}
+
+fn get_optional_fn(exports: &Object, name: &str) -> Result<Option<Function>, JsValue> {
+    Ok(Reflect::get(exports, &name.into())?.dyn_into::<Function>().ok())
 }
 
 impl WebGalaxyFormatPlugin {
     pub async fn from_slice(bytes: &[u8]) -> Result<Self, JsValue> {
-        let wasm = JsFuture::from(WebAssembly::instantiate_buffer(bytes, &Object::new())).await?;
+        if is_component(bytes) {
+            return Err("Converters built as components can't run in the browser yet".into());
+        }
+        let guest = GuestImports::new()?;
+        let wasm = JsFuture::from(WebAssembly::instantiate_buffer(bytes, guest.imports())).await?;
         let wasm_instance: WebAssembly::Instance = Reflect::get(&wasm, &"instance".into())?.dyn_into()?;
 
         let c = wasm_instance.exports();
@@ -37,23 +55,38 @@ impl WebGalaxyFormatPlugin {
         let memory = Reflect::get(c.as_ref(), &"memory".into())?
             .dyn_into::<WebAssembly::Memory>()
             .expect("memory export wasn't a `WebAssembly.Memory`");
+        guest.set_memory(memory.clone());
+        // installs the converter's panic hook
+        if let Some(init_fn) = get_optional_fn(c.as_ref(), "init")? {
+            init_fn.call0(&JsValue::undefined())?;
+        }
         let present_fn = get_fn(c.as_ref(), "present")?;
         let store_fn = get_fn(c.as_ref(), "store")?;
+        let mut optional_fns = HashMap::new();
+        for name in OPTIONAL_CALLS {
+            if let Some(f) = get_optional_fn(c.as_ref(), name)? {
+                optional_fns.insert(*name, f);
+            }
+        }
         let alloc_fn = get_fn(c.as_ref(), "alloc")?;
         let free_fn = get_fn(c.as_ref(), "free")?;
         let get_result_ptr_fn = get_fn(c.as_ref(), "result_get_ptr")?;
         let get_result_len_fn = get_fn(c.as_ref(), "result_get_len")?;
         let get_result_success_fn = get_fn(c.as_ref(), "result_get_success")?;
+        let get_result_location_fn = get_optional_fn(c.as_ref(), "result_get_location")?;
 
         Ok(WebGalaxyFormatPlugin {
             memory,
             present_fn,
             store_fn,
+            optional_fns,
             alloc_fn,
             free_fn,
             get_result_ptr_fn,
             get_result_len_fn,
-            get_result_success_fn
+            get_result_success_fn,
+            get_result_location_fn,
+            guest,
         })
     }
 }
@@ -79,51 +112,180 @@ fn call2(f: &Function, a: u32, b: u32) -> Result<JsValue> {
     }
 }
 
+impl WebGalaxyFormatPlugin {
+    // turns a trap following a panic of the converter into an error with the panic's message
+    fn guest_result(&self, res: Result<JsValue>) -> Result<JsValue> {
+        match (res, self.guest.take_panic()) {
+            (Err(_), Some(msg)) => Err(anyhow!("Converter panicked: {}", msg)),
+            (res, _) => res,
+        }
+    }
+
+    fn call1(&self, f: &Function, a: u32) -> Result<JsValue> {
+        self.guest_result(call1(f, a))
+    }
+
+    fn call2(&self, f: &Function, a: u32, b: u32) -> Result<JsValue> {
+        self.guest_result(call2(f, a, b))
+    }
+
+    /// Like `present`, but window by window if the converter supports it, so that only one
+    /// chunk of `bytes` at a time is copied into the converter's memory.
+    pub fn present_windowed(&mut self, bytes: &[u8]) -> Result<Result<String, String>> {
+        if !self.has_present_window() {
+            return GalaxyFormatPluginV1::present(self, bytes);
+        }
+        let mut text = String::new();
+        let mut pos = 0;
+        let end = bytes.len() as u64;
+        let read = |pos: u64, len: u64| Ok(&bytes[pos as usize..(pos + len) as usize]);
+        while pos < end {
+            let window = match next_window(self, read, pos, end, WINDOW_CHUNK_SIZE, u32::MAX)? {
+                Ok(window) => window,
+                Err(e) => return Ok(Err(e)),
+            };
+            text.push_str(&window.text);
+            pos = window.next;
+        }
+        Ok(Ok(text))
+    }
+}
+
 impl GalaxyFormatPluginV1_ for WebGalaxyFormatPlugin {
     fn alloc(&mut self, size: u32) -> Result<u32> {
-        to_u32(call1(&self.alloc_fn, size)?)
+        to_u32(self.call1(&self.alloc_fn, size)?)
     }
 
     fn free(&mut self, ptr: u32) -> Result<()> {
-        call1(&self.free_fn, ptr)?;
+        self.call1(&self.free_fn, ptr)?;
         Ok(())
     }
 
     fn present(&mut self, ptr: u32, size: u32) -> Result<u32> {
-        to_u32(call2(&self.present_fn, ptr, size)?)
+        to_u32(self.call2(&self.present_fn, ptr, size)?)
     }
     
     fn store(&mut self, ptr: u32, size: u32) -> Result<u32> {
-        to_u32(call2(&self.store_fn, ptr, size)?)
+        to_u32(self.call2(&self.store_fn, ptr, size)?)
+    }
+
+    fn has_optional(&self, name: &str) -> bool {
+        self.optional_fns.contains_key(name)
+    }
+
+    fn call_optional(&mut self, name: &str, ptr: u32, size: u32) -> Result<u32> {
+        match self.optional_fns.get(name) {
+            Some(f) => to_u32(self.call2(f, ptr, size)?),
+            None => Err(anyhow!("The plugin doesn't export `{}`", name)),
+        }
     }
     
     fn result_get_ptr(&mut self, res_ptr: u32) -> Result<u32> {
-        to_u32(call1(&self.get_result_ptr_fn, res_ptr)?)
+        to_u32(self.call1(&self.get_result_ptr_fn, res_ptr)?)
     }
     
     fn result_get_len(&mut self, res_ptr: u32) -> Result<u32> {
-        to_u32(call1(&self.get_result_len_fn, res_ptr)?)
+        to_u32(self.call1(&self.get_result_len_fn, res_ptr)?)
     }
     
     fn result_get_success(&mut self, res_ptr: u32) -> Result<bool> {
-        Ok(to_u32(call1(&self.get_result_success_fn, res_ptr)?)? > 0)
+        Ok(to_u32(self.call1(&self.get_result_success_fn, res_ptr)?)? > 0)
+    }
+
+    fn has_location(&self) -> bool {
+        self.get_result_location_fn.is_some()
+    }
+
+    fn result_get_location(&mut self, res_ptr: u32) -> Result<u32> {
+        match &self.get_result_location_fn {
+            Some(get_result_location_fn) => to_u32(self.call1(get_result_location_fn, res_ptr)?),
+            None => Err(anyhow!("The plugin doesn't export `result_get_location`")),
+        }
     }
     
     
     fn memory_write(&mut self, ptr: u32, bytes: &[u8]) -> Result<()> {
-        let array = js_sys::Uint8Array::new(&self.memory.buffer());
-        for i in 0..bytes.len() as u32 {
-            array.set_index(ptr + i, bytes[i as usize]);
-        }
+        let array = Uint8Array::new(&self.memory.buffer());
+        let range = memory_range(MemoryAccess::Write, ptr, bytes.len(), array.length() as usize)?;
+        // a single copy instead of crossing into js for every byte
+        array.subarray(range.start as u32, range.end as u32).copy_from(bytes);
         Ok(())
     }
     
     fn memory_read(&mut self, ptr: u32, len: u32) -> Result<Vec<u8>> {
-        let mut ret_bytes: Vec<u8> = Vec::with_capacity(len as usize);
-        let array = js_sys::Uint8Array::new(&self.memory.buffer());
-        for i in 0..len {
-            ret_bytes.push(array.get_index(ptr + i));
+        let array = Uint8Array::new(&self.memory.buffer());
+        let range = memory_range(MemoryAccess::Read, ptr, len as usize, array.length() as usize)?;
+        Ok(array.subarray(range.start as u32, range.end as u32).to_vec())
+    }
+}
+
+// run with `wasm-pack test --node` (or `cargo test --target wasm32-unknown-unknown` with
+// `wasm-bindgen-test-runner` as the runner), no browser needed
+#[cfg(all(test, target_arch = "wasm32"))]
+mod tests {
+    use super::*;
+    use fg_plugin::MemoryAccessError;
+    use wasm_bindgen_test::*;
+
+    // presents and stores bytes as they are, `alloc` grows the memory as needed and `free`
+    // releases everything
+    const ECHO_WAT: &str = r#"
+        (module
+            (memory (export "memory") 1)
+            (global $heap (mut i32) (i32.const 1024))
+            (global $ptr (mut i32) (i32.const 0))
+            (global $len (mut i32) (i32.const 0))
+            (func (export "alloc") (param $n i32) (result i32)
+                (local $ptr i32)
+                (local.set $ptr (global.get $heap))
+                (global.set $heap (i32.add (global.get $heap) (local.get $n)))
+                (if (i32.gt_u (global.get $heap) (i32.shl (memory.size) (i32.const 16)))
+                    (then (drop (memory.grow
+                        (i32.add (i32.shr_u (i32.sub (global.get $heap) (i32.shl (memory.size) (i32.const 16))) (i32.const 16)) (i32.const 1))))))
+                (local.get $ptr))
+            (func (export "free") (param i32)
+                (global.set $heap (i32.const 1024)))
+            (func $echo (param i32 i32) (result i32)
+                (global.set $ptr (local.get 0))
+                (global.set $len (local.get 1))
+                (i32.const 0))
+            (export "present" (func $echo))
+            (export "store" (func $echo))
+            (func (export "result_get_ptr") (param i32) (result i32) (global.get $ptr))
+            (func (export "result_get_len") (param i32) (result i32) (global.get $len))
+            (func (export "result_get_success") (param i32) (result i32) (i32.const 1)))
+    "#;
+
+    async fn echo_plugin() -> WebGalaxyFormatPlugin {
+        WebGalaxyFormatPlugin::from_slice(&wat::parse_str(ECHO_WAT).unwrap()).await.unwrap()
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_memory_transfer() {
+        let mut plugin = echo_plugin().await;
+        // larger than the initial memory
+        let text: String = (0..3 * 65536).map(|i| (b'a' + (i % 26) as u8) as char).collect();
+        assert_eq!(GalaxyFormatPluginV1::present(&mut plugin, text.as_bytes()).unwrap(), Ok(text.clone()));
+        assert_eq!(GalaxyFormatPluginV1::store(&mut plugin, &text).unwrap(), Ok(text.into_bytes()));
+
+        let err = plugin.memory_read(u32::MAX - 4, 8).unwrap_err().downcast::<MemoryAccessError>().unwrap();
+        assert_eq!(err.access, MemoryAccess::Read);
+    }
+
+    // prints the throughput of a round trip through the converter's memory
+    #[wasm_bindgen_test]
+    async fn bench_memory_transfer() {
+        let mut plugin = echo_plugin().await;
+        for size in [1 << 10, 1 << 20, 16 << 20] {
+            let text = "a".repeat(size);
+            let runs = (64 << 20) / size;
+            let start = js_sys::Date::now();
+            for _ in 0..runs {
+                GalaxyFormatPluginV1::store(&mut plugin, &text).unwrap().unwrap();
+            }
+            let secs = (js_sys::Date::now() - start) / 1000.0;
+            let mb = (runs * size) as f64 / (1 << 20) as f64;
+            console_log!("{:>9} bytes: {:>8.1} MiB/s ({} runs)", size, mb / secs, runs);
         }
-        Ok(ret_bytes)
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,116 @@
+/* Where the index and converters are fetched from
+
+Set with the `index` and `plugins` query parameters of the page or in the settings panel (which
+keeps them in local storage), e.g. for an index served from the `fg-index/` directory of the
+repository:
+
+    ?index=http://localhost:8000/fg-index/test_index.json
+
+If only the index is set, converters are fetched from the `converters/` directory next to it.
+*/
+
+const DEFAULT_INDEX_URL: &str = "https://raw.githubusercontent.com/fkohlgrueber/format-galaxy/main/fg-index/test_index.json";
+const DEFAULT_PLUGIN_URL: &str = "https://raw.githubusercontent.com/fkohlgrueber/format-galaxy/main/fg-index/converters/";
+
+const INDEX_KEY: &str = "fg-index-url";
+const PLUGIN_KEY: &str = "fg-plugin-url";
+
+/// A (decoded) query parameter of the page.
+pub fn query_param(name: &str) -> Option<String> {
+    let search = web_sys::window()?.location().search().ok()?;
+    let value = search.trim_start_matches('?').split('&')
+        .find_map(|param| param.strip_prefix(name)?.strip_prefix('='))?;
+    js_sys::decode_uri_component(value).ok().map(String::from)
+}
+
+fn local_storage() -> Option<web_sys::Storage> {
+    web_sys::window()?.local_storage().ok()?
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sources {
+    pub index_url: String,
+    /// Converters are fetched from `<plugin_url><hash>.wasm`.
+    pub plugin_url: String,
+    // whether `plugin_url` was set instead of derived from the index, only then it's kept
+    plugin_url_set: bool,
+}
+
+impl Sources {
+    /// Query parameters take precedence over the settings panel, then the defaults apply.
+    pub fn load() -> Self {
+        let stored = |key| local_storage().and_then(|storage| storage.get_item(key).ok()?);
+        match query_param("index") {
+            // converters stored for another index don't apply
+            Some(index_url) => Sources::new(Some(&index_url), query_param("plugins").as_deref()),
+            None => {
+                let plugin_url = query_param("plugins").or_else(|| stored(PLUGIN_KEY));
+                Sources::new(stored(INDEX_KEY).as_deref(), plugin_url.as_deref())
+            }
+        }
+    }
+
+    /// Empty or missing URLs are replaced by the defaults.
+    pub fn new(index_url: Option<&str>, plugin_url: Option<&str>) -> Self {
+        let index_url = index_url.map(str::trim).filter(|url| !url.is_empty());
+        let plugin_url = plugin_url.map(str::trim).filter(|url| !url.is_empty());
+        let plugin_url_set = plugin_url.is_some();
+        let plugin_url = match (plugin_url, index_url) {
+            (Some(url), _) if url.ends_with('/') => url.to_string(),
+            (Some(url), _) => format!("{}/", url),
+            (None, Some(index_url)) => match index_url.rsplit_once('/') {
+                Some((dir, _file)) => format!("{}/converters/", dir),
+                None => "converters/".to_string(),
+            },
+            (None, None) => DEFAULT_PLUGIN_URL.to_string(),
+        };
+        Sources {
+            index_url: index_url.unwrap_or(DEFAULT_INDEX_URL).to_string(),
+            plugin_url,
+            plugin_url_set,
+        }
+    }
+
+    /// Keeps the sources for the next visits.
+    pub fn save(&self) {
+        if let Some(storage) = local_storage() {
+            let _ = storage.set_item(INDEX_KEY, &self.index_url);
+            let _ = match self.explicit_plugin_url() {
+                Some(url) => storage.set_item(PLUGIN_KEY, url),
+                // follows the index when that changes
+                None => storage.remove_item(PLUGIN_KEY),
+            };
+        }
+    }
+
+    /// The converters URL if it was set, `None` if it's derived from the index or the default.
+    pub fn explicit_plugin_url(&self) -> Option<&str> {
+        self.plugin_url_set.then_some(self.plugin_url.as_str())
+    }
+
+    pub fn plugin_url(&self, hash: &str) -> String {
+        format!("{}{}.wasm", self.plugin_url, hash)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sources() {
+        assert_eq!(Sources::new(None, Some(" ")), Sources {
+            index_url: DEFAULT_INDEX_URL.to_string(),
+            plugin_url: DEFAULT_PLUGIN_URL.to_string(),
+            plugin_url_set: false,
+        });
+        let local = Sources::new(Some("http://localhost:8000/fg-index/test_index.json"), None);
+        assert_eq!(local.plugin_url("ab12"), "http://localhost:8000/fg-index/converters/ab12.wasm");
+        assert_eq!(local.explicit_plugin_url(), None);
+        let sources = Sources::new(Some("index.json"), Some("https://example.com/plugins"));
+        assert_eq!(sources.plugin_url, "https://example.com/plugins/");
+        assert_eq!(sources.explicit_plugin_url(), Some("https://example.com/plugins/"));
+        assert_eq!(Sources::new(Some("index.json"), None).plugin_url, "converters/");
+    }
+}